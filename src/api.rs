@@ -0,0 +1,353 @@
+use crate::post::PostPrivacy;
+use anyhow::Context;
+use reqwest::header::HeaderMap;
+use std::time::Instant;
+
+/// Headers whose values are never printed, when tracing or dumping the user config.
+const REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+/// Whether the value of the header with the given name is never printed.
+pub fn is_redacted_header(name: &str) -> bool {
+    REDACTED_HEADERS
+        .iter()
+        .any(|redacted| redacted.eq_ignore_ascii_case(name))
+}
+
+/// Format headers for tracing, one per line, with sensitive values redacted.
+pub fn format_trace_headers(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if value.is_sensitive() || is_redacted_header(name.as_str()) {
+                "<redacted>"
+            } else {
+                value.to_str().unwrap_or("<binary>")
+            };
+            format!("{name}: {value}")
+        })
+        .collect()
+}
+
+/// Trace an http request.
+pub fn trace_http_request(request: &reqwest::Request) {
+    eprintln!("  http: {} {}", request.method(), request.url());
+    for header in format_trace_headers(request.headers()) {
+        eprintln!("    {header}");
+    }
+}
+
+/// Trace an http response.
+pub fn trace_http_response(response: &reqwest::Response) {
+    eprintln!("  http: {} {}", response.status(), response.url());
+    for header in format_trace_headers(response.headers()) {
+        eprintln!("    {header}");
+    }
+}
+
+/// A traced call to the imgchest API.
+///
+/// The imgchest client does not expose its requests or responses,
+/// so only the operation, its outcome, and the time taken are traced.
+/// Its token is never printed.
+struct ApiTrace {
+    operation: String,
+    start: Instant,
+}
+
+impl ApiTrace {
+    /// Start tracing an operation, if tracing is enabled.
+    fn start(enabled: bool, operation: String) -> Option<Self> {
+        if !enabled {
+            return None;
+        }
+
+        eprintln!("  api: {operation}");
+        Some(Self {
+            operation,
+            start: Instant::now(),
+        })
+    }
+
+    /// Trace the result of the call.
+    fn finish<T, E>(trace: Option<Self>, result: &Result<T, E>)
+    where
+        E: std::fmt::Display,
+    {
+        let Some(trace) = trace else {
+            return;
+        };
+
+        let elapsed = trace.start.elapsed().as_millis();
+        let operation = trace.operation;
+        match result {
+            Ok(_) => eprintln!("  api: {operation} succeeded in {elapsed}ms"),
+            Err(error) => eprintln!("  api: {operation} failed in {elapsed}ms: {error}"),
+        }
+    }
+}
+
+/// A post, as returned by the API.
+#[derive(Debug, Clone)]
+pub struct RemotePost {
+    /// The post id.
+    pub id: String,
+
+    /// The post title.
+    pub title: Option<String>,
+
+    /// The post privacy.
+    pub privacy: PostPrivacy,
+
+    /// Whether the post is nsfw.
+    pub nsfw: bool,
+
+    /// The post files, in order.
+    pub images: Vec<RemoteFile>,
+}
+
+/// A post file, as returned by the API.
+#[derive(Debug, Clone)]
+pub struct RemoteFile {
+    /// The file id.
+    pub id: String,
+
+    /// The file description.
+    pub description: Option<String>,
+
+    /// The url of the file data.
+    pub link: String,
+}
+
+/// A post to create.
+#[derive(Debug)]
+pub struct NewPost {
+    /// The post title.
+    pub title: String,
+
+    /// The post privacy.
+    pub privacy: PostPrivacy,
+
+    /// Whether the post is nsfw.
+    pub nsfw: bool,
+
+    /// The files to upload, in order.
+    pub files: Vec<imgchest::UploadPostFile>,
+}
+
+/// Changes to the metadata of a post.
+#[derive(Debug, Default)]
+pub struct PostUpdate {
+    /// The new title.
+    pub title: Option<String>,
+
+    /// The new privacy.
+    pub privacy: Option<PostPrivacy>,
+
+    /// The new nsfw flag.
+    pub nsfw: Option<bool>,
+}
+
+/// The imgchest API operations used for syncing.
+///
+/// This is implemented by the imgchest client,
+/// and can be implemented by a mock to test syncing without the network.
+pub trait Api {
+    /// Get a post.
+    async fn get_post(&self, id: &str) -> anyhow::Result<RemotePost>;
+
+    /// Create a post.
+    async fn create_post(&self, post: NewPost) -> anyhow::Result<RemotePost>;
+
+    /// Update the metadata of a post.
+    async fn update_post(&self, id: &str, update: PostUpdate) -> anyhow::Result<()>;
+
+    /// Delete a post.
+    async fn delete_post(&self, id: &str) -> anyhow::Result<()>;
+
+    /// Append files to a post.
+    ///
+    /// Returns the updated post.
+    async fn add_post_images(
+        &self,
+        id: &str,
+        files: Vec<imgchest::UploadPostFile>,
+    ) -> anyhow::Result<RemotePost>;
+
+    /// Delete a file.
+    async fn delete_file(&self, id: &str) -> anyhow::Result<()>;
+
+    /// Update the descriptions of files.
+    async fn update_files_bulk(&self, updates: Vec<imgchest::FileUpdate>) -> anyhow::Result<()>;
+
+    /// Get the http client used to download file data.
+    fn http_client(&self) -> &reqwest::Client;
+
+    /// Whether api calls and file downloads are traced, with --trace-http.
+    fn trace_http(&self) -> bool {
+        false
+    }
+
+    /// Make a client for the same API that uses a different token.
+    fn with_token(&self, token: &str) -> Self
+    where
+        Self: Sized;
+}
+
+/// An imgchest client that can trace its api calls.
+pub struct Client {
+    /// The imgchest client.
+    client: imgchest::Client,
+
+    /// Whether api calls and file downloads are traced.
+    trace_http: bool,
+}
+
+impl Client {
+    /// Make a new client.
+    ///
+    /// The given headers, like a custom user agent, are sent with every api call and file download.
+    pub fn new(trace_http: bool, headers: &HeaderMap) -> anyhow::Result<Self> {
+        let mut client = imgchest::Client::new();
+        if !headers.is_empty() {
+            // The token is sent with each request, not by the http client, so it is kept.
+            client.client = reqwest::Client::builder()
+                .default_headers(headers.clone())
+                .build()
+                .context("failed to build http client")?;
+        }
+
+        Ok(Self { client, trace_http })
+    }
+
+    /// Set the API token.
+    pub fn set_token(&self, token: &str) {
+        self.client.set_token(token);
+    }
+}
+
+impl Api for Client {
+    async fn get_post(&self, id: &str) -> anyhow::Result<RemotePost> {
+        let trace = ApiTrace::start(self.trace_http, format!("get_post {id}"));
+        let result = self.client.get_post(id).await;
+        ApiTrace::finish(trace, &result);
+        Ok(convert_post(result?))
+    }
+
+    async fn create_post(&self, post: NewPost) -> anyhow::Result<RemotePost> {
+        let mut builder = imgchest::CreatePostBuilder::new();
+        builder
+            .title(post.title)
+            .privacy(convert_privacy_to_imgchest(post.privacy))
+            .nsfw(post.nsfw);
+        for file in post.files {
+            builder.image(file);
+        }
+
+        let trace = ApiTrace::start(self.trace_http, String::from("create_post"));
+        let result = self.client.create_post(builder).await;
+        ApiTrace::finish(trace, &result);
+        Ok(convert_post(result?))
+    }
+
+    async fn update_post(&self, id: &str, update: PostUpdate) -> anyhow::Result<()> {
+        let mut builder = imgchest::UpdatePostBuilder::new();
+        if let Some(title) = update.title {
+            builder.title(title);
+        }
+        if let Some(privacy) = update.privacy {
+            builder.privacy(convert_privacy_to_imgchest(privacy));
+        }
+        if let Some(nsfw) = update.nsfw {
+            builder.nsfw(nsfw);
+        }
+
+        let trace = ApiTrace::start(self.trace_http, format!("update_post {id}"));
+        let result = self.client.update_post(id, builder).await;
+        ApiTrace::finish(trace, &result);
+        result?;
+        Ok(())
+    }
+
+    async fn delete_post(&self, id: &str) -> anyhow::Result<()> {
+        let trace = ApiTrace::start(self.trace_http, format!("delete_post {id}"));
+        let result = self.client.delete_post(id).await;
+        ApiTrace::finish(trace, &result);
+        result?;
+        Ok(())
+    }
+
+    async fn add_post_images(
+        &self,
+        id: &str,
+        files: Vec<imgchest::UploadPostFile>,
+    ) -> anyhow::Result<RemotePost> {
+        let trace = ApiTrace::start(self.trace_http, format!("add_post_images {id}"));
+        let result = self.client.add_post_images(id, files).await;
+        ApiTrace::finish(trace, &result);
+        Ok(convert_post(result?))
+    }
+
+    async fn delete_file(&self, id: &str) -> anyhow::Result<()> {
+        let trace = ApiTrace::start(self.trace_http, format!("delete_file {id}"));
+        let result = self.client.delete_file(id).await;
+        ApiTrace::finish(trace, &result);
+        result?;
+        Ok(())
+    }
+
+    async fn update_files_bulk(&self, updates: Vec<imgchest::FileUpdate>) -> anyhow::Result<()> {
+        let trace = ApiTrace::start(self.trace_http, String::from("update_files_bulk"));
+        let result = self.client.update_files_bulk(updates).await;
+        ApiTrace::finish(trace, &result);
+        result?;
+        Ok(())
+    }
+
+    fn http_client(&self) -> &reqwest::Client {
+        &self.client.client
+    }
+
+    fn trace_http(&self) -> bool {
+        self.trace_http
+    }
+
+    fn with_token(&self, token: &str) -> Self {
+        let client = Self::new(self.trace_http);
+        client.set_token(token);
+        client
+    }
+}
+
+fn convert_post(post: imgchest::Post) -> RemotePost {
+    RemotePost {
+        id: post.id.into(),
+        title: post.title.map(String::from),
+        privacy: match post.privacy {
+            imgchest::PostPrivacy::Public => PostPrivacy::Public,
+            imgchest::PostPrivacy::Hidden => PostPrivacy::Hidden,
+            imgchest::PostPrivacy::Secret => PostPrivacy::Secret,
+        },
+        nsfw: post.nsfw,
+        images: Vec::from(post.images)
+            .into_iter()
+            .map(|image| RemoteFile {
+                id: image.id.into(),
+                description: image.description.map(String::from),
+                link: image.link.into(),
+            })
+            .collect(),
+    }
+}
+
+fn convert_privacy_to_imgchest(privacy: PostPrivacy) -> imgchest::PostPrivacy {
+    match privacy {
+        PostPrivacy::Public => imgchest::PostPrivacy::Public,
+        PostPrivacy::Hidden => imgchest::PostPrivacy::Hidden,
+        PostPrivacy::Secret => imgchest::PostPrivacy::Secret,
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// A token bucket over bytes, used to pace uploads.
+///
+/// This is shared between all posts, so that concurrent uploads share one limit.
+/// The imgchest client does not expose the upload stream,
+/// so uploads are paced by waiting before each request based on its total file size.
+#[derive(Debug)]
+pub struct BandwidthLimiter {
+    /// The max number of bytes per second.
+    bytes_per_sec: u64,
+
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    /// The number of bytes that may be sent without waiting.
+    ///
+    /// This is negative if earlier requests borrowed bytes that have not been refilled yet.
+    available: f64,
+
+    /// The last time the bucket was refilled.
+    last_refill: Instant,
+}
+
+impl BandwidthLimiter {
+    /// Make a new limiter with the given rate.
+    ///
+    /// The bucket starts full, allowing a burst of one second of bytes.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            state: Mutex::new(BucketState {
+                available: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until the given number of bytes may be sent.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = self.reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Take bytes from the bucket, returning how long to wait before sending them.
+    ///
+    /// Requests larger than the bucket are allowed, but borrow from future refills,
+    /// so later requests wait longer.
+    pub fn reserve(&self, bytes: u64, now: Instant) -> Duration {
+        let rate = self.bytes_per_sec as f64;
+        let mut state = self.state.lock().unwrap();
+
+        let elapsed = now.saturating_duration_since(state.last_refill);
+        state.available = (state.available + elapsed.as_secs_f64() * rate).min(rate);
+        state.last_refill = std::cmp::max(state.last_refill, now);
+        state.available -= bytes as f64;
+
+        if state.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.available / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bandwidth_limiter_paces_uploads() {
+        let limiter = BandwidthLimiter::new(1000);
+        let start = Instant::now();
+
+        // The bucket starts full.
+        assert!(limiter.reserve(600, start) == Duration::ZERO);
+        assert!(limiter.reserve(400, start) == Duration::ZERO);
+
+        // Large requests borrow from future refills.
+        assert!(limiter.reserve(2000, start) == Duration::from_secs(2));
+        assert!(
+            limiter.reserve(500, start + Duration::from_secs(1)) == Duration::from_millis(1500)
+        );
+
+        // The bucket never holds more than one second of bytes.
+        let later = start + Duration::from_secs(60);
+        assert!(limiter.reserve(1000, later) == Duration::ZERO);
+        assert!(limiter.reserve(500, later) == Duration::from_millis(500));
+    }
+}
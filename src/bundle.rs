@@ -0,0 +1,289 @@
+use crate::post::PostPrivacy;
+use anyhow::ensure;
+use anyhow::Context;
+use std::io::Read;
+use std::io::Write;
+
+/// The version of the bundle format.
+///
+/// This is increased whenever the manifest changes in a way older versions cannot read.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// The name of the manifest in a bundle.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// The directory of the files in a bundle.
+pub const FILES_DIR: &str = "files";
+
+/// The size of a tar block.
+const BLOCK_SIZE: usize = 512;
+
+/// The metadata of an exported post.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    /// The version of the bundle format.
+    pub version: u32,
+
+    /// The post title.
+    pub title: String,
+
+    /// The post privacy.
+    pub privacy: PostPrivacy,
+
+    /// Whether the post is nsfw.
+    pub nsfw: bool,
+
+    /// The id of the remote post, if it was synced.
+    pub id: Option<String>,
+
+    /// The post files, in order.
+    pub files: Vec<ManifestFile>,
+}
+
+/// A file of an exported post.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ManifestFile {
+    /// The file name, inside of the files directory of the bundle.
+    pub name: String,
+
+    /// The file description.
+    pub description: String,
+
+    /// The sha256 file hash, as a hex string.
+    pub sha256: String,
+
+    /// The id of the remote file, if it is known.
+    pub id: Option<String>,
+}
+
+/// A writer for tar archives, supporting only regular files.
+pub struct TarWriter<W> {
+    writer: W,
+}
+
+impl<W> TarWriter<W>
+where
+    W: Write,
+{
+    /// Make a new tar writer.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Add a file to the archive.
+    ///
+    /// Paths over 100 bytes are split into the ustar prefix and name fields,
+    /// so the file name is limited to 100 bytes, and its directory to 155 bytes.
+    pub fn append(&mut self, path: &str, data: &[u8]) -> anyhow::Result<()> {
+        let (prefix, name) = split_path(path)
+            .with_context(|| format!("\"{path}\" is too long to store in a tar archive"))?;
+
+        let mut header = [0; BLOCK_SIZE];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        write_octal(&mut header[100..108], 0o644)?;
+        write_octal(&mut header[108..116], 0)?;
+        write_octal(&mut header[116..124], 0)?;
+        write_octal(&mut header[124..136], u64::try_from(data.len())?)
+            .with_context(|| format!("\"{path}\" is too large to store in a tar archive"))?;
+        write_octal(&mut header[136..148], 0)?;
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+        // The checksum is calculated with the checksum field as spaces.
+        header[148..156].fill(b' ');
+        let checksum: u64 = header.iter().map(|byte| u64::from(*byte)).sum();
+        write_octal(&mut header[148..155], checksum)?;
+
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)?;
+        let padding = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        self.writer.write_all(&[0; BLOCK_SIZE][..padding])?;
+
+        Ok(())
+    }
+
+    /// Write the end of the archive, returning the inner writer.
+    pub fn finish(mut self) -> anyhow::Result<W> {
+        self.writer.write_all(&[0; BLOCK_SIZE * 2])?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+/// A reader for tar archives, supporting only regular files.
+///
+/// Other entries, like directories and extended headers, are skipped.
+pub struct TarReader<R> {
+    reader: R,
+}
+
+impl<R> TarReader<R>
+where
+    R: Read,
+{
+    /// Make a new tar reader.
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    /// Read the next file, returning its path and data.
+    ///
+    /// Returns `None` at the end of the archive.
+    pub fn next_file(&mut self) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+        loop {
+            let mut header = [0; BLOCK_SIZE];
+            self.reader
+                .read_exact(&mut header)
+                .context("archive is truncated")?;
+            if header.iter().all(|byte| *byte == 0) {
+                return Ok(None);
+            }
+
+            let expected_checksum = parse_octal(&header[148..156])?;
+            let checksum: u64 = header
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| match i {
+                    148..156 => u64::from(b' '),
+                    _ => u64::from(*byte),
+                })
+                .sum();
+            ensure!(
+                checksum == expected_checksum,
+                "archive header checksum does not match"
+            );
+
+            let size = parse_octal(&header[124..136])?;
+            let padding = (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64;
+            let mut data = Vec::new();
+            (&mut self.reader).take(size).read_to_end(&mut data)?;
+            ensure!(u64::try_from(data.len())? == size, "archive is truncated");
+            std::io::copy(&mut (&mut self.reader).take(padding), &mut std::io::sink())?;
+
+            // Only regular files are read.
+            if !matches!(header[156], b'0' | 0) {
+                continue;
+            }
+
+            let name = parse_str(&header[..100])?;
+            let prefix = match &header[257..262] == b"ustar" {
+                true => parse_str(&header[345..500])?,
+                false => "",
+            };
+            let path = match prefix.is_empty() {
+                true => String::from(name),
+                false => format!("{prefix}/{name}"),
+            };
+
+            return Ok(Some((path, data)));
+        }
+    }
+}
+
+/// Split a path into the ustar prefix and name fields.
+///
+/// Paths that fit in the name field have an empty prefix.
+/// Returns `None` if the path cannot be split so that both parts fit.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+
+    path.match_indices('/')
+        .map(|(index, _)| (&path[..index], &path[index + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && !name.is_empty() && name.len() <= 100)
+}
+
+/// Parse a nul or space terminated octal string.
+fn parse_octal(field: &[u8]) -> anyhow::Result<u64> {
+    let value = std::str::from_utf8(field)
+        .context("invalid number in archive header")?
+        .trim_matches(|c| c == '\0' || c == ' ');
+    if value.is_empty() {
+        return Ok(0);
+    }
+
+    u64::from_str_radix(value, 8).context("invalid number in archive header")
+}
+
+/// Parse a nul-terminated string.
+fn parse_str(field: &[u8]) -> anyhow::Result<&str> {
+    let end = field
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(field.len());
+    std::str::from_utf8(&field[..end]).context("archive path is not utf8")
+}
+
+/// Write a number as a nul-terminated octal string, filling the field.
+///
+/// Numbers that need more digits than the field has are an error.
+fn write_octal(field: &mut [u8], value: u64) -> anyhow::Result<()> {
+    let digits = field.len() - 1;
+    let octal = format!("{value:0digits$o}");
+    ensure!(
+        octal.len() == digits,
+        "{value} does not fit in a {digits} digit tar header field"
+    );
+    field[..digits].copy_from_slice(octal.as_bytes());
+    field[digits] = 0;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tar_writer_works() {
+        let mut writer = TarWriter::new(Vec::new());
+        writer
+            .append("files/a.png", b"hello")
+            .expect("failed to append file");
+        let archive = writer.finish().expect("failed to finish archive");
+
+        // A header block, a padded data block, and two end blocks.
+        assert!(archive.len() == 512 * 4);
+        assert!(archive[..11] == *b"files/a.png");
+        assert!(archive[124..136] == *b"00000000005\0");
+        assert!(archive[257..263] == *b"ustar\0");
+        assert!(archive[512..517] == *b"hello");
+        assert!(archive[517..].iter().all(|byte| *byte == 0));
+
+        let checksum = std::str::from_utf8(&archive[148..154]).unwrap();
+        let checksum = u64::from_str_radix(checksum, 8).unwrap();
+        let expected: u64 = archive[..512]
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| match i {
+                148..156 => u64::from(b' '),
+                _ => u64::from(*byte),
+            })
+            .sum();
+        assert!(checksum == expected);
+
+        // Long paths are split into the prefix and name fields.
+        let mut writer = TarWriter::new(Vec::new());
+        let long_path = format!("files/{}", "a".repeat(100));
+        writer
+            .append(&long_path, b"long")
+            .expect("failed to append long path");
+        let archive = writer.finish().expect("failed to finish archive");
+        let mut reader = TarReader::new(archive.as_slice());
+        let (path, data) = reader
+            .next_file()
+            .expect("failed to read archive")
+            .expect("missing file");
+        assert!(path == long_path);
+        assert!(data == b"long");
+
+        // File names are still limited to the name field.
+        let mut writer = TarWriter::new(Vec::new());
+        let long_path = format!("files/{}", "a".repeat(101));
+        assert!(writer.append(&long_path, b"").is_err());
+    }
+}
@@ -1 +1,9 @@
+pub mod compare;
+pub mod completions;
 pub mod config;
+pub mod dedup;
+pub mod export;
+pub mod import;
+pub mod publish;
+pub mod status;
+pub mod tokens;
@@ -0,0 +1,170 @@
+use crate::api::Api;
+use crate::config::Config;
+use crate::hash_cache::get_hash_cache_path;
+use crate::hash_cache::HashCache;
+use crate::post::PostDiff;
+use crate::post_dirs::PostDirs;
+use crate::Stats;
+use crate::SyncContext;
+use crate::Timer;
+use anyhow::Context;
+use camino::Utf8Path;
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(
+    subcommand,
+    name = "compare",
+    description = "download every managed post and report the posts that drifted from their local config, ignoring the cache. Nothing is written. This uses the same input and token options as syncing"
+)]
+pub struct Options {}
+
+/// Compare each post directory with its remote posts, listing the posts that drifted.
+pub(crate) async fn exec<A>(ctx: &SyncContext<A>) -> anyhow::Result<()>
+where
+    A: Api,
+{
+    let mut post_dirs = PostDirs::new(ctx).await?;
+    let mut drifted = Vec::new();
+    while let Some(mut post_dir) = post_dirs.next().await? {
+        post_dirs.delay().await;
+
+        println!("comparing \"{}\"", post_dir.label);
+
+        let (is_drifted, stats) = compare_post(
+            ctx,
+            &post_dir.path,
+            &mut post_dir.config,
+            post_dir.cache_path.as_deref(),
+        )
+        .await?;
+        if is_drifted {
+            drifted.push(post_dir.label);
+        }
+        post_dirs.add_stats(stats);
+    }
+    post_dirs.finish()?;
+
+    println!("{} posts drifted from their local config", drifted.len());
+    for name in drifted.iter() {
+        println!("  {name}");
+    }
+
+    post_dirs.print_total_stats();
+
+    Ok(())
+}
+
+/// Compare a post with its remote post, ignoring the cache.
+///
+/// Hashes are reused from the hash cache next to the cache path, if given, but nothing is written.
+/// Each post of an album is compared with its own remote post.
+/// Returns whether the remote post drifted from the local config.
+pub(crate) async fn compare_post<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    config: &mut Config,
+    cache_path: Option<&Utf8Path>,
+) -> anyhow::Result<(bool, Stats)>
+where
+    A: Api,
+{
+    let post_config = config.post_mut();
+    if !post_config.album() {
+        return compare_single_post(ctx, dir_path, config, cache_path).await;
+    }
+
+    let mut stats = Stats::new();
+    let chunks = crate::get_album_chunks(&ctx.options, dir_path, &post_config)?;
+    let num_chunks = chunks.len();
+    let mut is_drifted = false;
+    for (i, mut chunk_config) in chunks.into_iter().enumerate() {
+        println!("  album post {}/{num_chunks}:", i + 1);
+
+        // Album posts have their own hash caches, next to their own caches.
+        let chunk_cache_path =
+            cache_path.map(|cache_path| crate::get_album_cache_path(cache_path, i));
+        let (chunk_is_drifted, chunk_stats) = compare_single_post(
+            ctx,
+            dir_path,
+            &mut chunk_config,
+            chunk_cache_path.as_deref(),
+        )
+        .await?;
+        is_drifted |= chunk_is_drifted;
+        stats += chunk_stats;
+    }
+
+    Ok((is_drifted, stats))
+}
+
+/// Compare a single post with its remote post, which is not an album.
+async fn compare_single_post<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    config: &mut Config,
+    cache_path: Option<&Utf8Path>,
+) -> anyhow::Result<(bool, Stats)>
+where
+    A: Api,
+{
+    let options = &ctx.options;
+    let mut stats = Stats::new();
+
+    let post_config = config.post_mut();
+    let id = match post_config.id() {
+        Some(id) => id,
+        None => {
+            println!("  post has no id, skipping");
+            return Ok((false, stats));
+        }
+    };
+    let client = crate::get_post_client(ctx, &post_config)?;
+    let client = &*client;
+
+    let mut hash_cache = match cache_path {
+        Some(cache_path) if !options.no_read_cache => {
+            HashCache::load(&get_hash_cache_path(cache_path)).await?
+        }
+        _ => None,
+    };
+    let timer = Timer::start();
+    let (mut new_post, _, unreadable) = crate::create_post_from_post_config(
+        dir_path,
+        &post_config,
+        options,
+        ctx.default_privacy,
+        ctx.nsfw_marker,
+        &ctx.hasher,
+        hash_cache.as_mut(),
+    )
+    .await?;
+    timer.stop(&mut stats.hash_time);
+
+    let timer = Timer::start();
+    let old_post =
+        crate::create_post_from_online(client, id, options.api_base.as_deref(), &ctx.http_headers)
+            .await
+            .context("failed to create post from online")?;
+    timer.stop(&mut stats.download_time);
+    stats.api_calls += 1;
+
+    let diffs = crate::diff_local_post(
+        options,
+        &old_post,
+        &mut new_post,
+        &unreadable,
+        post_config.draft(),
+    )?;
+    let is_drifted = !diffs
+        .iter()
+        .all(|diff| matches!(diff, PostDiff::RetainFile { .. }));
+    if is_drifted {
+        println!("  remote post drifted from local config: [");
+        for diff in diffs.iter() {
+            println!("    {diff:?},");
+        }
+        println!("  ]");
+    }
+
+    Ok((is_drifted, stats))
+}
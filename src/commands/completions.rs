@@ -0,0 +1,280 @@
+use argh::ArgsInfo;
+use argh::CommandInfoWithArgs;
+use argh::FlagInfo;
+use argh::FlagInfoKind;
+use std::fmt::Write;
+
+const BIN_NAME: &str = "imgchest-sync";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl std::str::FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            _ => anyhow::bail!("\"{input}\" is not a supported shell"),
+        }
+    }
+}
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(
+    subcommand,
+    name = "completions",
+    description = "print a shell completion script to stdout"
+)]
+pub struct Options {
+    #[argh(
+        positional,
+        description = "the shell to generate completions for (bash, zsh, fish)"
+    )]
+    pub shell: Shell,
+}
+
+pub async fn exec(options: Options) -> anyhow::Result<()> {
+    let info = crate::Options::get_args_info();
+    let script = match options.shell {
+        Shell::Bash => generate_bash(&info),
+        Shell::Zsh => generate_zsh(&info),
+        Shell::Fish => generate_fish(&info),
+    };
+    print!("{script}");
+
+    Ok(())
+}
+
+/// Get the visible flags of a command, excluding the help flag.
+fn visible_flags(info: &CommandInfoWithArgs) -> impl Iterator<Item = &FlagInfo<'static>> {
+    info.flags
+        .iter()
+        .filter(|flag| !flag.hidden && flag.long != "--help")
+}
+
+fn takes_value(flag: &FlagInfo) -> bool {
+    matches!(flag.kind, FlagInfoKind::Option { .. })
+}
+
+fn generate_bash(info: &CommandInfoWithArgs) -> String {
+    let fn_name = format!("_{}", BIN_NAME.replace('-', "_"));
+    let subcommand_names: Vec<&str> = info.commands.iter().map(|command| command.name).collect();
+
+    let mut value_flags = Vec::new();
+    let mut words = Vec::new();
+    for flag in visible_flags(info) {
+        words.push(flag.long.to_string());
+        words.extend(flag.short.map(|short| format!("-{short}")));
+        if takes_value(flag) {
+            value_flags.push(flag.long.to_string());
+            value_flags.extend(flag.short.map(|short| format!("-{short}")));
+        }
+    }
+    words.extend(subcommand_names.iter().map(|name| name.to_string()));
+
+    let mut script = String::new();
+    writeln!(script, "{fn_name}() {{").unwrap();
+    writeln!(script, "    local cur prev subcommand opts word").unwrap();
+    writeln!(script, "    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"").unwrap();
+    writeln!(script, "    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"").unwrap();
+    writeln!(script, "    subcommand=\"\"").unwrap();
+    writeln!(
+        script,
+        "    for word in \"${{COMP_WORDS[@]:1:COMP_CWORD-1}}\"; do"
+    )
+    .unwrap();
+    writeln!(script, "        case \"$word\" in").unwrap();
+    writeln!(
+        script,
+        "            {}) subcommand=\"$word\"; break ;;",
+        subcommand_names.join("|")
+    )
+    .unwrap();
+    writeln!(script, "        esac").unwrap();
+    writeln!(script, "    done").unwrap();
+    writeln!(script, "    case \"$subcommand\" in").unwrap();
+    for command in info.commands.iter() {
+        let mut command_value_flags = Vec::new();
+        let mut command_words = Vec::new();
+        for flag in visible_flags(&command.command) {
+            command_words.push(flag.long.to_string());
+            command_words.extend(flag.short.map(|short| format!("-{short}")));
+            if takes_value(flag) {
+                command_value_flags.push(flag.long.to_string());
+                command_value_flags.extend(flag.short.map(|short| format!("-{short}")));
+            }
+        }
+        writeln!(script, "        {})", command.name).unwrap();
+        write_bash_value_check(&mut script, &command_value_flags);
+        writeln!(script, "            opts=\"{}\"", command_words.join(" ")).unwrap();
+        writeln!(script, "            ;;").unwrap();
+    }
+    writeln!(script, "        *)").unwrap();
+    write_bash_value_check(&mut script, &value_flags);
+    writeln!(script, "            opts=\"{}\"", words.join(" ")).unwrap();
+    writeln!(script, "            ;;").unwrap();
+    writeln!(script, "    esac").unwrap();
+    writeln!(
+        script,
+        "    COMPREPLY=( $(compgen -W \"$opts\" -- \"$cur\") )"
+    )
+    .unwrap();
+    writeln!(script, "}}").unwrap();
+    writeln!(script, "complete -o default -F {fn_name} {BIN_NAME}").unwrap();
+
+    script
+}
+
+/// Fall back to the default completion if the previous word expects a value.
+fn write_bash_value_check(script: &mut String, value_flags: &[String]) {
+    if value_flags.is_empty() {
+        return;
+    }
+
+    writeln!(script, "            case \"$prev\" in").unwrap();
+    writeln!(
+        script,
+        "                {}) COMPREPLY=(); return 0 ;;",
+        value_flags.join("|")
+    )
+    .unwrap();
+    writeln!(script, "            esac").unwrap();
+}
+
+/// Escape a string for use inside of a single-quoted zsh `_arguments` spec.
+fn escape_zsh(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '\'' => output.push_str("'\\''"),
+            '[' | ']' | ':' | '\\' => {
+                output.push('\\');
+                output.push(c);
+            }
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+fn write_zsh_flags(script: &mut String, info: &CommandInfoWithArgs, indent: &str) {
+    for flag in visible_flags(info) {
+        let description = escape_zsh(flag.description);
+        let value = match flag.kind {
+            FlagInfoKind::Option { arg_name } => format!(":{}:_default", escape_zsh(arg_name)),
+            FlagInfoKind::Switch => String::new(),
+        };
+        match flag.short {
+            Some(short) => writeln!(
+                script,
+                "{indent}'(-{short} {long})'{{-{short},{long}}}'[{description}]{value}' \\",
+                long = flag.long
+            )
+            .unwrap(),
+            None => writeln!(
+                script,
+                "{indent}'{long}[{description}]{value}' \\",
+                long = flag.long
+            )
+            .unwrap(),
+        }
+    }
+}
+
+fn generate_zsh(info: &CommandInfoWithArgs) -> String {
+    let fn_name = format!("_{}", BIN_NAME.replace('-', "_"));
+
+    let mut script = String::new();
+    writeln!(script, "#compdef {BIN_NAME}").unwrap();
+    writeln!(script).unwrap();
+    writeln!(script, "{fn_name}() {{").unwrap();
+    writeln!(script, "    local context state state_descr line").unwrap();
+    writeln!(script, "    local -a subcommands").unwrap();
+    writeln!(script, "    subcommands=(").unwrap();
+    for command in info.commands.iter() {
+        writeln!(
+            script,
+            "        '{}:{}'",
+            command.name,
+            escape_zsh(command.command.description)
+        )
+        .unwrap();
+    }
+    writeln!(script, "    )").unwrap();
+    writeln!(script, "    _arguments -C \\").unwrap();
+    write_zsh_flags(&mut script, info, "        ");
+    writeln!(script, "        '1: :->command' \\").unwrap();
+    writeln!(script, "        '*:: :->args'").unwrap();
+    writeln!(script, "    case $state in").unwrap();
+    writeln!(script, "        command)").unwrap();
+    writeln!(script, "            _describe 'command' subcommands").unwrap();
+    writeln!(script, "            ;;").unwrap();
+    writeln!(script, "        args)").unwrap();
+    writeln!(script, "            case $words[1] in").unwrap();
+    for command in info.commands.iter() {
+        writeln!(script, "                {})", command.name).unwrap();
+        writeln!(script, "                    _arguments \\").unwrap();
+        write_zsh_flags(&mut script, &command.command, "                        ");
+        writeln!(script, "                        '*: :_default'").unwrap();
+        writeln!(script, "                    ;;").unwrap();
+    }
+    writeln!(script, "            esac").unwrap();
+    writeln!(script, "            ;;").unwrap();
+    writeln!(script, "    esac").unwrap();
+    writeln!(script, "}}").unwrap();
+    writeln!(script).unwrap();
+    writeln!(script, "{fn_name} \"$@\"").unwrap();
+
+    script
+}
+
+/// Escape a string for use inside of a single-quoted fish string.
+fn escape_fish(input: &str) -> String {
+    input.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn write_fish_flags(script: &mut String, info: &CommandInfoWithArgs, condition: &str) {
+    for flag in visible_flags(info) {
+        write!(
+            script,
+            "complete -c {BIN_NAME} -n '{condition}' -l {}",
+            flag.long.trim_start_matches('-')
+        )
+        .unwrap();
+        if let Some(short) = flag.short {
+            write!(script, " -s {short}").unwrap();
+        }
+        if takes_value(flag) {
+            script.push_str(" -r");
+        }
+        writeln!(script, " -d '{}'", escape_fish(flag.description)).unwrap();
+    }
+}
+
+fn generate_fish(info: &CommandInfoWithArgs) -> String {
+    let mut script = String::new();
+    let top_level = "__fish_use_subcommand";
+    write_fish_flags(&mut script, info, top_level);
+    for command in info.commands.iter() {
+        writeln!(
+            script,
+            "complete -c {BIN_NAME} -n '{top_level}' -f -a {} -d '{}'",
+            command.name,
+            escape_fish(command.command.description)
+        )
+        .unwrap();
+    }
+    for command in info.commands.iter() {
+        let condition = format!("__fish_seen_subcommand_from {}", command.name);
+        write_fish_flags(&mut script, &command.command, &condition);
+    }
+
+    script
+}
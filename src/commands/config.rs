@@ -1,10 +1,21 @@
+use crate::config::Config;
+use crate::config::PostConfigPrivacy;
 use crate::UserConfig;
 use anyhow::bail;
 use anyhow::ensure;
 use anyhow::Context;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
 use std::path::Path;
+use toml_edit::DocumentMut;
+use toml_edit::Item;
+use toml_edit::Table;
 
-#[derive(Debug, argh::FromArgs)]
+/// The keys that can be set with --key.
+const ALLOWED_KEYS: &str =
+    "token, defaults.privacy, defaults.nsfw_marker, http.user_agent, tokens.<name>";
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
 #[argh(subcommand, name = "config", description = "interact with the config")]
 pub struct Options {
     #[argh(switch, description = "whether to open the config file")]
@@ -20,6 +31,46 @@ pub struct Options {
         description = "the new value of the key"
     )]
     pub value: Option<String>,
+
+    #[argh(
+        switch,
+        description = "whether to print the effective config, with tokens redacted"
+    )]
+    pub dump: bool,
+
+    #[argh(
+        option,
+        long = "post",
+        description = "a post config file to edit with --disable-file or --enable-file"
+    )]
+    pub post: Option<Utf8PathBuf>,
+
+    #[argh(
+        option,
+        long = "disable-file",
+        description = "the path of a file in the post config to disable"
+    )]
+    pub disable_file: Option<String>,
+
+    #[argh(
+        option,
+        long = "enable-file",
+        description = "the path of a file in the post config to enable"
+    )]
+    pub enable_file: Option<String>,
+
+    #[argh(
+        switch,
+        long = "sort-files",
+        description = "whether to sort the files of the post config given with --post into natural order by path. This changes the upload order, so moved files are uploaded again on the next sync"
+    )]
+    pub sort_files: bool,
+
+    #[argh(
+        switch,
+        description = "whether to only validate and print config changes, without writing them"
+    )]
+    pub check: bool,
 }
 
 pub async fn exec(
@@ -35,19 +86,105 @@ pub async fn exec(
         ensure!(options.key.is_some(), "if a config value (--value, -v) is specified, a config key (--key, -k) must also be specified");
     }
 
+    let toggle_file = match (
+        options.disable_file.as_deref(),
+        options.enable_file.as_deref(),
+    ) {
+        (Some(path), None) => Some((path, true)),
+        (None, Some(path)) => Some((path, false)),
+        (None, None) => None,
+        (Some(_), Some(_)) => {
+            bail!("--disable-file and --enable-file cannot be used together");
+        }
+    };
+    match (options.post.as_deref(), toggle_file) {
+        (Some(post_path), Some((file_path, disabled))) => {
+            set_post_file_disabled(post_path, file_path, disabled, options.check).await?;
+        }
+        (Some(_), None) if options.sort_files => {}
+        (Some(_), None) => {
+            bail!("--post requires --disable-file, --enable-file, or --sort-files");
+        }
+        (None, Some(_)) => {
+            bail!("--disable-file and --enable-file require a post config, specified with --post");
+        }
+        (None, None) => {}
+    }
+    match (options.post.as_deref(), options.sort_files) {
+        (Some(post_path), true) => {
+            sort_post_files(post_path, options.check).await?;
+        }
+        (None, true) => {
+            bail!("--sort-files requires a post config, specified with --post");
+        }
+        (_, false) => {}
+    }
+
     if let (Some(key), Some(value)) = (options.key.as_deref(), options.value.as_deref()) {
-        match key {
-            "token" => {
+        // An empty value removes the key.
+        let new_value = (!value.is_empty()).then_some(value);
+        let (display_value, unchanged) = match key.split_once('.') {
+            None if key == "token" => {
+                validate_token(value)?;
+                let unchanged = config.token() == new_value;
                 config.set_token(value);
+                (String::from("<redacted>"), unchanged)
+            }
+            Some(("defaults", "privacy")) => {
+                let privacy = new_value
+                    .map(|value| value.parse::<PostConfigPrivacy>())
+                    .transpose()?;
+                let unchanged = config.default_privacy() == privacy;
+                config.set_default_privacy(privacy);
+                (format!("\"{value}\""), unchanged)
+            }
+            Some(("defaults", "nsfw_marker")) => {
+                let nsfw_marker = new_value
+                    .map(|value| {
+                        value.parse::<bool>().with_context(|| {
+                            format!("\"{value}\" is not a bool. Use \"true\" or \"false\"")
+                        })
+                    })
+                    .transpose()?;
+                let unchanged = config.nsfw_marker() == nsfw_marker;
+                config.set_nsfw_marker(nsfw_marker);
+                (String::from(value), unchanged)
+            }
+            Some(("http", "user_agent")) => {
+                let unchanged = config.http_user_agent() == new_value;
+                config.set_http_user_agent(new_value)?;
+                (format!("\"{value}\""), unchanged)
+            }
+            Some(("tokens", name)) if !name.is_empty() => {
+                validate_token(value)?;
+                let old_token = config
+                    .tokens()
+                    .find(|(token_name, _token)| *token_name == name)
+                    .map(|(_name, token)| token);
+                let unchanged = old_token == new_value;
+                config.set_named_token(name, new_value);
+                (String::from("<redacted>"), unchanged)
             }
             _ => {
-                bail!("key \"{key}\" is not recognized");
+                bail!("key \"{key}\" is not recognized. Valid keys are: {ALLOWED_KEYS}");
+            }
+        };
+
+        if options.check {
+            match (unchanged, new_value) {
+                (true, _) => println!("\"{key}\" is unchanged"),
+                (false, Some(_)) => println!("would set \"{key}\" to {display_value}"),
+                (false, None) => println!("would remove \"{key}\""),
             }
+        } else {
+            crate::util::write_string_safe(&config_path, &config.to_string())
+                .await
+                .context("failed to write string")?;
         }
+    }
 
-        crate::util::write_string_safe(&config_path, &config.to_string())
-            .await
-            .context("failed to write string")?;
+    if options.dump {
+        print!("{}", dump_config(&config));
     }
 
     if options.open {
@@ -69,3 +206,194 @@ pub async fn exec(
 
     Ok(())
 }
+
+/// Ensure that a token looks valid.
+fn validate_token(token: &str) -> anyhow::Result<()> {
+    ensure!(
+        !token.chars().any(|c| c.is_whitespace() || c.is_control()),
+        "token must not contain whitespace or control characters"
+    );
+
+    Ok(())
+}
+
+/// Enable or disable a file in a post config.
+///
+/// If the post config does not list the file, the file is toggled in the included config that lists it.
+/// If check is true, the change is only printed.
+pub async fn set_post_file_disabled(
+    post_path: &Utf8Path,
+    file_path: &str,
+    disabled: bool,
+    check: bool,
+) -> anyhow::Result<()> {
+    let search_path = post_path.to_path_buf();
+    let search_file_path = String::from(file_path);
+    let found = tokio::task::spawn_blocking(move || {
+        find_config_with_file(&search_path, &search_file_path, &mut Vec::new())
+    })
+    .await
+    .context("config task panicked")??;
+    let (post_path, mut config) =
+        found.with_context(|| format!("post config has no file with path \"{file_path}\""))?;
+    let post_path = post_path.as_path();
+
+    let unchanged = config
+        .post_mut()
+        .files()
+        .iter()
+        .filter(|file| file.path() == Some(file_path))
+        .all(|file| file.disabled() == disabled);
+    config.post_mut().set_file_disabled(file_path, disabled);
+
+    if check {
+        let action = if disabled { "disable" } else { "enable" };
+        if unchanged {
+            println!("\"{file_path}\" is already {action}d in \"{post_path}\"");
+        } else {
+            println!("would {action} \"{file_path}\" in \"{post_path}\"");
+        }
+        return Ok(());
+    }
+
+    crate::util::write_string_safe(post_path, &config.to_string())
+        .await
+        .context("failed to write post config")?;
+
+    Ok(())
+}
+
+/// Find the config that lists a file, searching the given config and then its includes in order.
+///
+/// Included configs are resolved relative to the directory of the including config.
+/// The stack holds the configs being searched, to detect cycles.
+fn find_config_with_file(
+    config_path: &Utf8Path,
+    file_path: &str,
+    stack: &mut Vec<Utf8PathBuf>,
+) -> anyhow::Result<Option<(Utf8PathBuf, Config)>> {
+    let canonical_path = config_path
+        .canonicalize_utf8()
+        .with_context(|| format!("failed to read \"{config_path}\""))?;
+    ensure!(
+        !stack.contains(&canonical_path),
+        "included config \"{config_path}\" includes itself"
+    );
+
+    let config_raw = std::fs::read_to_string(config_path)
+        .with_context(|| format!("failed to read \"{config_path}\""))?;
+    let mut config =
+        Config::new(&config_raw).with_context(|| format!("failed to parse \"{config_path}\""))?;
+    let post_config = config.post_mut();
+    if post_config
+        .files()
+        .iter()
+        .any(|file| file.path() == Some(file_path))
+    {
+        return Ok(Some((config_path.into(), config)));
+    }
+
+    let dir_path = config_path.parent().unwrap_or(Utf8Path::new(""));
+    let includes: Vec<Utf8PathBuf> = post_config
+        .includes()
+        .into_iter()
+        .map(|include| dir_path.join(include))
+        .collect();
+    stack.push(canonical_path);
+    for include in includes.iter() {
+        if let Some(found) = find_config_with_file(include, file_path, stack)? {
+            return Ok(Some(found));
+        }
+    }
+    stack.pop();
+
+    Ok(None)
+}
+
+/// Sort the files of a post config into natural order.
+///
+/// If check is true, the change is only printed.
+async fn sort_post_files(post_path: &Utf8Path, check: bool) -> anyhow::Result<()> {
+    let config_raw = tokio::fs::read_to_string(post_path)
+        .await
+        .context("failed to read post config")?;
+    let mut config = Config::new(&config_raw).context("failed to parse post config")?;
+    // Included files always come after the files of this config, so they cannot be sorted together.
+    ensure!(
+        config.post_mut().includes().is_empty(),
+        "--sort-files cannot sort a post config with includes, since included files always come after its own files"
+    );
+
+    if !config.post_mut().sort_files() {
+        println!("files are already sorted");
+        return Ok(());
+    }
+
+    if check {
+        println!("would sort the files of \"{post_path}\"");
+        return Ok(());
+    }
+
+    crate::util::write_string_safe(post_path, &config.to_string())
+        .await
+        .context("failed to write post config")?;
+    eprintln!("warning: the upload order changed, so the next sync will upload moved files again");
+
+    Ok(())
+}
+
+/// Build a document of the effective config, with defaults filled in and tokens and sensitive headers redacted.
+pub(crate) fn dump_config(config: &UserConfig) -> DocumentMut {
+    const REDACTED: &str = "<redacted>";
+
+    let mut document = DocumentMut::new();
+    if config.token().is_some() {
+        document.insert("token", toml_edit::value(REDACTED));
+    }
+
+    let mut tokens = Table::new();
+    for (name, _token) in config.tokens() {
+        tokens.insert(name, toml_edit::value(REDACTED));
+    }
+    document.insert("tokens", Item::Table(tokens));
+
+    let mut http = Table::new();
+    if let Some(user_agent) = config.http_user_agent() {
+        http.insert("user_agent", toml_edit::value(user_agent));
+    }
+    let mut headers = Table::new();
+    for (name, value) in config.http_extra_headers() {
+        let value = match crate::api::is_redacted_header(name) {
+            true => REDACTED,
+            false => value,
+        };
+        headers.insert(name, toml_edit::value(value));
+    }
+    http.insert("headers", Item::Table(headers));
+    document.insert("http", Item::Table(http));
+
+    let mut env = Table::new();
+    if let Ok(api_base) = std::env::var("IMGCHEST_SYNC_API_BASE") {
+        env.insert("IMGCHEST_SYNC_API_BASE", toml_edit::value(api_base));
+    }
+    document.insert("env", Item::Table(env));
+
+    let mut post_defaults = Table::new();
+    post_defaults.insert(
+        "privacy",
+        toml_edit::value(
+            config
+                .default_privacy()
+                .unwrap_or(PostConfigPrivacy::Hidden)
+                .as_str(),
+        ),
+    );
+    post_defaults.insert("nsfw", toml_edit::value(false));
+    post_defaults.insert(
+        "nsfw_marker",
+        toml_edit::value(config.nsfw_marker().unwrap_or(false)),
+    );
+    document.insert("post_defaults", Item::Table(post_defaults));
+
+    document
+}
@@ -0,0 +1,236 @@
+use crate::config::Config;
+use crate::hash_cache::get_hash_cache_path;
+use crate::hash_cache::HashCache;
+use crate::post_dirs::PostDirs;
+use crate::SyncContext;
+use anyhow::Context;
+use camino::Utf8Path;
+use std::collections::BTreeMap;
+
+/// The format of a dedup report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => anyhow::bail!("\"{input}\" is not a valid report format"),
+        }
+    }
+}
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(
+    subcommand,
+    name = "dedup",
+    description = "find files that are duplicated across post directories. This uses the same input options as syncing"
+)]
+pub struct Options {
+    #[argh(subcommand)]
+    pub subcommand: Subcommand,
+}
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(subcommand)]
+pub enum Subcommand {
+    Report(ReportOptions),
+}
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(
+    subcommand,
+    name = "report",
+    description = "hash every file, reusing cached hashes, and report groups of identical files in more than one directory. Nothing is written"
+)]
+pub struct ReportOptions {
+    #[argh(
+        option,
+        long = "format",
+        default = "ReportFormat::Text",
+        description = "the output format, text or json. Defaults to text"
+    )]
+    pub format: ReportFormat,
+}
+
+/// A group of identical files.
+#[derive(Debug, serde::Serialize)]
+pub struct DuplicateGroup {
+    /// The sha256 hash of the files, as a hex string.
+    pub sha256: String,
+
+    /// The files.
+    pub files: Vec<DuplicateFile>,
+}
+
+/// A file of a duplicate group.
+#[derive(Debug, serde::Serialize)]
+pub struct DuplicateFile {
+    /// The name of the post directory.
+    pub dir: String,
+
+    /// The path of the file, relative to the post directory if possible.
+    pub path: String,
+}
+
+/// A dedup report.
+#[derive(Debug, serde::Serialize)]
+struct Report<'a> {
+    /// The groups of duplicate files.
+    groups: &'a [DuplicateGroup],
+}
+
+/// Hash the files of each post directory and report the groups of duplicate files.
+pub(crate) async fn exec<A>(ctx: &SyncContext<A>, format: ReportFormat) -> anyhow::Result<()> {
+    let mut post_dirs = PostDirs::new(ctx).await?;
+    let mut files_by_hash = BTreeMap::new();
+    while let Some(mut post_dir) = post_dirs.next().await? {
+        // Only the report may be printed to stdout, so that it can be parsed.
+        eprintln!("hashing \"{}\"", post_dir.label);
+
+        collect_file_hashes(
+            ctx,
+            &post_dir.path,
+            &mut post_dir.config,
+            post_dir.cache_path.as_deref(),
+            &mut files_by_hash,
+        )
+        .await?;
+    }
+    post_dirs.finish()?;
+
+    let groups: Vec<DuplicateGroup> = files_by_hash
+        .into_iter()
+        .filter(|(_, files)| files.iter().any(|file| file.dir != files[0].dir))
+        .map(|(sha256, files)| DuplicateGroup { sha256, files })
+        .collect();
+    print_report(&groups, format)
+}
+
+/// Hash the local files of a post for a dedup report, grouping them by hash.
+///
+/// Hashes are reused from the hash cache next to the cache path, if given,
+/// but the hash cache is never written.
+async fn collect_file_hashes<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    config: &mut Config,
+    cache_path: Option<&Utf8Path>,
+    files_by_hash: &mut BTreeMap<String, Vec<DuplicateFile>>,
+) -> anyhow::Result<()> {
+    let options = &ctx.options;
+    let dir_name = dir_path.file_name().context("missing dir name")?;
+
+    let mut hash_cache = match cache_path {
+        Some(cache_path) if !options.no_read_cache => {
+            HashCache::load(&get_hash_cache_path(cache_path)).await?
+        }
+        _ => None,
+    };
+
+    let post_config = config.post_mut();
+    let (post, _, _) = crate::create_post_from_post_config(
+        dir_path,
+        &post_config,
+        options,
+        ctx.default_privacy,
+        ctx.nsfw_marker,
+        &ctx.hasher,
+        hash_cache.as_mut(),
+    )
+    .await?;
+
+    for file in post.files {
+        let path = match (file.path, file.data) {
+            (Some(path), _) => match path.strip_prefix(dir_path) {
+                Ok(path) => path.to_string(),
+                Err(_) => path.to_string(),
+            },
+            (None, Some(data)) => data.file_name,
+            (None, None) => continue,
+        };
+        files_by_hash
+            .entry(file.sha256)
+            .or_default()
+            .push(DuplicateFile {
+                dir: dir_name.into(),
+                path,
+            });
+    }
+
+    Ok(())
+}
+
+/// Print a dedup report to stdout.
+pub fn print_report(groups: &[DuplicateGroup], format: ReportFormat) -> anyhow::Result<()> {
+    match format {
+        ReportFormat::Text => {
+            for group in groups.iter() {
+                println!("{}", group.sha256);
+                for file in group.files.iter() {
+                    println!("  {}/{}", file.dir, file.path);
+                }
+            }
+            println!("found {} groups of duplicate files", groups.len());
+        }
+        ReportFormat::Json => {
+            let report = serde_json::to_string_pretty(&Report { groups })?;
+            println!("{report}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::block_on;
+    use crate::test::mock_context;
+    use crate::test::TestPostDir;
+    use crate::test::TEST_POST_CONFIG;
+
+    #[test]
+    fn collect_file_hashes_finds_duplicates() {
+        let ctx = mock_context(&["dedup", "report", "--format", "json"]);
+        let first = TestPostDir::new(
+            "collect-file-hashes-finds-duplicates-1",
+            &[("a.png", "a"), ("b.png", "b")],
+            TEST_POST_CONFIG,
+        );
+        let second = TestPostDir::new(
+            "collect-file-hashes-finds-duplicates-2",
+            &[("a.png", "b"), ("b.png", "c")],
+            TEST_POST_CONFIG,
+        );
+
+        let mut files_by_hash = BTreeMap::new();
+        for dir in [&first, &second] {
+            let mut config = Config::new(&dir.read_config()).expect("failed to parse config");
+            let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+            block_on(collect_file_hashes(
+                &ctx,
+                &dir.path,
+                &mut config,
+                Some(&cache_path),
+                &mut files_by_hash,
+            ))
+            .expect("failed to collect file hashes");
+        }
+
+        let files = &files_by_hash[&crate::hash_bytes(b"b")];
+        assert!(files.len() == 2);
+        assert!(Some(files[0].dir.as_str()) == first.path.file_name());
+        assert!(files[0].path == "b.png");
+        assert!(Some(files[1].dir.as_str()) == second.path.file_name());
+        assert!(files[1].path == "a.png");
+        assert!(files_by_hash[&crate::hash_bytes(b"a")].len() == 1);
+        assert!(ctx.client.take_calls().is_empty());
+    }
+}
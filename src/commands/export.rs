@@ -0,0 +1,189 @@
+use crate::bundle::Manifest;
+use crate::bundle::ManifestFile;
+use crate::bundle::TarWriter;
+use crate::bundle::BUNDLE_VERSION;
+use crate::bundle::FILES_DIR;
+use crate::bundle::MANIFEST_NAME;
+use crate::config::Config;
+use crate::config::PostConfigPrivacy;
+use crate::Cache;
+use anyhow::ensure;
+use anyhow::Context;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use std::collections::HashSet;
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(
+    subcommand,
+    name = "export",
+    description = "bundle the files of a post folder into a tar archive, with a json manifest of its title, privacy, nsfw, descriptions, and ids"
+)]
+pub struct Options {
+    #[argh(positional, description = "the post directory to export")]
+    pub dir: Utf8PathBuf,
+
+    #[argh(
+        option,
+        long = "output",
+        short = 'o',
+        description = "the path of the archive to write. Defaults to the directory name with a \".tar\" extension, in the current directory"
+    )]
+    pub output: Option<Utf8PathBuf>,
+}
+
+pub async fn exec(
+    options: &crate::Options,
+    export_options: &Options,
+    default_privacy: PostConfigPrivacy,
+    nsfw_marker: bool,
+) -> anyhow::Result<()> {
+    let dir_path = export_options
+        .dir
+        .canonicalize_utf8()
+        .with_context(|| format!("failed to canonicalize \"{}\"", export_options.dir))?;
+    let dir_name = dir_path.file_name().context("missing dir name")?;
+    let config_path = dir_path.join("imgchest-sync.toml");
+    let config_raw = tokio::fs::read_to_string(&config_path)
+        .await
+        .with_context(|| format!("failed to read \"{config_path}\""))?;
+    let mut config = Config::new(&config_raw).context("failed to parse config file")?;
+    config
+        .load_includes(dir_path.as_std_path())
+        .context("failed to load included configs")?;
+    let post_config = config.post_mut();
+    ensure!(!post_config.album(), "exporting albums is not supported");
+
+    let hash_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.hash_jobs.unwrap_or(0))
+        .thread_name(|i| format!("imgchest-sync-hash-{i}"))
+        .build()
+        .context("failed to create hash thread pool")?;
+    let hasher = crate::Hasher {
+        pool: hash_pool,
+        db: crate::open_hash_db(options)?,
+    };
+    let (post, _, _) = crate::create_post_from_post_config(
+        &dir_path,
+        &post_config,
+        options,
+        default_privacy,
+        nsfw_marker,
+        &hasher,
+        None,
+    )
+    .await?;
+
+    // The cache knows the remote file ids, as long as the files did not change since the last sync.
+    let cache_path = crate::get_cache_path(&dir_path, options.cache_dir.as_deref())?;
+    let cache = crate::util::try_read_to_string(&cache_path)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|cache_raw| toml::from_str::<Cache>(&cache_raw).ok());
+    let cached_files = cache.map(|cache| cache.post.files).unwrap_or_default();
+
+    let mut names = HashSet::new();
+    let mut manifest_files = Vec::with_capacity(post.files.len());
+    for (index, file) in post.files.iter().enumerate() {
+        let file_name = crate::upload_file_name(file)?;
+        // Files from different directories may share a name.
+        let name = unique_file_name(&names, index, file_name);
+        names.insert(name.clone());
+
+        let cached_id = cached_files
+            .get(index)
+            .filter(|cached_file| cached_file.sha256 == file.sha256)
+            .and_then(|cached_file| cached_file.id.clone());
+
+        manifest_files.push(ManifestFile {
+            name,
+            description: file.description.clone(),
+            sha256: file.sha256.clone(),
+            id: cached_id.or_else(|| file.id.clone()),
+        });
+    }
+    let manifest = Manifest {
+        version: BUNDLE_VERSION,
+        title: post.title.clone(),
+        privacy: post.privacy,
+        nsfw: post.nsfw,
+        id: post_config.id().map(String::from),
+        files: manifest_files,
+    };
+
+    let output = match export_options.output.clone() {
+        Some(output) => output,
+        None => Utf8PathBuf::from(format!("{dir_name}.tar")),
+    };
+    println!(
+        "exporting \"{dir_path}\" with {} files to \"{output}\"",
+        post.files.len()
+    );
+    tokio::task::spawn_blocking(move || write_bundle(&output, &manifest, &post.files))
+        .await
+        .context("export task panicked")??;
+
+    Ok(())
+}
+
+/// Get a name for a file that is not in the given names, numbering it with its index if needed.
+///
+/// A numbered name may be taken by another file as well, so the number is increased until it is unused.
+pub(crate) fn unique_file_name(names: &HashSet<String>, index: usize, file_name: &str) -> String {
+    let mut name = String::from(file_name);
+    let mut number = index;
+    while names.contains(&name) {
+        name = format!("{number}-{file_name}");
+        number += 1;
+    }
+
+    name
+}
+
+/// Write a bundle, using a temp file.
+fn write_bundle(
+    path: &Utf8Path,
+    manifest: &Manifest,
+    files: &[crate::post::PostFile],
+) -> anyhow::Result<()> {
+    let tmp_path = nd_util::with_push_extension(path, "temp");
+    let result = (|| {
+        let file = std::fs::File::create(&tmp_path)
+            .with_context(|| format!("failed to create \"{}\"", tmp_path.display()))?;
+        let mut writer = TarWriter::new(std::io::BufWriter::new(file));
+
+        // The manifest is first, so that importing can check its version before unpacking any files.
+        let manifest_raw = serde_json::to_string_pretty(manifest)?;
+        writer.append(MANIFEST_NAME, manifest_raw.as_bytes())?;
+        for (manifest_file, file) in manifest.files.iter().zip(files.iter()) {
+            let archive_path = format!("{FILES_DIR}/{}", manifest_file.name);
+            match (file.data.as_ref(), file.path.as_deref()) {
+                (Some(data), _) => writer.append(&archive_path, &data.bytes)?,
+                (None, Some(path)) if file.orient => {
+                    let data = crate::read_oriented_file(path, &file.sha256)?;
+                    writer.append(&archive_path, &data)?;
+                }
+                (None, Some(path)) => {
+                    let data = std::fs::read(path)
+                        .with_context(|| format!("failed to read \"{path}\""))?;
+                    writer.append(&archive_path, &data)?;
+                }
+                (None, None) => unreachable!("post file has neither data nor a path"),
+            }
+        }
+        writer
+            .finish()?
+            .into_inner()
+            .map_err(|error| error.into_error())?
+            .sync_all()?;
+
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("failed to rename \"{}\" to \"{path}\"", tmp_path.display()))
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+
+    result
+}
@@ -0,0 +1,347 @@
+use crate::bundle::Manifest;
+use crate::bundle::TarReader;
+use crate::bundle::BUNDLE_VERSION;
+use crate::bundle::FILES_DIR;
+use crate::bundle::MANIFEST_NAME;
+use crate::config::Config;
+use crate::post::PostPrivacy;
+use anyhow::bail;
+use anyhow::ensure;
+use anyhow::Context;
+use camino::Utf8Component;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use std::collections::HashMap;
+use std::io::Write;
+use toml_edit::ArrayOfTables;
+use toml_edit::DocumentMut;
+use toml_edit::Item;
+use toml_edit::Table;
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(
+    subcommand,
+    name = "import",
+    description = "unpack an archive made by the export subcommand into a new post folder, with a config that creates a new post on the next sync"
+)]
+pub struct Options {
+    #[argh(positional, description = "the archive to import")]
+    pub archive: Utf8PathBuf,
+
+    #[argh(
+        option,
+        long = "output",
+        short = 'o',
+        description = "the directory to unpack the post into. Defaults to the archive name without its extension, in the current directory"
+    )]
+    pub output: Option<Utf8PathBuf>,
+}
+
+pub async fn exec(options: Options) -> anyhow::Result<()> {
+    let output = match options.output {
+        Some(output) => output,
+        None => Utf8PathBuf::from(
+            options
+                .archive
+                .file_stem()
+                .context("missing archive file name")?,
+        ),
+    };
+    let config_path = output.join("imgchest-sync.toml");
+    ensure!(
+        !tokio::fs::try_exists(&config_path)
+            .await
+            .context("failed to check for config file")?,
+        "\"{output}\" already has a post config"
+    );
+
+    println!("importing \"{}\" to \"{output}\"", options.archive);
+    let archive = options.archive;
+    let unpack_output = output.clone();
+    let manifest = tokio::task::spawn_blocking(move || unpack_bundle(&archive, &unpack_output))
+        .await
+        .context("import task panicked")??;
+
+    let config = create_post_config(&manifest)?;
+    crate::util::write_string_safe(&config_path, &config)
+        .await
+        .context("failed to write config file")?;
+    println!("  imported {} files", manifest.files.len());
+
+    Ok(())
+}
+
+/// Unpack the files of a bundle into a directory, returning its manifest.
+///
+/// Existing files are never overwritten.
+/// If unpacking fails, the files it created are removed, along with the directory if it created it,
+/// so that the import can be retried.
+pub fn unpack_bundle(archive_path: &Utf8Path, output: &Utf8Path) -> anyhow::Result<Manifest> {
+    let file = std::fs::File::open(archive_path)
+        .with_context(|| format!("failed to open \"{archive_path}\""))?;
+    let mut reader = TarReader::new(std::io::BufReader::new(file));
+
+    let (path, data) = reader.next_file()?.context("archive is empty")?;
+    ensure!(
+        path == MANIFEST_NAME,
+        "archive does not start with \"{MANIFEST_NAME}\""
+    );
+    let manifest = parse_manifest(&data)?;
+
+    let mut files = HashMap::with_capacity(manifest.files.len());
+    for (index, file) in manifest.files.iter().enumerate() {
+        ensure!(
+            is_plain_file_name(&file.name),
+            "manifest file name \"{}\" is not a plain file name",
+            file.name
+        );
+        ensure!(
+            files.insert(file.name.as_str(), (index, false)).is_none(),
+            "manifest file name \"{}\" is used more than once",
+            file.name
+        );
+    }
+
+    let output_existed = output.try_exists()?;
+    std::fs::create_dir_all(output).with_context(|| format!("failed to create \"{output}\""))?;
+    let mut created = Vec::with_capacity(manifest.files.len());
+    let result = unpack_files(&mut reader, &manifest, &mut files, output, &mut created);
+    if result.is_err() {
+        for path in created.iter() {
+            let _ = std::fs::remove_file(path);
+        }
+        if !output_existed {
+            let _ = std::fs::remove_dir(output);
+        }
+    }
+    result?;
+
+    Ok(manifest)
+}
+
+/// Unpack the files after the manifest of a bundle, checking them against the manifest.
+///
+/// `files` maps each manifest file name to its index and whether it was seen.
+/// The path of each created file is pushed to `created`.
+fn unpack_files<R>(
+    reader: &mut TarReader<R>,
+    manifest: &Manifest,
+    files: &mut HashMap<&str, (usize, bool)>,
+    output: &Utf8Path,
+    created: &mut Vec<Utf8PathBuf>,
+) -> anyhow::Result<()>
+where
+    R: std::io::Read,
+{
+    while let Some((path, data)) = reader.next_file()? {
+        let name = match path
+            .strip_prefix(FILES_DIR)
+            .and_then(|path| path.strip_prefix('/'))
+        {
+            Some(name) => name,
+            None => bail!("archive has unexpected file \"{path}\""),
+        };
+        let (index, seen) = files
+            .get_mut(name)
+            .with_context(|| format!("archive file \"{path}\" is not in the manifest"))?;
+        ensure!(!*seen, "archive has \"{path}\" more than once");
+        *seen = true;
+        ensure!(
+            crate::hash_bytes(&data) == manifest.files[*index].sha256,
+            "\"{path}\" does not match its hash in the manifest"
+        );
+
+        let file_path = output.join(name);
+        let mut file = std::fs::File::create_new(&file_path)
+            .with_context(|| format!("failed to create \"{file_path}\""))?;
+        created.push(file_path);
+        file.write_all(&data)?;
+        file.sync_all()?;
+    }
+
+    if let Some(file) = manifest
+        .files
+        .iter()
+        .find(|file| !files[file.name.as_str()].1)
+    {
+        bail!("archive is missing \"{FILES_DIR}/{}\"", file.name);
+    }
+
+    Ok(())
+}
+
+/// Parse a manifest, checking its version first.
+fn parse_manifest(data: &[u8]) -> anyhow::Result<Manifest> {
+    let manifest: serde_json::Value =
+        serde_json::from_slice(data).context("failed to parse manifest")?;
+    let version = manifest
+        .get("version")
+        .and_then(|version| version.as_u64())
+        .context("manifest is missing a version")?;
+    ensure!(
+        version <= u64::from(BUNDLE_VERSION),
+        "archive was made with version {version} of the bundle format, but only versions up to {BUNDLE_VERSION} are supported. Update imgchest-sync to import it"
+    );
+
+    serde_json::from_value(manifest).context("failed to parse manifest")
+}
+
+/// Check whether a name is a single, normal path component.
+///
+/// Names of files that imgchest-sync keeps in a post folder are not plain,
+/// like the post config, or the cache and its journal and hash cache.
+/// They are compared case-insensitively, as file systems may be case-insensitive.
+fn is_plain_file_name(name: &str) -> bool {
+    let mut components = Utf8Path::new(name).components();
+    let lowercase_name = name.to_ascii_lowercase();
+    matches!(components.next(), Some(Utf8Component::Normal(component)) if component == name)
+        && components.next().is_none()
+        && lowercase_name != "imgchest-sync.toml"
+        && !lowercase_name.starts_with(".imgchest-sync-cache.")
+}
+
+/// Create a post config for an imported bundle.
+///
+/// The config has no ids, so the next sync creates a new post.
+pub fn create_post_config(manifest: &Manifest) -> anyhow::Result<String> {
+    let privacy = match manifest.privacy {
+        PostPrivacy::Public => "public",
+        PostPrivacy::Hidden => "hidden",
+        PostPrivacy::Secret => "secret",
+    };
+
+    let mut post_table = Table::new();
+    post_table.insert("title", toml_edit::value(manifest.title.as_str()));
+    post_table.insert("privacy", toml_edit::value(privacy));
+    post_table.insert("nsfw", toml_edit::value(manifest.nsfw));
+
+    let mut files_array = ArrayOfTables::new();
+    for file in manifest.files.iter() {
+        let mut file_table = Table::new();
+        file_table.insert("path", toml_edit::value(file.name.as_str()));
+        if !file.description.is_empty() {
+            file_table.insert("description", toml_edit::value(file.description.as_str()));
+        }
+        files_array.push(file_table);
+    }
+    post_table.insert("files", Item::ArrayOfTables(files_array));
+
+    let mut document = DocumentMut::new();
+    document.insert("post", Item::Table(post_table));
+    let config = document.to_string();
+
+    // Make sure the config can be synced.
+    Config::new(&config).context("imported config is invalid")?;
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bundle::TarWriter;
+    use crate::config::PostConfigPrivacy;
+    use crate::test::TestDir;
+
+    #[test]
+    fn unpack_bundle_works() {
+        let dir = TestDir::new("unpack-bundle-works");
+        let write_bundle = |name: &str, manifest: &str, files: &[(&str, &str)]| {
+            let mut writer = TarWriter::new(Vec::new());
+            writer
+                .append("manifest.json", manifest.as_bytes())
+                .expect("failed to append manifest");
+            for (path, data) in files {
+                writer
+                    .append(path, data.as_bytes())
+                    .expect("failed to append file");
+            }
+            let archive_path = dir.path.join(name);
+            std::fs::write(&archive_path, writer.finish().unwrap()).expect("failed to write");
+            archive_path
+        };
+        let manifest_raw = format!(
+            "{{\"version\":1,\"title\":\"Trip\",\"privacy\":\"secret\",\"nsfw\":false,\"id\":\"abc\",\"files\":[{{\"name\":\"a.png\",\"description\":\"first\",\"sha256\":\"{}\",\"id\":\"f1\"}},{{\"name\":\"b.png\",\"description\":\"\",\"sha256\":\"{}\",\"id\":null}}]}}",
+            crate::hash_bytes(b"a"),
+            crate::hash_bytes(b"b"),
+        );
+
+        let archive_path = write_bundle(
+            "post.tar",
+            &manifest_raw,
+            &[("files/a.png", "a"), ("files/b.png", "b")],
+        );
+        let output = dir.path.join("restored");
+        let manifest = unpack_bundle(&archive_path, &output).expect("failed to unpack bundle");
+        assert!(std::fs::read_to_string(output.join("a.png")).unwrap() == "a");
+        assert!(std::fs::read_to_string(output.join("b.png")).unwrap() == "b");
+
+        let config = create_post_config(&manifest).expect("failed to create config");
+        let mut config = Config::new(&config).expect("failed to parse config");
+        let post_config = config.post_mut();
+        assert!(post_config.id().is_none());
+        assert!(post_config.title() == Some("Trip"));
+        assert!(post_config.privacy() == Some(PostConfigPrivacy::Secret));
+        let files: Vec<_> = post_config
+            .files()
+            .iter()
+            .map(|file| {
+                (
+                    file.path().map(String::from),
+                    file.description().map(String::from),
+                )
+            })
+            .collect();
+        assert!(
+            files
+                == [
+                    (Some(String::from("a.png")), Some(String::from("first"))),
+                    (Some(String::from("b.png")), None)
+                ]
+        );
+
+        // Existing files are never overwritten.
+        let error =
+            unpack_bundle(&archive_path, &output).expect_err("existing files were overwritten");
+        assert!(format!("{error:#}").contains("a.png"), "{error:#}");
+
+        let archive_path = write_bundle("corrupt.tar", &manifest_raw, &[("files/a.png", "b")]);
+        let error = unpack_bundle(&archive_path, &dir.path.join("corrupt"))
+            .expect_err("corrupt file was not detected");
+        assert!(format!("{error:#}").contains("does not match"), "{error:#}");
+
+        // Failed imports remove what they unpacked, so they can be retried.
+        let archive_path = write_bundle("partial.tar", &manifest_raw, &[("files/a.png", "a")]);
+        let partial_output = dir.path.join("partial");
+        let error = unpack_bundle(&archive_path, &partial_output)
+            .expect_err("missing file was not detected");
+        assert!(format!("{error:#}").contains("missing"), "{error:#}");
+        assert!(!partial_output.exists());
+        assert!(std::fs::read_to_string(output.join("a.png")).unwrap() == "a");
+
+        // Files that imgchest-sync keeps in the post folder cannot be unpacked.
+        for name in [
+            "IMGCHEST-SYNC.TOML",
+            ".imgchest-sync-cache.toml",
+            ".imgchest-sync-cache.journal.toml",
+            ".Imgchest-Sync-Cache.hashes.toml",
+        ] {
+            let manifest_raw = format!(
+                "{{\"version\":1,\"title\":\"Trip\",\"privacy\":\"secret\",\"nsfw\":false,\"id\":\"abc\",\"files\":[{{\"name\":\"{name}\",\"description\":\"\",\"sha256\":\"{}\",\"id\":null}}]}}",
+                crate::hash_bytes(b"a"),
+            );
+            let archive_path = write_bundle("reserved.tar", &manifest_raw, &[]);
+            let error = unpack_bundle(&archive_path, &dir.path.join("reserved"))
+                .expect_err("reserved file name was not rejected");
+            assert!(
+                format!("{error:#}").contains("not a plain file name"),
+                "{error:#}"
+            );
+        }
+
+        let archive_path = write_bundle("future.tar", "{\"version\":2,\"new\":true}", &[]);
+        let error = unpack_bundle(&archive_path, &dir.path.join("future"))
+            .expect_err("newer bundle version was not rejected");
+        assert!(format!("{error:#}").contains("version 2"), "{error:#}");
+    }
+}
@@ -0,0 +1,147 @@
+use crate::api::Api;
+use crate::api::PostUpdate;
+use crate::config::Config;
+use crate::config::PostConfigPrivacy;
+use crate::post::PostPrivacy;
+use crate::post_dirs::PostDirs;
+use crate::Cache;
+use crate::ConfigTarget;
+use crate::Stats;
+use crate::SyncContext;
+use crate::Timer;
+use anyhow::Context;
+use camino::Utf8Path;
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(
+    subcommand,
+    name = "publish",
+    description = "make every managed post that is public in its config, but still secret online, public. This uses the same input and token options as syncing"
+)]
+pub struct Options {}
+
+/// Publish the posts of each post directory.
+pub(crate) async fn exec<A>(ctx: &SyncContext<A>) -> anyhow::Result<()>
+where
+    A: Api,
+{
+    let mut post_dirs = PostDirs::new(ctx).await?;
+    while let Some(mut post_dir) = post_dirs.next().await? {
+        post_dirs.delay().await;
+
+        println!("publishing \"{}\"", post_dir.label);
+
+        let stats = publish_post(
+            ctx,
+            &mut post_dir.config,
+            post_dir.config_path.as_deref().into(),
+            post_dir.cache_path.as_deref(),
+        )
+        .await?;
+        post_dirs.add_stats(stats);
+    }
+    post_dirs.finish()?;
+    post_dirs.print_total_stats();
+
+    Ok(())
+}
+
+/// Make the posts of a config public, if the config is public but the remote posts are still secret.
+///
+/// This also clears the draft mark of the post.
+pub(crate) async fn publish_post<A>(
+    ctx: &SyncContext<A>,
+    config: &mut Config,
+    config_target: ConfigTarget<'_>,
+    cache_path: Option<&Utf8Path>,
+) -> anyhow::Result<Stats>
+where
+    A: Api,
+{
+    let options = &ctx.options;
+    let mut stats = Stats::new();
+
+    let mut post_config = config.post_mut();
+    let privacy = post_config.privacy().unwrap_or(ctx.default_privacy);
+    if privacy != PostConfigPrivacy::Public {
+        println!("  post is not public in its config, skipping");
+        return Ok(stats);
+    }
+
+    let album = post_config.album();
+    let ids: Vec<String> = if album {
+        post_config.ids().into_iter().map(String::from).collect()
+    } else {
+        post_config.id().map(String::from).into_iter().collect()
+    };
+    if ids.is_empty() {
+        println!("  post has no id, skipping");
+        return Ok(stats);
+    }
+
+    let client = crate::get_post_client(ctx, &post_config)?;
+    let client = &*client;
+    for (i, id) in ids.iter().enumerate() {
+        let timer = Timer::start();
+        let remote_post = client
+            .get_post(id)
+            .await
+            .context("failed to get remote post")?;
+        timer.stop(&mut stats.download_time);
+        stats.api_calls += 1;
+
+        if remote_post.privacy != PostPrivacy::Secret {
+            println!("  post \"{id}\" is not secret, skipping");
+            continue;
+        }
+
+        if options.dry_run {
+            println!("  would publish post \"{id}\"");
+            continue;
+        }
+
+        println!("  publishing post \"{id}\"");
+        let update = PostUpdate {
+            privacy: Some(PostPrivacy::Public),
+            ..PostUpdate::default()
+        };
+        let timer = Timer::start();
+        client
+            .update_post(id, update)
+            .await
+            .context("failed to publish post")?;
+        timer.stop(&mut stats.upload_time);
+        stats.api_calls += 1;
+
+        // Keep the cache in sync, so the next sync does not diff the privacy.
+        if let Some(cache_path) = cache_path {
+            let cache_path = match album {
+                true => crate::get_album_cache_path(cache_path, i),
+                false => cache_path.into(),
+            };
+            set_cache_privacy(&cache_path, PostPrivacy::Public).await?;
+        }
+    }
+
+    if post_config.draft() && !options.dry_run {
+        post_config.set_draft(false);
+        crate::write_config(config, config_target).await?;
+    }
+
+    Ok(stats)
+}
+
+/// Set the privacy of the post of a cache file, if it exists.
+async fn set_cache_privacy(cache_path: &Utf8Path, privacy: PostPrivacy) -> anyhow::Result<()> {
+    let cache_raw = match crate::util::try_read_to_string(cache_path)
+        .await
+        .context("failed to read cache file")?
+    {
+        Some(cache_raw) => cache_raw,
+        None => return Ok(()),
+    };
+    let mut cache: Cache = toml::from_str(&cache_raw).context("failed to parse cache file")?;
+    cache.post.privacy = privacy;
+
+    crate::write_cache(cache_path, &cache).await
+}
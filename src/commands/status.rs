@@ -0,0 +1,223 @@
+use crate::api::Api;
+use crate::config::Config;
+use crate::hash_cache::get_hash_cache_path;
+use crate::hash_cache::HashCache;
+use crate::post::Post;
+use crate::post::PostDiff;
+use crate::post_dirs::PostDirs;
+use crate::Cache;
+use crate::Stats;
+use crate::SyncContext;
+use crate::Timer;
+use crate::DIFF_VERSION;
+use anyhow::Context;
+use camino::Utf8Path;
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(
+    subcommand,
+    name = "status",
+    description = "report what a sync would change in every managed post, using only the cache and the local files. Nothing is written and no requests are made. This uses the same input options as syncing"
+)]
+pub struct Options {}
+
+/// Report what syncing each post directory would change, listing the posts with changes.
+pub(crate) async fn exec<A>(ctx: &SyncContext<A>) -> anyhow::Result<()>
+where
+    A: Api,
+{
+    let mut post_dirs = PostDirs::new(ctx).await?;
+    let mut changed = Vec::new();
+    while let Some(mut post_dir) = post_dirs.next().await? {
+        post_dirs.delay().await;
+
+        println!("status of \"{}\"", post_dir.label);
+
+        let (has_changes, stats) = status_post(
+            ctx,
+            &post_dir.path,
+            &mut post_dir.config,
+            post_dir.cache_path.as_deref(),
+        )
+        .await?;
+        if has_changes {
+            changed.push(post_dir.label);
+        }
+        post_dirs.add_stats(stats);
+    }
+    post_dirs.finish()?;
+
+    println!("{} posts have changes to sync", changed.len());
+    for name in changed.iter() {
+        println!("  {name}");
+    }
+
+    post_dirs.print_total_stats();
+
+    Ok(())
+}
+
+/// Report what syncing a post would change, using only its cache and the local files.
+///
+/// The cached post holds the title, privacy, and nsfw flag from the last sync or cache refresh,
+/// so metadata changes are reported without any requests.
+/// Hashes are reused from the hash cache next to the cache path, if given, but nothing is written.
+/// Each post of an album is checked against its own cache.
+/// Returns whether syncing would change the post.
+pub(crate) async fn status_post<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    config: &mut Config,
+    cache_path: Option<&Utf8Path>,
+) -> anyhow::Result<(bool, Stats)>
+where
+    A: Api,
+{
+    let post_config = config.post_mut();
+    if !post_config.album() {
+        return status_single_post(ctx, dir_path, config, cache_path).await;
+    }
+
+    let mut stats = Stats::new();
+    let num_ids = post_config.ids().len();
+    let chunks = crate::get_album_chunks(&ctx.options, dir_path, &post_config)?;
+    let num_chunks = chunks.len();
+    let mut has_changes = false;
+    for (i, mut chunk_config) in chunks.into_iter().enumerate() {
+        println!("  album post {}/{num_chunks}:", i + 1);
+
+        let chunk_cache_path =
+            cache_path.map(|cache_path| crate::get_album_cache_path(cache_path, i));
+        let (chunk_has_changes, chunk_stats) = status_single_post(
+            ctx,
+            dir_path,
+            &mut chunk_config,
+            chunk_cache_path.as_deref(),
+        )
+        .await?;
+        has_changes |= chunk_has_changes;
+        stats += chunk_stats;
+    }
+    if num_ids > num_chunks {
+        println!(
+            "  album only needs {num_chunks} posts, so {} posts are unused",
+            num_ids - num_chunks
+        );
+    }
+
+    Ok((has_changes, stats))
+}
+
+/// Report what syncing a single post would change, which is not an album.
+async fn status_single_post<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    config: &mut Config,
+    cache_path: Option<&Utf8Path>,
+) -> anyhow::Result<(bool, Stats)>
+where
+    A: Api,
+{
+    let options = &ctx.options;
+    let mut stats = Stats::new();
+
+    let post_config = config.post_mut();
+
+    let cache = match cache_path {
+        Some(cache_path) if !options.no_read_cache => crate::util::try_read_to_string(cache_path)
+            .await
+            .context("failed to read cache file")?
+            .and_then(|cache_raw| toml::from_str::<Cache>(&cache_raw).ok())
+            .filter(|cache| cache.diff_version == DIFF_VERSION),
+        _ => None,
+    };
+    let mut hash_cache = match cache_path {
+        Some(cache_path) if !options.no_read_cache => {
+            HashCache::load(&get_hash_cache_path(cache_path)).await?
+        }
+        _ => None,
+    };
+    let timer = Timer::start();
+    let (mut new_post, _, unreadable) = crate::create_post_from_post_config(
+        dir_path,
+        &post_config,
+        options,
+        ctx.default_privacy,
+        ctx.nsfw_marker,
+        &ctx.hasher,
+        hash_cache.as_mut(),
+    )
+    .await?;
+    timer.stop(&mut stats.hash_time);
+
+    if post_config.id().is_none() {
+        println!(
+            "  post has no id, a post with {} files would be created",
+            new_post.files.len()
+        );
+        return Ok((true, stats));
+    }
+    let old_post = match cache {
+        Some(cache) => cache.post,
+        None => {
+            println!("  post has no usable cache. Sync it or use --refresh-cache first");
+            return Ok((false, stats));
+        }
+    };
+
+    let diffs = crate::diff_local_post(
+        options,
+        &old_post,
+        &mut new_post,
+        &unreadable,
+        post_config.draft(),
+    )?;
+    let changes = describe_status(&old_post, &new_post, &diffs);
+    if changes.is_empty() {
+        println!("  up to date");
+    }
+    for change in changes.iter() {
+        println!("  {change}");
+    }
+
+    Ok((!changes.is_empty(), stats))
+}
+
+/// Describe the changes that syncing would make to a post, one line per change.
+pub fn describe_status(old_post: &Post, new_post: &Post, diffs: &[PostDiff]) -> Vec<String> {
+    let mut changes = Vec::new();
+    let mut num_added = 0;
+    let mut num_removed = 0;
+    let mut num_moved = 0;
+    let mut num_edited = 0;
+    for diff in diffs {
+        match diff {
+            PostDiff::EditTitle { title } => changes.push(format!(
+                "title will change from \"{}\" to \"{title}\"",
+                old_post.title
+            )),
+            PostDiff::EditPrivacy { privacy } => changes.push(format!(
+                "privacy will change from {:?} to {privacy:?}",
+                old_post.privacy
+            )),
+            PostDiff::EditNsfw { nsfw } => {
+                changes.push(format!("nsfw will change from {} to {nsfw}", old_post.nsfw))
+            }
+            PostDiff::EditFileDescription { .. } => num_edited += 1,
+            PostDiff::RetainFile { .. } => {}
+            PostDiff::AddFile { .. } => num_added += 1,
+            PostDiff::RemoveFile { .. } => num_removed += 1,
+            PostDiff::MoveFile { .. } => num_moved += 1,
+        }
+    }
+
+    if num_added + num_removed + num_moved + num_edited > 0 {
+        changes.push(format!(
+            "files will change from {} to {}: {num_added} added, {num_removed} removed, {num_moved} moved, {num_edited} descriptions edited",
+            old_post.files.len(),
+            new_post.files.len()
+        ));
+    }
+
+    changes
+}
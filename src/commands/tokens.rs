@@ -0,0 +1,130 @@
+use crate::commands::dedup::ReportFormat;
+use crate::TokenSource;
+use sha2::Digest;
+use sha2::Sha256;
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(
+    subcommand,
+    name = "tokens",
+    description = "show which tokens would be used, and where they come from, without printing them. This uses the same token options as syncing, and lists the keyring credentials of the post configs of --input or --config"
+)]
+pub struct Options {
+    #[argh(
+        option,
+        long = "format",
+        default = "ReportFormat::Text",
+        description = "the output format, text or json. Defaults to text"
+    )]
+    pub format: ReportFormat,
+}
+
+/// A token, as printed by the tokens subcommand.
+#[derive(Debug, serde::Serialize)]
+struct TokenInfo<'a> {
+    /// The account name of a named token, or `None` for the default token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    account: Option<&'a str>,
+
+    /// The keyring credential of a credential token, as "<service>:<user>".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    credential: Option<&'a str>,
+
+    /// Where the token came from.
+    source: &'a str,
+
+    /// The start of the sha256 hash of the token, to tell tokens apart without revealing them.
+    ///
+    /// This is `None` if the token could not be read.
+    fingerprint: Option<String>,
+
+    /// Why the token could not be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The tokens, as printed by the tokens subcommand.
+#[derive(Debug, serde::Serialize)]
+struct Report<'a> {
+    /// The tokens.
+    tokens: Vec<TokenInfo<'a>>,
+}
+
+pub fn exec(
+    options: &Options,
+    default_token: Option<(String, TokenSource)>,
+    named_tokens: &[(String, String)],
+    credential_tokens: &[(String, anyhow::Result<String>)],
+) -> anyhow::Result<()> {
+    let mut tokens = Vec::with_capacity(named_tokens.len() + credential_tokens.len() + 1);
+    if let Some((token, source)) = default_token.as_ref() {
+        tokens.push(TokenInfo {
+            account: None,
+            credential: None,
+            source: source.as_str(),
+            fingerprint: Some(fingerprint(token)),
+            error: None,
+        });
+    }
+    for (account, token) in named_tokens.iter() {
+        tokens.push(TokenInfo {
+            account: Some(account),
+            credential: None,
+            source: "user config",
+            fingerprint: Some(fingerprint(token)),
+            error: None,
+        });
+    }
+    for (credential, token) in credential_tokens.iter() {
+        let (fingerprint, error) = match token {
+            Ok(token) => (Some(fingerprint(token)), None),
+            Err(error) => (None, Some(format!("{error:#}"))),
+        };
+        tokens.push(TokenInfo {
+            account: None,
+            credential: Some(credential),
+            source: "keyring",
+            fingerprint,
+            error,
+        });
+    }
+
+    match options.format {
+        ReportFormat::Text => {
+            if tokens.is_empty() {
+                println!("no tokens are configured");
+            }
+            for token in tokens.iter() {
+                let name = match (token.account, token.credential) {
+                    (Some(account), _) => format!("account \"{account}\""),
+                    (None, Some(credential)) => format!("credential \"{credential}\""),
+                    (None, None) => String::from("default token"),
+                };
+                match (token.fingerprint.as_deref(), token.error.as_deref()) {
+                    (Some(fingerprint), _) => {
+                        println!("{name}: from {}, fingerprint {fingerprint}", token.source)
+                    }
+                    (None, error) => println!(
+                        "{name}: from {}, unreadable: {}",
+                        token.source,
+                        error.unwrap_or_default()
+                    ),
+                }
+            }
+        }
+        ReportFormat::Json => {
+            let report = Report { tokens };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Get a short fingerprint of a token.
+pub fn fingerprint(token: &str) -> String {
+    let hash = Sha256::digest(token.as_bytes());
+    let mut fingerprint = base16ct::lower::encode_string(&hash);
+    fingerprint.truncate(12);
+    fingerprint
+}
@@ -1,15 +1,35 @@
-use anyhow::bail;
-use anyhow::ensure;
-use anyhow::Context;
+use crate::error::SyncError;
+use reqwest::header::HeaderMap;
+use reqwest::header::HeaderName;
+use reqwest::header::HeaderValue;
+use reqwest::header::USER_AGENT;
+use std::path::Path;
+use std::path::PathBuf;
 use toml_edit::Array;
 use toml_edit::ArrayOfTables;
 use toml_edit::DocumentMut;
 use toml_edit::Item;
+use toml_edit::Table;
 use toml_edit::TableLike;
 use toml_edit::Value;
 
 const POST_TABLE: &str = "post";
 
+/// Keys of the post table that are not copied into the posts of an album.
+const ALBUM_ONLY_KEYS: &[&str] = &[
+    "id",
+    "ids",
+    "title",
+    "album",
+    "album_chunk_size",
+    "deleted",
+    "files",
+    "include",
+];
+
+/// The only keys allowed in the post table of an included config.
+const INCLUDED_KEYS: &[&str] = &["files", "include"];
+
 enum ArrayOfTablesLike<'a> {
     Array(&'a Array),
     ArrayOfTables(&'a ArrayOfTables),
@@ -41,95 +61,424 @@ impl<'a> ArrayOfTablesLike<'a> {
 #[derive(Debug)]
 pub struct Config {
     document: DocumentMut,
+
+    /// The index of the first file from an included config, if includes were loaded.
+    ///
+    /// Included files are left out when the config is written.
+    included_start: Option<usize>,
 }
 
 impl Config {
     /// Make a config from a string.
-    pub fn new(input: &str) -> anyhow::Result<Self> {
-        let document: DocumentMut = input.parse()?;
+    ///
+    /// Included configs are not loaded until [`Config::load_includes`] is called.
+    pub fn new(input: &str) -> Result<Self, SyncError> {
+        let mut document: DocumentMut = input.parse()?;
+
+        // A config with includes may get all of its files from them.
+        if let Some(post_table) = document
+            .get_mut(POST_TABLE)
+            .and_then(|item| item.as_table_like_mut())
+        {
+            if post_table.contains_key("include") && !post_table.contains_key("files") {
+                post_table.insert("files", Item::ArrayOfTables(ArrayOfTables::new()));
+            }
+        }
+
         let post_table = document
             .as_table()
             .get(POST_TABLE)
-            .context("missing \"post\" table")?
+            .ok_or_else(|| SyncError::invalid_config("missing \"post\" table"))?
             .as_table_like()
-            .context("\"post\" key does not refer to a table")?;
+            .ok_or_else(|| SyncError::invalid_config("\"post\" key does not refer to a table"))?;
         let _id = post_table
             .get("id")
             .map(|item| {
-                item.as_str()
-                    .context("\"id\" field of post config is not a string")
+                item.as_str().ok_or_else(|| {
+                    SyncError::invalid_config("\"id\" field of post config is not a string")
+                })
             })
             .transpose()?;
         let _title = post_table
             .get("title")
             .map(|item| {
-                item.as_str()
-                    .context("\"title\" field of post config is not a string")
+                item.as_str().ok_or_else(|| {
+                    SyncError::invalid_config("\"title\" field of post config is not a string")
+                })
             })
             .transpose()?;
         let _privacy = post_table
             .get("privacy")
             .map(|item| {
                 item.as_str()
-                    .context("\"privacy\" field of post config is not a string")?
+                    .ok_or_else(|| {
+                        SyncError::invalid_config(
+                            "\"privacy\" field of post config is not a string",
+                        )
+                    })?
                     .parse::<PostConfigPrivacy>()
-                    .context("failed to parse post privacy")
+            })
+            .transpose()?;
+        let _description_format = post_table
+            .get("description_format")
+            .map(|item| {
+                item.as_str()
+                    .ok_or_else(|| {
+                        SyncError::invalid_config(
+                            "\"description_format\" field of post config is not a string",
+                        )
+                    })?
+                    .parse::<PostConfigDescriptionFormat>()
             })
             .transpose()?;
         let _nsfw = post_table
             .get("nsfw")
             .map(|item| {
-                item.as_bool()
-                    .context("\"nsfw\" field of post config is not a bool")
+                item.as_bool().ok_or_else(|| {
+                    SyncError::invalid_config("\"nsfw\" field of post config is not a bool")
+                })
+            })
+            .transpose()?;
+        let account = post_table
+            .get("account")
+            .map(|item| {
+                item.as_str().ok_or_else(|| {
+                    SyncError::invalid_config("\"account\" field of post config is not a string")
+                })
+            })
+            .transpose()?;
+        let credential = post_table
+            .get("credential")
+            .map(|item| {
+                item.as_str()
+                    .filter(|credential| {
+                        credential
+                            .split_once(':')
+                            .is_some_and(|(service, user)| !service.is_empty() && !user.is_empty())
+                    })
+                    .ok_or_else(|| {
+                        SyncError::invalid_config(
+                            "\"credential\" field of post config is not a string of the form \"<service>:<user>\"",
+                        )
+                    })
+            })
+            .transpose()?;
+        if account.is_some() && credential.is_some() {
+            return Err(SyncError::invalid_config(
+                "\"account\" and \"credential\" fields of post config cannot both be set",
+            ));
+        }
+        let _deleted = post_table
+            .get("deleted")
+            .map(|item| {
+                item.as_bool().ok_or_else(|| {
+                    SyncError::invalid_config("\"deleted\" field of post config is not a bool")
+                })
+            })
+            .transpose()?;
+        let _draft = post_table
+            .get("draft")
+            .map(|item| {
+                item.as_bool().ok_or_else(|| {
+                    SyncError::invalid_config("\"draft\" field of post config is not a bool")
+                })
+            })
+            .transpose()?;
+        let _enabled = post_table
+            .get("enabled")
+            .map(|item| {
+                item.as_bool().ok_or_else(|| {
+                    SyncError::invalid_config("\"enabled\" field of post config is not a bool")
+                })
+            })
+            .transpose()?;
+        let _extensions = post_table
+            .get("extensions")
+            .map(|item| {
+                let array = item.as_array().ok_or_else(|| {
+                    SyncError::invalid_config("\"extensions\" field of post config is not an array")
+                })?;
+                if !array.iter().all(|value| value.is_str()) {
+                    return Err(SyncError::invalid_config(
+                        "\"extensions\" field of post config must be an array of strings",
+                    ));
+                }
+
+                Ok(array)
+            })
+            .transpose()?;
+        let album = post_table
+            .get("album")
+            .map(|item| {
+                item.as_bool().ok_or_else(|| {
+                    SyncError::invalid_config("\"album\" field of post config is not a bool")
+                })
+            })
+            .transpose()?
+            .unwrap_or(false);
+        let _ids = post_table
+            .get("ids")
+            .map(|item| {
+                let array = item.as_array().ok_or_else(|| {
+                    SyncError::invalid_config("\"ids\" field of post config is not an array")
+                })?;
+                if !array.iter().all(|value| value.is_str()) {
+                    return Err(SyncError::invalid_config(
+                        "\"ids\" field of post config must be an array of strings",
+                    ));
+                }
+
+                Ok(array)
             })
             .transpose()?;
+        let _album_chunk_size = post_table
+            .get("album_chunk_size")
+            .map(|item| {
+                item.as_integer()
+                    .filter(|album_chunk_size| *album_chunk_size > 0)
+                    .ok_or_else(|| {
+                        SyncError::invalid_config(
+                            "\"album_chunk_size\" field of post config is not a positive integer",
+                        )
+                    })
+            })
+            .transpose()?;
+        let include = post_table
+            .get("include")
+            .map(|item| {
+                let array = item.as_array().ok_or_else(|| {
+                    SyncError::invalid_config("\"include\" field of post config is not an array")
+                })?;
+                if !array.iter().all(|value| value.is_str()) {
+                    return Err(SyncError::invalid_config(
+                        "\"include\" field of post config must be an array of strings",
+                    ));
+                }
+
+                Ok(array)
+            })
+            .transpose()?;
+        if album && post_table.contains_key("id") {
+            return Err(SyncError::invalid_config(
+                "albums use the \"ids\" field of post config instead of \"id\"",
+            ));
+        }
         let files = {
             let item = post_table
                 .get("files")
-                .context("missing \"files\" key of post config")?;
+                .ok_or_else(|| SyncError::invalid_config("missing \"files\" key of post config"))?;
 
             match item {
                 Item::Value(Value::Array(array)) => {
-                    for value in array.iter() {
-                        ensure!(
-                            value.is_inline_table(),
-                            "\"files\" field of post config must be an array of tables"
-                        );
+                    if !array.iter().all(|value| value.is_inline_table()) {
+                        return Err(SyncError::invalid_config(
+                            "\"files\" field of post config must be an array of tables",
+                        ));
                     }
 
                     ArrayOfTablesLike::Array(array)
                 }
                 Item::ArrayOfTables(array) => ArrayOfTablesLike::ArrayOfTables(array),
                 _ => {
-                    bail!("\"files\" key of post config is not an array of tables");
+                    return Err(SyncError::invalid_config(
+                        "\"files\" key of post config is not an array of tables",
+                    ));
                 }
             }
         };
-        ensure!(
-            files.len() != 0,
-            "\"files\" array of post config must have at least one entry"
-        );
+        // Files may come from included configs, which are checked once they are loaded.
+        if files.len() == 0 && include.is_none() {
+            return Err(SyncError::invalid_config(
+                "\"files\" array of post config must have at least one entry",
+            ));
+        }
         for (i, table) in files.iter().enumerate() {
             let file_n = i + 1;
 
-            let _path = table
+            let path = table
                 .get("path")
-                .with_context(|| format!("file {file_n} of post config missing \"path\""))?
-                .as_str()
-                .with_context(|| {
-                    format!("file {file_n} of post config \"path\" key is not a string")
-                });
+                .map(|item| {
+                    item.as_str().ok_or_else(|| {
+                        SyncError::invalid_config(format!(
+                            "file {file_n} of post config \"path\" key is not a string"
+                        ))
+                    })
+                })
+                .transpose()?;
+            let command = table
+                .get("command")
+                .map(|item| {
+                    let array = item.as_array().ok_or_else(|| {
+                        SyncError::invalid_config(format!(
+                            "file {file_n} of post config \"command\" key is not an array"
+                        ))
+                    })?;
+                    if array.is_empty() || !array.iter().all(|value| value.is_str()) {
+                        return Err(SyncError::invalid_config(format!(
+                            "file {file_n} of post config \"command\" key must be a non-empty array of strings"
+                        )));
+                    }
+
+                    Ok(array)
+                })
+                .transpose()?;
+            let _id = table
+                .get("id")
+                .map(|item| {
+                    item.as_str().ok_or_else(|| {
+                        SyncError::invalid_config(format!(
+                            "file {file_n} of post config \"id\" key is not a string"
+                        ))
+                    })
+                })
+                .transpose()?;
+            let name = table
+                .get("name")
+                .map(|item| {
+                    item.as_str().ok_or_else(|| {
+                        SyncError::invalid_config(format!(
+                            "file {file_n} of post config \"name\" key is not a string"
+                        ))
+                    })
+                })
+                .transpose()?;
+            let _disabled = table
+                .get("disabled")
+                .map(|item| {
+                    item.as_bool().ok_or_else(|| {
+                        SyncError::invalid_config(format!(
+                            "file {file_n} of post config \"disabled\" key is not a bool"
+                        ))
+                    })
+                })
+                .transpose()?;
+            match (path, command, name) {
+                (Some(_path), None, None) => {}
+                (None, Some(_command), Some(_name)) => {}
+                (None, None, _) => {
+                    return Err(SyncError::invalid_config(format!(
+                        "file {file_n} of post config missing \"path\""
+                    )));
+                }
+                (Some(_path), Some(_command), _) => {
+                    return Err(SyncError::invalid_config(format!(
+                        "file {file_n} of post config cannot have both a \"path\" and a \"command\""
+                    )));
+                }
+                (None, Some(_command), None) => {
+                    return Err(SyncError::invalid_config(format!(
+                        "file {file_n} of post config has a \"command\" but is missing \"name\""
+                    )));
+                }
+                (Some(_path), None, Some(_name)) => {
+                    return Err(SyncError::invalid_config(format!(
+                        "file {file_n} of post config \"name\" key is only valid with a \"command\""
+                    )));
+                }
+            }
+            let _content_type = table
+                .get("content_type")
+                .map(|item| {
+                    item.as_str()
+                        .ok_or_else(|| {
+                            SyncError::invalid_config(format!(
+                                "file {file_n} of post config \"content_type\" key is not a string"
+                            ))
+                        })?
+                        .parse::<PostConfigContentType>()
+                })
+                .transpose()?;
             let _description = table
                 .get("description")
                 .map(|item| {
-                    item.as_str().with_context(|| {
-                        format!("file {file_n} of post config \"description\" key is not a string")
+                    item.as_str().ok_or_else(|| {
+                        SyncError::invalid_config(format!(
+                            "file {file_n} of post config \"description\" key is not a string"
+                        ))
                     })
                 })
                 .transpose()?;
         }
 
-        Ok(Self { document })
+        Ok(Self {
+            document,
+            included_start: None,
+        })
+    }
+
+    /// Make a config from a post table that is already valid.
+    fn from_post_table(post_table: Table) -> Self {
+        let mut document = DocumentMut::new();
+        document.insert(POST_TABLE, Item::Table(post_table));
+
+        Self {
+            document,
+            included_start: None,
+        }
+    }
+
+    /// Load the configs listed in the "include" key, appending their files in order.
+    ///
+    /// Included configs may only have "files" and "include" keys in their post table.
+    /// Their files come after the files of the including config, followed by the files of their own includes.
+    /// Include paths are resolved relative to the directory of the including config,
+    /// which is the given directory for this config.
+    /// File paths in included configs are not rewritten, so they stay relative to the post folder.
+    pub fn load_includes(&mut self, dir_path: &Path) -> Result<(), SyncError> {
+        let includes: Vec<PathBuf> = self
+            .post_mut()
+            .includes()
+            .into_iter()
+            .map(|include| dir_path.join(include))
+            .collect();
+        if includes.is_empty() {
+            return Ok(());
+        }
+
+        let mut tables = Vec::new();
+        let mut stack = Vec::new();
+        for include in includes.iter() {
+            collect_included_files(include, &mut stack, &mut tables)?;
+        }
+
+        let post_table = self
+            .document
+            .get_mut(POST_TABLE)
+            .and_then(|item| item.as_table_like_mut())
+            .expect("missing \"post\" table");
+        let item = post_table
+            .get_mut("files")
+            .expect("missing \"files\" key of post config");
+        let included_start = match item {
+            Item::Value(Value::Array(array)) => {
+                let start = array.len();
+                for table in tables {
+                    array.push(table.into_inline_table());
+                }
+                if array.is_empty() {
+                    return Err(SyncError::invalid_config(
+                        "\"files\" array of post config must have at least one entry",
+                    ));
+                }
+                start
+            }
+            Item::ArrayOfTables(array) => {
+                let start = array.len();
+                for table in tables {
+                    array.push(table);
+                }
+                if array.is_empty() {
+                    return Err(SyncError::invalid_config(
+                        "\"files\" array of post config must have at least one entry",
+                    ));
+                }
+                start
+            }
+            _ => panic!("\"files\" key of post config is not an array of tables"),
+        };
+        self.included_start = Some(included_start);
+
+        Ok(())
     }
 
     /// Get the post config mutably.
@@ -148,10 +497,99 @@ impl Config {
 
 impl std::fmt::Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.document.fmt(f)
+        let included_start = match self.included_start {
+            Some(included_start) => included_start,
+            None => return self.document.fmt(f),
+        };
+
+        // Included files belong to their own configs.
+        let mut document = self.document.clone();
+        let item = document
+            .get_mut(POST_TABLE)
+            .and_then(|item| item.as_table_like_mut())
+            .and_then(|post_table| post_table.get_mut("files"));
+        match item {
+            Some(Item::Value(Value::Array(array))) => {
+                while array.len() > included_start {
+                    array.remove(array.len() - 1);
+                }
+            }
+            Some(Item::ArrayOfTables(array)) => {
+                while array.len() > included_start {
+                    array.remove(array.len() - 1);
+                }
+            }
+            _ => {}
+        }
+
+        document.fmt(f)
     }
 }
 
+/// Read an included config, adding its files and then the files of its own includes.
+///
+/// The stack holds the configs being included, to detect cycles.
+fn collect_included_files(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    tables: &mut Vec<Table>,
+) -> Result<(), SyncError> {
+    let display_path = path.display();
+    let path = std::fs::canonicalize(path).map_err(|error| {
+        SyncError::invalid_config(format!(
+            "failed to read included config \"{display_path}\": {error}"
+        ))
+    })?;
+    if stack.contains(&path) {
+        return Err(SyncError::invalid_config(format!(
+            "included config \"{display_path}\" includes itself"
+        )));
+    }
+
+    let input = std::fs::read_to_string(&path).map_err(|error| {
+        SyncError::invalid_config(format!(
+            "failed to read included config \"{display_path}\": {error}"
+        ))
+    })?;
+    let mut config = Config::new(&input).map_err(|error| {
+        SyncError::invalid_config(format!(
+            "included config \"{display_path}\" is invalid: {error}"
+        ))
+    })?;
+    let post_config = config.post_mut();
+    if let Some((key, _item)) = post_config
+        .table
+        .iter()
+        .find(|(key, _item)| !INCLUDED_KEYS.contains(key))
+    {
+        return Err(SyncError::invalid_config(format!(
+            "included config \"{display_path}\" has a \"{key}\" key, but may only have \"files\" and \"include\" keys"
+        )));
+    }
+
+    for file in post_config.files().iter() {
+        let mut table = Table::new();
+        for (key, item) in file.table.iter() {
+            table.insert(key, item.clone());
+        }
+        tables.push(table);
+    }
+
+    let dir_path = path.parent().unwrap_or(Path::new(""));
+    let includes: Vec<PathBuf> = post_config
+        .includes()
+        .into_iter()
+        .map(|include| dir_path.join(include))
+        .collect();
+    stack.push(path);
+    for include in includes.iter() {
+        collect_included_files(include, stack, tables)?;
+    }
+    stack.pop();
+
+    Ok(())
+}
+
 /// The post config.
 pub struct PostConfig<'a> {
     table: &'a mut dyn TableLike,
@@ -194,6 +632,18 @@ impl PostConfig<'_> {
         })
     }
 
+    /// Set the title.
+    pub fn set_title(&mut self, title: Option<&str>) {
+        match title {
+            Some(title) => {
+                self.table.insert("title", toml_edit::value(title));
+            }
+            None => {
+                self.table.remove("title");
+            }
+        }
+    }
+
     /// Get the privacy.
     pub fn privacy(&self) -> Option<PostConfigPrivacy> {
         self.table.get("privacy").map(|item| {
@@ -204,6 +654,20 @@ impl PostConfig<'_> {
         })
     }
 
+    /// Set the privacy.
+    pub fn set_privacy(&mut self, privacy: Option<PostConfigPrivacy>) {
+        let privacy = match privacy {
+            Some(privacy) => privacy,
+            None => {
+                self.table.remove("privacy");
+                return;
+            }
+        };
+
+        self.table
+            .insert("privacy", toml_edit::value(privacy.as_str()));
+    }
+
     /// Get the nsfw.
     pub fn nsfw(&self) -> Option<bool> {
         self.table.get("nsfw").map(|item| {
@@ -212,6 +676,226 @@ impl PostConfig<'_> {
         })
     }
 
+    /// Set the nsfw.
+    pub fn set_nsfw(&mut self, nsfw: Option<bool>) {
+        match nsfw {
+            Some(nsfw) => {
+                self.table.insert("nsfw", toml_edit::value(nsfw));
+            }
+            None => {
+                self.table.remove("nsfw");
+            }
+        }
+    }
+
+    /// Get the name of the account token to use.
+    pub fn account(&self) -> Option<&str> {
+        self.table.get("account").map(|item| {
+            item.as_str()
+                .expect("\"account\" field of post config is not a string")
+        })
+    }
+
+    /// Get the keyring credential holding the token to use, as "<service>:<user>".
+    pub fn credential(&self) -> Option<&str> {
+        self.table.get("credential").map(|item| {
+            item.as_str()
+                .expect("\"credential\" field of post config is not a string")
+        })
+    }
+
+    /// Check whether the post is marked as deleted.
+    pub fn deleted(&self) -> bool {
+        self.table.get("deleted").is_some_and(|item| {
+            item.as_bool()
+                .expect("\"deleted\" field of post config is not a bool")
+        })
+    }
+
+    /// Check whether the post is a draft, which was created with --create-as and not yet published.
+    pub fn draft(&self) -> bool {
+        self.table.get("draft").is_some_and(|item| {
+            item.as_bool()
+                .expect("\"draft\" field of post config is not a bool")
+        })
+    }
+
+    /// Check whether the post is enabled.
+    ///
+    /// Disabled posts are skipped entirely. Posts are enabled by default.
+    pub fn enabled(&self) -> bool {
+        self.table.get("enabled").is_none_or(|item| {
+            item.as_bool()
+                .expect("\"enabled\" field of post config is not a bool")
+        })
+    }
+
+    /// Set whether the post is a draft.
+    pub fn set_draft(&mut self, draft: bool) {
+        if draft {
+            self.table.insert("draft", toml_edit::value(true));
+        } else {
+            self.table.remove("draft");
+        }
+    }
+
+    /// Get the file extensions allowed for this post, if specified.
+    pub fn extensions(&self) -> Option<Vec<&str>> {
+        self.table.get("extensions").map(|item| {
+            item.as_array()
+                .expect("\"extensions\" field of post config is not an array")
+                .iter()
+                .map(|value| value.as_str().expect("extension is not a str"))
+                .collect()
+        })
+    }
+
+    /// Check whether this config refers to a post that exists online.
+    ///
+    /// This is true if the post has an id, or if it is an album with any ids.
+    pub fn has_remote_post(&self) -> bool {
+        self.id().is_some() || !self.ids().is_empty()
+    }
+
+    /// Check whether the post is an album, which is split into multiple posts.
+    pub fn album(&self) -> bool {
+        self.table.get("album").is_some_and(|item| {
+            item.as_bool()
+                .expect("\"album\" field of post config is not a bool")
+        })
+    }
+
+    /// Get the max number of files in each post of an album, if specified.
+    pub fn album_chunk_size(&self) -> Option<usize> {
+        self.table.get("album_chunk_size").map(|item| {
+            let album_chunk_size = item
+                .as_integer()
+                .expect("\"album_chunk_size\" field of post config is not an integer");
+            usize::try_from(album_chunk_size).unwrap_or(usize::MAX)
+        })
+    }
+
+    /// Get the ids of the posts of an album.
+    pub fn ids(&self) -> Vec<&str> {
+        self.table
+            .get("ids")
+            .map(|item| {
+                item.as_array()
+                    .expect("\"ids\" field of post config is not an array")
+                    .iter()
+                    .map(|value| value.as_str().expect("id is not a str"))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Set the ids of the posts of an album.
+    ///
+    /// If the ids are empty, the key is removed.
+    pub fn set_ids(&mut self, ids: &[String]) {
+        if ids.is_empty() {
+            self.table.remove("ids");
+            return;
+        }
+
+        self.table
+            .insert("ids", toml_edit::value(Array::from_iter(ids)));
+    }
+
+    /// Split an album into a post config for each of its posts.
+    ///
+    /// Disabled files are left out.
+    /// Each post uses the given title, numbered if there is more than one post.
+    /// Ids are taken from the "ids" key, in order.
+    pub fn album_chunks(&self, title: &str, chunk_size: usize) -> Vec<Config> {
+        let ids = self.ids();
+        let post_tables = self.chunk_post_tables(chunk_size, ALBUM_ONLY_KEYS);
+        let num_chunks = post_tables.len();
+
+        post_tables
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut post_table)| {
+                if let Some(id) = ids.get(i) {
+                    post_table.insert("id", toml_edit::value(*id));
+                }
+                let title = if num_chunks > 1 {
+                    format!("{title} ({}/{num_chunks})", i + 1)
+                } else {
+                    String::from(title)
+                };
+                post_table.insert("title", toml_edit::value(title));
+
+                Config::from_post_table(post_table)
+            })
+            .collect()
+    }
+
+    /// Split the files of a post into a post config for each batch of files.
+    ///
+    /// Disabled files are left out.
+    /// Every other key is kept, so each batch makes a post with the same title, privacy, and nsfw.
+    pub fn file_batches(&self, batch_size: usize) -> Vec<Config> {
+        self.chunk_post_tables(batch_size, &[])
+            .into_iter()
+            .map(Config::from_post_table)
+            .collect()
+    }
+
+    /// Split the enabled files of a post into post tables with up to the given number of files.
+    ///
+    /// The other keys of the post are copied into each table, except for the given keys.
+    fn chunk_post_tables(&self, chunk_size: usize, skipped_keys: &[&str]) -> Vec<Table> {
+        let files = self.files();
+        let files: Vec<&dyn TableLike> = files
+            .iter()
+            .filter(|file| !file.disabled())
+            .map(|file| file.table)
+            .collect();
+
+        files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let mut post_table = Table::new();
+                for (key, item) in self.table.iter() {
+                    if !skipped_keys.contains(&key) {
+                        post_table.insert(key, item.clone());
+                    }
+                }
+
+                let mut files_array = ArrayOfTables::new();
+                for file in chunk {
+                    let mut file_table = Table::new();
+                    for (key, item) in file.iter() {
+                        file_table.insert(key, item.clone());
+                    }
+                    files_array.push(file_table);
+                }
+                post_table.insert("files", Item::ArrayOfTables(files_array));
+
+                post_table
+            })
+            .collect()
+    }
+
+    /// Get the paths of the included configs.
+    pub fn includes(&self) -> Vec<&str> {
+        self.table
+            .get("include")
+            .map(|item| {
+                item.as_array()
+                    .expect("\"include\" field of post config is not an array")
+                    .iter()
+                    .map(|value| {
+                        value
+                            .as_str()
+                            .expect("\"include\" field of post config must be an array of strings")
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     /// Iter over the files.
     pub fn files(&self) -> PostConfigFilesArray {
         let item = self
@@ -222,7 +906,7 @@ impl PostConfig<'_> {
         let array = match item {
             Item::Value(Value::Array(array)) => {
                 for value in array.iter() {
-                    if value.is_inline_table() {
+                    if !value.is_inline_table() {
                         panic!("\"files\" field of post config must be an array of tables");
                     }
                 }
@@ -237,6 +921,122 @@ impl PostConfig<'_> {
 
         PostConfigFilesArray { array }
     }
+
+    /// Set whether the file with the given path is disabled.
+    ///
+    /// Returns false if no file has the given path.
+    pub fn set_file_disabled(&mut self, path: &str, disabled: bool) -> bool {
+        let item = self
+            .table
+            .get_mut("files")
+            .expect("missing \"files\" key of post config");
+
+        let tables: Vec<&mut dyn TableLike> = match item {
+            Item::Value(Value::Array(array)) => array
+                .iter_mut()
+                .map(|value| {
+                    value.as_inline_table_mut().expect("value must be a table")
+                        as &mut dyn TableLike
+                })
+                .collect(),
+            Item::ArrayOfTables(array) => array
+                .iter_mut()
+                .map(|table| table as &mut dyn TableLike)
+                .collect(),
+            _ => {
+                panic!("\"files\" key of post config is not an array of tables");
+            }
+        };
+
+        let mut found = false;
+        for table in tables {
+            if table.get("path").and_then(|item| item.as_str()) != Some(path) {
+                continue;
+            }
+
+            if disabled {
+                table.insert("disabled", toml_edit::value(true));
+            } else {
+                table.remove("disabled");
+            }
+            found = true;
+        }
+
+        found
+    }
+
+    /// Sort the files into natural order by path, or by name for generated files.
+    ///
+    /// Comments and formatting stay with each file where possible.
+    /// Returns false if the files were already sorted.
+    pub fn sort_files(&mut self) -> bool {
+        fn sort_key(table: &dyn TableLike) -> &str {
+            table
+                .get("path")
+                .or_else(|| table.get("name"))
+                .and_then(|item| item.as_str())
+                .unwrap_or("")
+        }
+
+        let item = self
+            .table
+            .get_mut("files")
+            .expect("missing \"files\" key of post config");
+
+        match item {
+            Item::Value(Value::Array(array)) => {
+                let keys: Vec<String> = array
+                    .iter()
+                    .map(|value| {
+                        let table = value.as_inline_table().expect("value must be a table");
+                        String::from(sort_key(table))
+                    })
+                    .collect();
+                if keys.is_sorted_by(|a, b| crate::util::natural_cmp(a, b).is_le()) {
+                    return false;
+                }
+
+                // The decor is the whitespace around each value, so it stays at its index.
+                let decors: Vec<_> = array.iter().map(|value| value.decor().clone()).collect();
+                array.sort_by(|a, b| {
+                    let a = a.as_inline_table().expect("value must be a table");
+                    let b = b.as_inline_table().expect("value must be a table");
+                    crate::util::natural_cmp(sort_key(a), sort_key(b))
+                });
+                for (value, decor) in array.iter_mut().zip(decors) {
+                    *value.decor_mut() = decor;
+                }
+            }
+            Item::ArrayOfTables(array) => {
+                let mut tables: Vec<Table> = array.iter().cloned().collect();
+                if tables
+                    .is_sorted_by(|a, b| crate::util::natural_cmp(sort_key(a), sort_key(b)).is_le())
+                {
+                    return false;
+                }
+
+                // Tables are written in the order of their document positions, not their order in the array.
+                // The sorted tables take over the positions of the old tables, in order.
+                let mut positions: Vec<Option<usize>> =
+                    tables.iter().map(|table| table.position()).collect();
+                positions.sort_unstable();
+                tables.sort_by(|a, b| crate::util::natural_cmp(sort_key(a), sort_key(b)));
+
+                array.clear();
+                for (mut table, position) in tables.into_iter().zip(positions) {
+                    if let Some(position) = position {
+                        table.set_position(position);
+                    }
+                    array.push(table);
+                }
+            }
+            _ => {
+                panic!("\"files\" key of post config is not an array of tables");
+            }
+        }
+
+        true
+    }
 }
 
 /// Config for the post files array.
@@ -263,12 +1063,39 @@ pub struct PostConfigFile<'a> {
 
 impl PostConfigFile<'_> {
     /// The file path.
-    pub fn path(&self) -> &str {
+    ///
+    /// This is missing if the file is generated by a command.
+    pub fn path(&self) -> Option<&str> {
         self.table
             .get("path")
-            .expect("missing path")
-            .as_str()
-            .expect("path is not a str")
+            .map(|item| item.as_str().expect("path is not a str"))
+    }
+
+    /// The command used to generate the file, if it exists.
+    ///
+    /// The stdout of the command is the file data.
+    pub fn command(&self) -> Option<Vec<&str>> {
+        self.table.get("command").map(|item| {
+            item.as_array()
+                .expect("command is not an array")
+                .iter()
+                .map(|value| value.as_str().expect("command arg is not a str"))
+                .collect()
+        })
+    }
+
+    /// The name of the file generated by a command, if it exists.
+    pub fn name(&self) -> Option<&str> {
+        self.table
+            .get("name")
+            .map(|item| item.as_str().expect("name is not a str"))
+    }
+
+    /// The id of the remote file this file corresponds to, if it exists.
+    pub fn id(&self) -> Option<&str> {
+        self.table
+            .get("id")
+            .map(|item| item.as_str().expect("id is not a str"))
     }
 
     /// The file description
@@ -277,6 +1104,25 @@ impl PostConfigFile<'_> {
             .get("description")
             .map(|item| item.as_str().expect("description is not a str"))
     }
+
+    /// The content type to upload the file as, overriding its extension.
+    pub fn content_type(&self) -> Option<PostConfigContentType> {
+        self.table.get("content_type").map(|item| {
+            item.as_str()
+                .expect("content_type is not a str")
+                .parse()
+                .expect("invalid content_type")
+        })
+    }
+
+    /// Whether the file is disabled.
+    ///
+    /// Disabled files are kept in the config, but are not synced.
+    pub fn disabled(&self) -> bool {
+        self.table
+            .get("disabled")
+            .is_some_and(|item| item.as_bool().expect("disabled is not a bool"))
+    }
 }
 
 /// Post privacy
@@ -287,15 +1133,85 @@ pub enum PostConfigPrivacy {
     Secret,
 }
 
+impl PostConfigPrivacy {
+    /// Get this as a str.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Public => "public",
+            Self::Hidden => "hidden",
+            Self::Secret => "secret",
+        }
+    }
+}
+
 impl std::str::FromStr for PostConfigPrivacy {
-    type Err = anyhow::Error;
+    type Err = SyncError;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
         match input {
             "public" => Ok(Self::Public),
             "hidden" => Ok(Self::Hidden),
             "secret" => Ok(Self::Secret),
-            _ => bail!("\"{input}\" is not a valid post privacy type"),
+            _ => Err(SyncError::InvalidPostPrivacy(input.into())),
+        }
+    }
+}
+
+/// The content type of a file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PostConfigContentType {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Mp4,
+}
+
+impl PostConfigContentType {
+    /// Get the file extensions of this content type.
+    ///
+    /// The first extension is the preferred one.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::Png => &["png"],
+            Self::Jpeg => &["jpg", "jpeg"],
+            Self::Gif => &["gif"],
+            Self::Webp => &["webp"],
+            Self::Mp4 => &["mp4"],
+        }
+    }
+}
+
+impl std::str::FromStr for PostConfigContentType {
+    type Err = SyncError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "image/png" => Ok(Self::Png),
+            "image/jpeg" => Ok(Self::Jpeg),
+            "image/gif" => Ok(Self::Gif),
+            "image/webp" => Ok(Self::Webp),
+            "video/mp4" => Ok(Self::Mp4),
+            _ => Err(SyncError::InvalidContentType(input.into())),
+        }
+    }
+}
+
+/// The format of file descriptions.
+///
+/// imgchest renders descriptions as markdown, which is currently the only format.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PostConfigDescriptionFormat {
+    Markdown,
+}
+
+impl std::str::FromStr for PostConfigDescriptionFormat {
+    type Err = SyncError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "markdown" => Ok(Self::Markdown),
+            _ => Err(SyncError::InvalidDescriptionFormat(input.into())),
         }
     }
 }
@@ -308,17 +1224,269 @@ pub struct UserConfig {
 
 impl UserConfig {
     /// Make a config from a string.
-    pub fn new(input: &str) -> anyhow::Result<Self> {
+    pub fn new(input: &str) -> Result<Self, SyncError> {
         let document: DocumentMut = input.parse()?;
         let _token = document
             .get("token")
             .map(|item| {
-                item.as_str()
-                    .context("\"token\" field of user config is not a string")
+                item.as_str().ok_or_else(|| {
+                    SyncError::invalid_config("\"token\" field of user config is not a string")
+                })
             })
             .transpose()?;
+        if let Some(tokens) = document.get("tokens") {
+            let tokens = tokens.as_table_like().ok_or_else(|| {
+                SyncError::invalid_config("\"tokens\" field of user config is not a table")
+            })?;
+            for (name, token) in tokens.iter() {
+                if token.as_str().is_none() {
+                    return Err(SyncError::invalid_config(format!(
+                        "token \"{name}\" of user config is not a string"
+                    )));
+                }
+            }
+        }
+        if let Some(defaults) = document.get("defaults") {
+            let defaults = defaults.as_table_like().ok_or_else(|| {
+                SyncError::invalid_config("\"defaults\" field of user config is not a table")
+            })?;
+            let _privacy = defaults
+                .get("privacy")
+                .map(|item| {
+                    item.as_str()
+                        .ok_or_else(|| {
+                            SyncError::invalid_config(
+                                "\"privacy\" field of defaults user config is not a string",
+                            )
+                        })?
+                        .parse::<PostConfigPrivacy>()
+                })
+                .transpose()?;
+            let _nsfw_marker = defaults
+                .get("nsfw_marker")
+                .map(|item| {
+                    item.as_bool().ok_or_else(|| {
+                        SyncError::invalid_config(
+                            "\"nsfw_marker\" field of defaults user config is not a bool",
+                        )
+                    })
+                })
+                .transpose()?;
+        }
+        if let Some(http) = document.get("http") {
+            let http = http.as_table_like().ok_or_else(|| {
+                SyncError::invalid_config("\"http\" field of user config is not a table")
+            })?;
+            let _user_agent = http
+                .get("user_agent")
+                .map(|item| {
+                    item.as_str().ok_or_else(|| {
+                        SyncError::invalid_config(
+                            "\"user_agent\" field of http user config is not a string",
+                        )
+                    })
+                })
+                .transpose()?;
+            if let Some(headers) = http.get("headers") {
+                let headers = headers.as_table_like().ok_or_else(|| {
+                    SyncError::invalid_config(
+                        "\"headers\" field of http user config is not a table",
+                    )
+                })?;
+                for (name, value) in headers.iter() {
+                    if value.as_str().is_none() {
+                        return Err(SyncError::invalid_config(format!(
+                            "value of \"{name}\" in http user config is not a string"
+                        )));
+                    }
+                }
+            }
+        }
+
+        let config = Self { document };
+
+        // Validate header names and values now,
+        // so that errors are reported when the config is loaded.
+        config.http_headers()?;
 
-        Ok(Self { document })
+        Ok(config)
+    }
+
+    /// Iter over the named tokens.
+    pub fn tokens(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.document
+            .get("tokens")
+            .map(|item| {
+                item.as_table_like()
+                    .expect("\"tokens\" field of user config is not a table")
+            })
+            .into_iter()
+            .flat_map(|table| table.iter())
+            .map(|(name, item)| {
+                let token = item.as_str().expect("token of user config is not a string");
+                (name, token)
+            })
+    }
+
+    /// Set a named token.
+    ///
+    /// If None is passed, the named token is removed.
+    pub fn set_named_token(&mut self, name: &str, token: Option<&str>) {
+        let tokens = get_or_insert_table(&mut self.document, "tokens");
+        match token {
+            Some(token) => {
+                tokens.insert(name, toml_edit::value(token));
+            }
+            None => {
+                tokens.remove(name);
+            }
+        }
+    }
+
+    /// Get the default post privacy, if it exists.
+    pub fn default_privacy(&self) -> Option<PostConfigPrivacy> {
+        self.document
+            .get("defaults")
+            .map(|item| {
+                item.as_table_like()
+                    .expect("\"defaults\" field of user config is not a table")
+            })?
+            .get("privacy")
+            .map(|item| {
+                item.as_str()
+                    .expect("\"privacy\" field of defaults user config is not a string")
+                    .parse::<PostConfigPrivacy>()
+                    .expect("failed to parse default post privacy")
+            })
+    }
+
+    /// Set the default post privacy.
+    ///
+    /// If None is passed, the default privacy is removed.
+    pub fn set_default_privacy(&mut self, privacy: Option<PostConfigPrivacy>) {
+        let defaults = get_or_insert_table(&mut self.document, "defaults");
+        match privacy {
+            Some(privacy) => {
+                defaults.insert("privacy", toml_edit::value(privacy.as_str()));
+            }
+            None => {
+                defaults.remove("privacy");
+            }
+        }
+    }
+
+    /// Check whether a ".nsfw" file in a post directory marks the post as nsfw.
+    pub fn nsfw_marker(&self) -> Option<bool> {
+        self.document
+            .get("defaults")
+            .map(|item| {
+                item.as_table_like()
+                    .expect("\"defaults\" field of user config is not a table")
+            })?
+            .get("nsfw_marker")
+            .map(|item| {
+                item.as_bool()
+                    .expect("\"nsfw_marker\" field of defaults user config is not a bool")
+            })
+    }
+
+    /// Set whether a ".nsfw" file in a post directory marks the post as nsfw.
+    ///
+    /// If None is passed, the setting is removed.
+    pub fn set_nsfw_marker(&mut self, nsfw_marker: Option<bool>) {
+        let defaults = get_or_insert_table(&mut self.document, "defaults");
+        match nsfw_marker {
+            Some(nsfw_marker) => {
+                defaults.insert("nsfw_marker", toml_edit::value(nsfw_marker));
+            }
+            None => {
+                defaults.remove("nsfw_marker");
+            }
+        }
+    }
+
+    /// Get the http table, if it exists.
+    fn http_table(&self) -> Option<&dyn TableLike> {
+        self.document.get("http").map(|item| {
+            item.as_table_like()
+                .expect("\"http\" field of user config is not a table")
+        })
+    }
+
+    /// Get the http user agent, if it exists.
+    pub fn http_user_agent(&self) -> Option<&str> {
+        self.http_table()?.get("user_agent").map(|item| {
+            item.as_str()
+                .expect("\"user_agent\" field of http user config is not a string")
+        })
+    }
+
+    /// Set the http user agent.
+    ///
+    /// If None is passed, the user agent is removed.
+    pub fn set_http_user_agent(&mut self, user_agent: Option<&str>) -> Result<(), SyncError> {
+        let http = get_or_insert_table(&mut self.document, "http");
+        match user_agent {
+            Some(user_agent) => {
+                HeaderValue::from_str(user_agent).map_err(|_error| {
+                    SyncError::invalid_config(format!(
+                        "\"{user_agent}\" is not a valid header value"
+                    ))
+                })?;
+                http.insert("user_agent", toml_edit::value(user_agent));
+            }
+            None => {
+                http.remove("user_agent");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Iter over the extra http headers.
+    pub fn http_extra_headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.http_table()
+            .and_then(|table| table.get("headers"))
+            .map(|item| {
+                item.as_table_like()
+                    .expect("\"headers\" field of http user config is not a table")
+            })
+            .into_iter()
+            .flat_map(|table| table.iter())
+            .map(|(key, item)| {
+                let value = item
+                    .as_str()
+                    .expect("http header value of user config is not a string");
+                (key, value)
+            })
+    }
+
+    /// Get all http headers, including the user agent.
+    pub fn http_headers(&self) -> Result<HeaderMap, SyncError> {
+        let mut headers = HeaderMap::new();
+        if let Some(user_agent) = self.http_user_agent() {
+            let value = HeaderValue::from_str(user_agent).map_err(|_error| {
+                SyncError::invalid_config(
+                    "\"user_agent\" field of http user config is not a valid header value",
+                )
+            })?;
+            headers.insert(USER_AGENT, value);
+        }
+        for (name, value) in self.http_extra_headers() {
+            let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_error| {
+                SyncError::invalid_config(format!(
+                    "\"{name}\" in http user config is not a valid header name"
+                ))
+            })?;
+            let header_value = HeaderValue::from_str(value).map_err(|_error| {
+                SyncError::invalid_config(format!(
+                    "value of \"{name}\" in http user config is not a valid header value"
+                ))
+            })?;
+            headers.append(header_name, header_value);
+        }
+
+        Ok(headers)
     }
 
     /// Get the token, if it exists.
@@ -335,6 +1503,7 @@ impl UserConfig {
     pub fn set_token(&mut self, new_token: &str) {
         if new_token.is_empty() {
             self.document.remove("token");
+            return;
         }
 
         self.document.insert("token", toml_edit::value(new_token));
@@ -346,3 +1515,162 @@ impl std::fmt::Display for UserConfig {
         self.document.fmt(f)
     }
 }
+
+/// Get a top-level table of a document, inserting it if it does not exist.
+fn get_or_insert_table<'a>(document: &'a mut DocumentMut, key: &str) -> &'a mut dyn TableLike {
+    document
+        .entry(key)
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .expect("user config table is not a table")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::block_on;
+    use crate::test::TestDir;
+
+    #[test]
+    fn post_config_sort_files_works() {
+        let mut config = Config::new(
+            "[post]\ntitle = \"post\"\n\n# The tenth file\n[[post.files]]\npath = \"10.png\"\n\n[[post.files]]\npath = \"2.png\"\ndescription = \"second\"\n\n[[post.files]]\nname = \"1.png\"\ncommand = [\"gen\"]\n",
+        )
+        .expect("failed to parse config");
+        assert!(config.post_mut().sort_files());
+        assert!(
+            config.to_string()
+                == "[post]\ntitle = \"post\"\n\n[[post.files]]\nname = \"1.png\"\ncommand = [\"gen\"]\n\n[[post.files]]\npath = \"2.png\"\ndescription = \"second\"\n\n# The tenth file\n[[post.files]]\npath = \"10.png\"\n",
+            "{config}"
+        );
+        assert!(!config.post_mut().sort_files());
+
+        let mut config = Config::new(
+            "[post]\nfiles = [\n    { path = \"b.png\" },\n    { path = \"a.png\" },\n]\n",
+        )
+        .expect("failed to parse config");
+        assert!(config.post_mut().sort_files());
+        assert!(
+            config.to_string()
+                == "[post]\nfiles = [\n    { path = \"a.png\" },\n    { path = \"b.png\" },\n]\n",
+            "{config}"
+        );
+    }
+
+    #[test]
+    fn config_includes_files_in_order() {
+        let dir = TestDir::new("config-includes-files-in-order");
+        dir.write(
+            "a.toml",
+            "[post]\ninclude = [\"nested/c.toml\"]\n\n[[post.files]]\npath = \"a.png\"\n",
+        );
+        dir.write("b.toml", "[post]\nfiles = [{ path = \"b.png\" }]\n");
+        let config_raw =
+            "[post]\ninclude = [\"a.toml\", \"b.toml\"]\n\n[[post.files]]\npath = \"main.png\"\n";
+        dir.write("imgchest-sync.toml", config_raw);
+        std::fs::create_dir(dir.path.join("nested")).expect("failed to create dir");
+        dir.write(
+            "nested/c.toml",
+            "[post]\n\n[[post.files]]\npath = \"c.png\"\n\n[[post.files]]\npath = \"d.png\"\n",
+        );
+
+        let mut config = Config::new(config_raw).expect("failed to parse config");
+        config
+            .load_includes(dir.path.as_std_path())
+            .expect("failed to load includes");
+        let paths: Vec<String> = config
+            .post_mut()
+            .files()
+            .iter()
+            .map(|file| String::from(file.path().unwrap()))
+            .collect();
+        assert!(paths == ["main.png", "a.png", "c.png", "d.png", "b.png"]);
+
+        // Included files are not written back to the main config.
+        config.post_mut().set_id(Some("abc"));
+        let written = config.to_string();
+        assert!(written.contains("id = \"abc\""));
+        assert!(written.contains("main.png"));
+        assert!(!written.contains("a.png"));
+
+        // A config may get all of its files from includes.
+        let mut config =
+            Config::new("[post]\ninclude = [\"b.toml\"]\n").expect("failed to parse config");
+        config
+            .load_includes(dir.path.as_std_path())
+            .expect("failed to load includes");
+        assert!(config.post_mut().files().len() == 1);
+
+        // Files are toggled in the config that lists them.
+        block_on(crate::commands::config::set_post_file_disabled(
+            &dir.path.join("imgchest-sync.toml"),
+            "c.png",
+            true,
+            false,
+        ))
+        .expect("failed to disable included file");
+        let nested_config = std::fs::read_to_string(dir.path.join("nested/c.toml")).unwrap();
+        assert!(nested_config.contains("disabled = true"), "{nested_config}");
+        assert!(
+            std::fs::read_to_string(dir.path.join("imgchest-sync.toml")).unwrap() == config_raw
+        );
+
+        dir.write(
+            "nested/c.toml",
+            "[post]\ninclude = [\"../a.toml\"]\n\n[[post.files]]\npath = \"c.png\"\n",
+        );
+        let mut config = Config::new(config_raw).expect("failed to parse config");
+        let error = config
+            .load_includes(dir.path.as_std_path())
+            .expect_err("include cycle was not detected");
+        assert!(error.to_string().contains("includes itself"), "{error}");
+
+        dir.write(
+            "b.toml",
+            "[post]\ntitle = \"nope\"\nfiles = [{ path = \"b.png\" }]\n",
+        );
+        dir.write(
+            "nested/c.toml",
+            "[post]\n\n[[post.files]]\npath = \"c.png\"\n",
+        );
+        let mut config = Config::new(config_raw).expect("failed to parse config");
+        let error = config
+            .load_includes(dir.path.as_std_path())
+            .expect_err("included title was not rejected");
+        assert!(error.to_string().contains("\"title\""), "{error}");
+    }
+
+    #[test]
+    fn user_config_setters_work() {
+        let mut config = UserConfig::new("").expect("failed to parse user config");
+
+        config.set_default_privacy(Some(PostConfigPrivacy::Secret));
+        config.set_nsfw_marker(Some(true));
+        config.set_named_token("work", Some("abc"));
+        config
+            .set_http_user_agent(Some("imgchest-sync"))
+            .expect("failed to set user agent");
+        assert!(config.set_http_user_agent(Some("bad\nagent")).is_err());
+
+        let config = UserConfig::new(&config.to_string()).expect("failed to reparse user config");
+        assert!(config.default_privacy() == Some(PostConfigPrivacy::Secret));
+        assert!(config.nsfw_marker() == Some(true));
+        assert!(config.tokens().eq([("work", "abc")]));
+        assert!(config.http_user_agent() == Some("imgchest-sync"));
+
+        let mut config = config;
+        config.set_default_privacy(None);
+        config.set_nsfw_marker(None);
+        config.set_named_token("work", None);
+        config.set_http_user_agent(None).unwrap();
+        config.set_token("abc");
+        config.set_token("");
+        assert!(config.default_privacy().is_none());
+        assert!(config.nsfw_marker().is_none());
+        assert!(config.tokens().next().is_none());
+        assert!(config.http_user_agent().is_none());
+        assert!(config.token().is_none());
+
+        assert!(UserConfig::new("[defaults]\nprivacy = \"private\"\n").is_err());
+    }
+}
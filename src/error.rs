@@ -0,0 +1,82 @@
+/// An error that may occur while syncing.
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    /// A config file was not valid toml.
+    #[error("failed to parse toml")]
+    TomlParse(#[from] toml_edit::TomlError),
+
+    /// A config file was valid toml, but did not have the expected structure.
+    #[error("{0}")]
+    InvalidConfig(String),
+
+    /// A post privacy string was not recognized.
+    #[error("\"{0}\" is not a valid post privacy type")]
+    InvalidPostPrivacy(String),
+
+    /// The old post had no files, so it cannot be diffed.
+    #[error("old post has no files")]
+    OldPostEmpty,
+
+    /// The new post had no files, so it cannot be diffed.
+    #[error("new post has no files")]
+    NewPostEmpty,
+
+    /// A description format string was not recognized.
+    #[error("\"{0}\" is not a valid description format")]
+    InvalidDescriptionFormat(String),
+
+    /// A file content type was not a supported image or video type.
+    #[error("\"{0}\" is not a supported content type. Valid values are: image/png, image/jpeg, image/gif, image/webp, video/mp4")]
+    InvalidContentType(String),
+
+    /// A file description was longer than the max length.
+    #[error("description of \"{file}\" is {length} characters long, which is more than the max of {max_length}")]
+    DescriptionTooLong {
+        /// The path or name of the file.
+        file: String,
+
+        /// The length of the description, in characters.
+        length: usize,
+
+        /// The max length of a description, in characters.
+        max_length: usize,
+    },
+
+    /// A post title was longer than the max length.
+    #[error("title of \"{dir}\" is {length} characters long, which is more than the max of {max_length}. Pass --truncate-titles to shorten it")]
+    TitleTooLong {
+        /// The post directory.
+        dir: String,
+
+        /// The length of the title, in characters.
+        length: usize,
+
+        /// The max length of a title, in characters.
+        max_length: usize,
+    },
+
+    /// A relative file path resolved to a path outside of its post directory.
+    #[error("\"{path}\" resolves to \"{resolved}\", which is outside of the post directory. Pass --allow-path-escape to allow it")]
+    PathEscapesDir {
+        /// The path of the file, as given in the config.
+        path: String,
+
+        /// The resolved path of the file.
+        resolved: String,
+    },
+
+    /// A config value referenced an environment variable that is not defined.
+    #[error("environment variable \"{0}\" is not defined")]
+    UndefinedEnvVar(String),
+
+    /// An io error occured.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl SyncError {
+    /// Make an invalid config error from a message.
+    pub(crate) fn invalid_config(message: impl Into<String>) -> Self {
+        Self::InvalidConfig(message.into())
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::Context;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
+
+/// A cache of local file hashes, so unchanged files do not need to be rehashed.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct HashCache {
+    /// The cached hashes, keyed by the file path from the post config.
+    #[serde(default)]
+    pub files: BTreeMap<String, HashCacheEntry>,
+}
+
+/// A cached file hash.
+#[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct HashCacheEntry {
+    /// The size of the file when it was hashed.
+    pub size: u64,
+
+    /// The modification time of the file when it was hashed, in nanoseconds since the unix epoch.
+    pub modified: u64,
+
+    /// The sha256 hash of the file.
+    pub sha256: String,
+
+    /// Whether the file was reoriented before hashing, if it was checked with --auto-orient.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oriented: Option<bool>,
+}
+
+impl HashCache {
+    /// Load a hash cache, if it exists.
+    pub async fn load(path: &Utf8Path) -> anyhow::Result<Option<Self>> {
+        let raw = match crate::util::try_read_to_string(path)
+            .await
+            .context("failed to read hash cache file")?
+        {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+
+        let hash_cache = toml::from_str(&raw).context("failed to parse hash cache file")?;
+        Ok(Some(hash_cache))
+    }
+
+    /// Save this hash cache.
+    pub async fn save(&self, path: &Utf8Path) -> anyhow::Result<()> {
+        let mut raw = String::new();
+        raw.push_str("# This file was autogenerated by imgchest-sync.\n");
+        raw.push_str("# DO NOT EDIT.\n");
+        raw.push('\n');
+        raw += &toml::to_string(self)?;
+
+        crate::util::write_string_safe(path, &raw)
+            .await
+            .context("failed to write hash cache file")?;
+
+        Ok(())
+    }
+
+    /// Get the cached hash for a file, if the file has not changed since it was hashed.
+    ///
+    /// With auto_orient, only hashes of files that were checked for reorienting are returned.
+    /// Without it, hashes of reoriented files are not returned.
+    ///
+    /// Returns the hash, and whether the file was reoriented.
+    pub fn get(&self, key: &str, metadata: &Metadata, auto_orient: bool) -> Option<(&str, bool)> {
+        let entry = self.files.get(key)?;
+        let (size, modified) = metadata_key(metadata)?;
+        if entry.size != size || entry.modified != modified {
+            return None;
+        }
+        let oriented = cached_orientation(auto_orient, entry.oriented)?;
+
+        Some((&entry.sha256, oriented))
+    }
+
+    /// Insert the hash for a file.
+    ///
+    /// Returns true if the cache changed.
+    pub fn insert(
+        &mut self,
+        key: String,
+        metadata: &Metadata,
+        sha256: String,
+        oriented: Option<bool>,
+    ) -> bool {
+        let (size, modified) = match metadata_key(metadata) {
+            Some(metadata_key) => metadata_key,
+            None => return false,
+        };
+        let entry = HashCacheEntry {
+            size,
+            modified,
+            sha256,
+            oriented,
+        };
+
+        if self.files.get(&key) == Some(&entry) {
+            return false;
+        }
+
+        self.files.insert(key, entry);
+        true
+    }
+}
+
+/// Check whether a cached hash can be used, given whether it was checked for reorienting.
+///
+/// Returns whether the file was reoriented, or `None` if the hash cannot be used.
+pub fn cached_orientation(auto_orient: bool, oriented: Option<bool>) -> Option<bool> {
+    match (auto_orient, oriented) {
+        (true, Some(oriented)) => Some(oriented),
+        (false, None | Some(false)) => Some(false),
+        (true, None) | (false, Some(true)) => None,
+    }
+}
+
+/// Get the size and modification time of a file, for checking whether it changed.
+pub fn metadata_key(metadata: &Metadata) -> Option<(u64, u64)> {
+    let modified = metadata.modified().ok()?;
+    let modified = modified.duration_since(UNIX_EPOCH).ok()?;
+    let modified = u64::try_from(modified.as_nanos()).ok()?;
+
+    Some((metadata.len(), modified))
+}
+
+/// Get the path of the hash cache, given the path of the post cache.
+pub fn get_hash_cache_path(cache_path: &Utf8Path) -> Utf8PathBuf {
+    cache_path.with_extension("hashes.toml")
+}
@@ -0,0 +1,110 @@
+use crate::hash_cache::cached_orientation;
+use crate::hash_cache::metadata_key;
+use anyhow::Context;
+use camino::Utf8Path;
+use rusqlite::OptionalExtension;
+use std::fs::Metadata;
+use std::sync::Mutex;
+
+/// A sqlite database of local file hashes, shared by every post folder.
+///
+/// Unlike the hash cache, this is keyed by absolute path,
+/// so one database can cover a whole tree of post folders.
+#[derive(Debug)]
+pub struct HashDb {
+    connection: Mutex<rusqlite::Connection>,
+}
+
+impl HashDb {
+    /// Open a hash database, creating it if it does not exist.
+    pub fn open(path: &Utf8Path) -> anyhow::Result<Self> {
+        let connection = rusqlite::Connection::open(path)
+            .with_context(|| format!("failed to open hash database \"{path}\""))?;
+        connection
+            .execute_batch(
+                "
+CREATE TABLE IF NOT EXISTS files (
+    path TEXT PRIMARY KEY NOT NULL,
+    size INTEGER NOT NULL,
+    modified INTEGER NOT NULL,
+    sha256 TEXT NOT NULL,
+    oriented INTEGER
+) STRICT;
+",
+            )
+            .context("failed to set up hash database")?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Get the stored hash for a file, if the file has not changed since it was hashed.
+    ///
+    /// This follows the same rules for --auto-orient as [`crate::hash_cache::HashCache::get`].
+    /// Returns the hash, and whether the file was reoriented.
+    pub fn get(
+        &self,
+        path: &Utf8Path,
+        metadata: &Metadata,
+        auto_orient: bool,
+    ) -> anyhow::Result<Option<(String, bool)>> {
+        let Some((size, modified)) = metadata_key(metadata) else {
+            return Ok(None);
+        };
+
+        let connection = self.connection.lock().unwrap();
+        let row = connection
+            .query_row(
+                "SELECT size, modified, sha256, oriented FROM files WHERE path = ?1",
+                [path.as_str()],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, Option<bool>>(3)?,
+                    ))
+                },
+            )
+            .optional()
+            .context("failed to query hash database")?;
+        let Some((row_size, row_modified, sha256, oriented)) = row else {
+            return Ok(None);
+        };
+        if u64::try_from(row_size).ok() != Some(size)
+            || u64::try_from(row_modified).ok() != Some(modified)
+        {
+            return Ok(None);
+        }
+
+        Ok(cached_orientation(auto_orient, oriented).map(|oriented| (sha256, oriented)))
+    }
+
+    /// Store the hash for a file, replacing any older hash.
+    pub fn insert(
+        &self,
+        path: &Utf8Path,
+        metadata: &Metadata,
+        sha256: &str,
+        oriented: Option<bool>,
+    ) -> anyhow::Result<()> {
+        let Some((size, modified)) = metadata_key(metadata) else {
+            return Ok(());
+        };
+        // sqlite integers are signed, and these fit for any realistic file.
+        let (Ok(size), Ok(modified)) = (i64::try_from(size), i64::try_from(modified)) else {
+            return Ok(());
+        };
+
+        let connection = self.connection.lock().unwrap();
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO files (path, size, modified, sha256, oriented) VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![path.as_str(), size, modified, sha256, oriented],
+            )
+            .context("failed to update hash database")?;
+
+        Ok(())
+    }
+}
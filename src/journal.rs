@@ -0,0 +1,131 @@
+use crate::api::RemotePost;
+use crate::post::Post;
+use crate::post::PostFile;
+use anyhow::Context;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+
+/// A journal of an in-progress post update.
+///
+/// Updates take multiple API calls, so they cannot be applied atomically.
+/// While an update runs, the cache of the post is replaced by this journal,
+/// which records the remote post as of the last completed call,
+/// along with the files that are being added.
+///
+/// If the update is interrupted, the next sync reconciles the journal with the remote post,
+/// instead of downloading every remote file to rebuild the cache.
+/// The journal is removed once a new cache is written.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct Journal {
+    /// The version of the diff logic used to make this journal.
+    pub diff_version: u32,
+
+    /// The id of the post being updated.
+    pub post_id: String,
+
+    /// The remote post, as of the last completed call.
+    pub post: Post,
+
+    /// The files being added, in order.
+    ///
+    /// These have no ids, as they are not known until the files are added.
+    #[serde(default)]
+    pub pending_files: Vec<PostFile>,
+}
+
+impl Journal {
+    /// Load a journal, if it exists.
+    pub async fn load(path: &Utf8Path) -> anyhow::Result<Option<Self>> {
+        let raw = match crate::util::try_read_to_string(path)
+            .await
+            .context("failed to read update journal")?
+        {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+
+        let journal = toml::from_str(&raw).context("failed to parse update journal")?;
+        Ok(Some(journal))
+    }
+
+    /// Save this journal.
+    pub async fn save(&self, path: &Utf8Path) -> anyhow::Result<()> {
+        let mut raw = String::new();
+        raw.push_str("# This file was autogenerated by imgchest-sync.\n");
+        raw.push_str("# DO NOT EDIT.\n");
+        raw.push('\n');
+        raw += &toml::to_string(self)?;
+
+        crate::util::write_string_safe(path, &raw)
+            .await
+            .context("failed to write update journal")?;
+
+        Ok(())
+    }
+
+    /// Mark the pending files as added, given their new ids in order.
+    pub fn add_pending_files<'a, I>(&mut self, ids: I)
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        for (mut file, id) in std::mem::take(&mut self.pending_files).into_iter().zip(ids) {
+            file.id = Some(id.into());
+            self.post.files.push(file);
+        }
+    }
+
+    /// Mark a file as removed.
+    pub fn remove_file(&mut self, id: &str) {
+        self.post
+            .files
+            .retain(|file| file.id.as_deref() != Some(id));
+    }
+
+    /// Reconcile this journal with the remote post, recovering the current state of the post.
+    ///
+    /// Remote files with known ids keep their hashes.
+    /// Remote files with unknown ids must be the pending files, which are added in one request,
+    /// so either all or none of them may be present.
+    /// The metadata and descriptions are taken from the remote post, as their updates may not have finished.
+    ///
+    /// Returns `None` if the remote post cannot be explained by this journal.
+    pub fn reconcile(self, remote_post: &RemotePost) -> Option<Post> {
+        let num_pending = self.pending_files.len();
+        let mut pending_files = self.pending_files.into_iter();
+        let mut known_files = self.post.files;
+
+        let mut files = Vec::with_capacity(remote_post.images.len());
+        for image in remote_post.images.iter() {
+            let known_index = known_files
+                .iter()
+                .position(|file| file.id.as_deref() == Some(image.id.as_str()));
+            let mut file = match known_index {
+                Some(index) => known_files.swap_remove(index),
+                None => {
+                    let mut file = pending_files.next()?;
+                    file.id = Some(image.id.clone());
+                    file
+                }
+            };
+            file.description = image.description.clone().unwrap_or_default();
+            files.push(file);
+        }
+
+        let num_added = num_pending - pending_files.len();
+        if num_added != 0 && num_added != num_pending {
+            return None;
+        }
+
+        Some(Post {
+            title: remote_post.title.clone().unwrap_or(self.post.title),
+            privacy: remote_post.privacy,
+            nsfw: remote_post.nsfw,
+            files,
+        })
+    }
+}
+
+/// Get the path of the update journal, given the path of the post cache.
+pub fn get_journal_path(cache_path: &Utf8Path) -> Utf8PathBuf {
+    cache_path.with_extension("journal.toml")
+}
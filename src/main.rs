@@ -1,493 +1,4693 @@
+mod api;
+mod bandwidth;
+mod bundle;
 mod commands;
 mod config;
+mod error;
+mod hash_cache;
+mod hash_db;
+mod journal;
+mod metrics;
 mod post;
+mod post_dirs;
+mod stats;
 mod util;
 
+use crate::api::Api;
+use crate::api::NewPost;
+use crate::api::PostUpdate;
+#[cfg(test)]
+use crate::api::RemoteFile;
+use crate::api::RemotePost;
+use crate::bandwidth::BandwidthLimiter;
 use crate::config::Config;
 use crate::config::PostConfig;
+use crate::config::PostConfigContentType;
 use crate::config::PostConfigPrivacy;
 use crate::config::UserConfig;
+use crate::error::SyncError;
+use crate::hash_cache::get_hash_cache_path;
+use crate::hash_cache::HashCache;
+use crate::hash_db::HashDb;
+use crate::journal::get_journal_path;
+use crate::journal::Journal;
+use crate::metrics::Metrics;
+use crate::post::DiffOptions;
+use crate::post::OrderMode;
 use crate::post::Post;
 use crate::post::PostDiff;
 use crate::post::PostFile;
+use crate::post::PostFileData;
 use crate::post::PostPrivacy;
+use crate::post_dirs::PostDirs;
+use crate::stats::Stats;
+use crate::stats::Timer;
+use anyhow::bail;
 use anyhow::ensure;
 use anyhow::Context;
+use camino::Utf8Component;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
 use directories::ProjectDirs;
-use regex::Regex;
+use reqwest::header::HeaderMap;
 use sha2::Digest;
 use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// The file extensions that imgchest is known to accept.
+const SUPPORTED_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "mp4"];
+
+/// The version of the diff logic.
+///
+/// Bump this when the semantics of `generate_post_diffs` change,
+/// so that caches made with the old logic are not trusted.
+const DIFF_VERSION: u32 = 2;
+
+/// The default max number of files in each post of an album.
+///
+/// This is the limit for free accounts.
+const DEFAULT_ALBUM_CHUNK_SIZE: usize = 20;
+
+/// The quality that jpegs reoriented with --auto-orient are re-encoded at.
+const AUTO_ORIENT_JPEG_QUALITY: u8 = 95;
+
+/// The max number of files uploaded when creating a post.
+///
+/// Larger posts are created with the first batch, and the rest are added in later batches.
+const CREATE_BATCH_SIZE: usize = 20;
+
+/// The description used to clear a file description.
+///
+/// The API does not accept an empty description, but it does accept whitespace.
+const CLEARED_DESCRIPTION: &str = " ";
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct Cache {
+    /// The version of the diff logic used to make this cache.
+    ///
+    /// Caches from before this was added are version 0.
+    #[serde(default)]
+    pub diff_version: u32,
+
     /// The old post
     pub post: Post,
 }
 
-#[derive(Debug, argh::FromArgs)]
-#[argh(description = "a CLI to sync folders to imgchest.com")]
-pub struct Options {
-    #[argh(
-        option,
-        long = "token",
-        short = 't',
-        description = "the API token to use"
-    )]
-    pub token: Option<String>,
+/// State shared by every post synced in a run.
+struct SyncContext<A = crate::api::Client> {
+    /// The options.
+    options: Options,
 
-    #[argh(
-        option,
-        long = "input",
-        short = 'i',
-        description = "the directory to sync posts from"
-    )]
-    pub input: Option<Utf8PathBuf>,
+    /// The imgchest client, using the default token.
+    client: A,
 
-    #[argh(
-        switch,
-        long = "no-read-cache",
-        description = "avoid reading the cache"
-    )]
-    pub no_read_cache: bool,
+    /// Imgchest clients for each named token in the user config.
+    account_clients: HashMap<String, A>,
 
-    #[argh(
-        switch,
-        long = "print-diffs",
-        description = "whether the generated post diffs should be printed"
-    )]
-    pub print_diffs: bool,
+    /// What is used to hash files.
+    ///
+    /// This is shared between all posts,
+    /// so that the total number of files being hashed at once stays bounded.
+    hasher: Hasher,
 
-    #[argh(
-        option,
-        long = "filter-regex",
-        description = "only process directory entry names accepted by the provided regex"
-    )]
-    pub filter_regex: Option<String>,
+    /// Extra headers for http requests made outside of the imgchest client.
+    http_headers: HeaderMap,
 
-    #[argh(subcommand)]
-    subcommand: Option<Subcommand>,
+    /// Limits the upload bandwidth, if --max-upload-bytes-per-sec was given.
+    ///
+    /// This is shared between all posts.
+    upload_limiter: Option<BandwidthLimiter>,
+
+    /// The privacy of posts that do not specify one, from the user config.
+    default_privacy: PostConfigPrivacy,
+
+    /// Whether a ".nsfw" file marks posts that do not specify nsfw as nsfw, from the user config.
+    nsfw_marker: bool,
+
+    /// The results of checking whether the token of each account can modify posts.
+    ///
+    /// The default token is `None`.
+    /// Failures keep their error message.
+    verified_accounts: std::sync::Mutex<HashMap<Option<String>, Result<(), String>>>,
+
+    /// Tokens read from the keyring, by credential.
+    ///
+    /// This avoids asking the keyring again for every post that uses the same credential.
+    credential_tokens: std::sync::Mutex<HashMap<String, String>>,
+
+    /// Whether the run was interrupted with Ctrl-C.
+    ///
+    /// No new directories are started once this is set.
+    interrupted: Arc<AtomicBool>,
 }
 
-#[derive(Debug, argh::FromArgs)]
-#[argh(subcommand)]
-enum Subcommand {
-    Config(self::commands::config::Options),
+/// What is used to hash local files.
+struct Hasher {
+    /// The pool used to hash files.
+    pool: rayon::ThreadPool,
+
+    /// The hash database, if --hash-cache-db was given.
+    db: Option<Arc<HashDb>>,
 }
 
-fn main() -> anyhow::Result<()> {
-    let options: Options = argh::from_env();
+/// The outcome of syncing a post.
+#[derive(Debug)]
+struct SyncOutcome {
+    /// The stats for the sync.
+    stats: Stats,
 
-    let tokio_rt = tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()?;
+    /// Whether the user asked to stop the run.
+    quit: bool,
 
-    tokio_rt.block_on(async_main(options))
+    /// What was done to the post.
+    action: SyncAction,
+
+    /// The id of the post that was acted on, if it exists.
+    post_id: Option<String>,
+
+    /// The synced posts, with their ids and hashes.
+    ///
+    /// This is only filled in when --dump-post-json is given.
+    posts: Vec<Post>,
 }
 
-async fn async_main(options: Options) -> anyhow::Result<()> {
-    let project_dirs =
-        ProjectDirs::from("", "", "imgchest-sync").context("failed to get config directory")?;
-    let config_dir = project_dirs.config_dir();
-    tokio::fs::create_dir_all(&config_dir)
-        .await
-        .context("failed to create config directory")?;
-    let config_path = config_dir.join("config.toml");
-    let config = {
-        let config_str = crate::util::try_read_to_string(&config_path)
-            .await?
-            .unwrap_or(String::new());
-        UserConfig::new(&config_str).context("failed to parse user config")?
-    };
+/// Where the default token came from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TokenSource {
+    /// The --token flag.
+    Flag,
 
-    match options.subcommand {
-        Some(Subcommand::Config(options)) => {
-            self::commands::config::exec(options, &config_path, config).await?;
-        }
-        None => {
-            let client = imgchest::Client::new();
-            let token = options
-                .token
-                .as_deref()
-                .or_else(|| config.token())
-                .context(
-                "missing API token. Specify it either with the --token flag or in the user config.",
-            )?;
-            client.set_token(token);
+    /// The --token-stdin flag.
+    Stdin,
+
+    /// The --token-file flag.
+    File,
+
+    /// The IMGCHEST_SYNC_TOKEN environment variable.
+    Env,
+
+    /// The user config.
+    UserConfig,
+}
 
-            exec(options, client).await?
+impl TokenSource {
+    /// Get this as a str.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Flag => "--token",
+            Self::Stdin => "--token-stdin",
+            Self::File => "--token-file",
+            Self::Env => "IMGCHEST_SYNC_TOKEN",
+            Self::UserConfig => "user config",
         }
     }
-
-    Ok(())
 }
 
-async fn exec(options: Options, client: imgchest::Client) -> anyhow::Result<()> {
-    let input = options
-        .input
-        .as_ref()
-        .context("missing input directory. Specify it with --input")?;
-    let filter_regex = options
-        .filter_regex
-        .map(|filter_regex| {
-            Regex::new(&format!("^{filter_regex}$")).context("invalid filter regex")
-        })
-        .transpose()?;
+/// What was done to a post while syncing it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SyncAction {
+    /// A new post was created.
+    Created,
 
-    let mut dir_iter = tokio::fs::read_dir(input).await?;
-    while let Some(entry) = dir_iter.next_entry().await? {
-        let file_type = entry.file_type().await?;
-        let entry_path = entry.path();
-        let entry_path: &Utf8Path = entry_path.as_path().try_into()?;
-        let entry_file_name = entry_path.file_name().context("missing file name")?;
+    /// An existing post was updated.
+    Updated,
 
-        if !file_type.is_dir() {
-            continue;
-        }
+    /// An existing post already matched.
+    Unchanged,
 
-        if let Some(filter_regex) = filter_regex.as_ref() {
-            if !filter_regex.is_match(entry_file_name) {
-                continue;
-            }
-        }
+    /// An existing post was deleted.
+    Deleted,
 
-        let dir_path = input.join(entry_path);
-        let config_path = dir_path.join("imgchest-sync.toml");
-        let cache_path = dir_path.join(".imgchest-sync-cache.toml");
+    /// The post was skipped.
+    Skipped,
+}
 
-        let mut config = match crate::util::try_read_to_string(&config_path)
-            .await
-            .context("failed to read config file")?
-        {
-            Some(config_raw) => Config::new(&config_raw).context("failed to parse config file")?,
-            None => continue,
-        };
+/// A synced post, as written by --output-ids.
+#[derive(Debug, serde::Serialize)]
+struct OutputId {
+    /// The post id.
+    id: String,
 
-        println!("syncing \"{entry_file_name}\"");
+    /// The post url.
+    url: String,
+}
 
-        let mut cache = None;
-        if !options.no_read_cache {
-            cache = match crate::util::try_read_to_string(&cache_path)
-                .await
-                .context("failed to read cache file")?
-            {
-                Some(cache_raw) => {
-                    match toml::from_str::<Cache>(&cache_raw).context("failed to parse cache file")
-                    {
-                        Ok(cache) => Some(cache),
-                        Err(error) => {
-                            eprintln!("  {error:?}");
-                            None
-                        }
-                    }
-                }
-                None => None,
-            };
-        }
+/// The summary of a run, as written by --summary-json.
+#[derive(Debug, Default, serde::Serialize)]
+struct RunSummary {
+    /// The time the run took, in seconds.
+    duration_seconds: f64,
 
-        let mut post_config = config.post_mut();
+    /// The number of directories processed.
+    directories_processed: usize,
 
-        let mut new_post = create_post_from_post_config(&dir_path, &post_config).await?;
+    /// The number of posts created.
+    created: usize,
 
-        let mut no_changes = false;
-        match post_config.id() {
-            Some(id) => {
-                let online_post;
-                let old_post = match cache.as_ref() {
-                    Some(cache) => &cache.post,
-                    None => {
-                        let post = create_post_from_online(&client, id)
-                            .await
-                            .context("failed to create post from online")?;
+    /// The number of posts updated.
+    updated: usize,
 
-                        online_post = post;
-                        &online_post
-                    }
-                };
+    /// The number of posts that already matched.
+    unchanged: usize,
 
-                let diffs = generate_post_diffs(old_post, &new_post)
-                    .context("failed to generate post diffs")?;
-                let diff_empty = diffs
-                    .iter()
-                    .all(|diff| matches!(diff, PostDiff::RetainFile { .. }));
+    /// The number of posts deleted.
+    deleted: usize,
 
-                if options.print_diffs {
-                    println!("  diffs: [");
-                    for diff in diffs.iter() {
-                        println!("    {diff:?},");
-                    }
-                    println!("  ]");
-                }
+    /// The number of posts skipped while syncing.
+    skipped: usize,
 
-                if !diff_empty {
-                    println!("  updating post");
-                    update_online_post(&client, id, diffs, old_post, &mut new_post, &cache_path)
-                        .await?;
-                } else {
-                    println!("  no changes");
+    /// The number of disabled posts, which were skipped before syncing.
+    disabled: usize,
 
-                    // Copy file ids
-                    for (new_file, old_file) in new_post.files.iter_mut().zip(old_post.files.iter())
-                    {
-                        let id = old_file.id.as_ref().context("missing old id")?.clone();
-                        new_file.id = Some(id);
-                    }
+    /// The number of posts that failed to sync.
+    failed: usize,
 
-                    no_changes = true;
-                }
-            }
-            None => {
-                let mut builder = imgchest::CreatePostBuilder::new();
-                builder
-                    .title(new_post.title.clone())
-                    .privacy(match new_post.privacy {
-                        PostPrivacy::Public => imgchest::PostPrivacy::Public,
-                        PostPrivacy::Hidden => imgchest::PostPrivacy::Hidden,
-                        PostPrivacy::Secret => imgchest::PostPrivacy::Secret,
-                    })
-                    .nsfw(new_post.nsfw);
-
-                for file in new_post.files.iter() {
-                    let path = file.path.as_ref().context("missing path")?;
-                    let file = imgchest::UploadPostFile::from_path(&path)
-                        .await
-                        .with_context(|| format!("failed to open image at \"{path}\""))?;
+    /// The number of bytes of files uploaded.
+    bytes_uploaded: u64,
 
-                    builder.image(file);
-                }
+    /// The result of each synced directory, in order.
+    directories: Vec<DirectorySummary>,
+}
 
-                println!("  creating new post");
-                let imgchest_post = client
-                    .create_post(builder)
-                    .await
-                    .context("failed to create new post")?;
+/// The result of syncing a directory, as written by --summary-json.
+#[derive(Debug, serde::Serialize)]
+struct DirectorySummary {
+    /// The directory name.
+    name: String,
 
-                // Set descriptions
-                ensure!(new_post.files.len() == imgchest_post.images.len());
-                let description_updates: Vec<_> = new_post
-                    .files
-                    .iter()
-                    .zip(imgchest_post.images.iter())
-                    .filter(|(file, _new_file)| !file.description.is_empty())
-                    .map(|(file, new_file)| imgchest::FileUpdate {
-                        id: new_file.id.to_string(),
-                        description: file.description.clone(),
-                    })
-                    .collect();
-                if !description_updates.is_empty() {
-                    client
-                        .update_files_bulk(description_updates)
-                        .await
-                        .context("failed to set file descriptions")?;
-                }
+    /// The sync action, or "failed".
+    result: &'static str,
 
-                post_config.set_id(Some(&*imgchest_post.id));
+    /// The post id, if the post has one.
+    post_id: Option<String>,
 
-                ensure!(imgchest_post.images.len() == new_post.files.len());
-                for (file, imgchest_image) in new_post
-                    .files
-                    .iter_mut()
-                    .zip(Vec::from(imgchest_post.images).into_iter())
-                {
-                    file.id = Some(imgchest_image.id.into());
-                }
+    /// The error, if the directory failed to sync.
+    error: Option<String>,
+}
 
-                crate::util::write_string_safe(config_path, &config.to_string())
-                    .await
-                    .context("failed to write new config")?;
-            }
-        }
+impl RunSummary {
+    /// Record the outcome of syncing a directory.
+    fn record(&mut self, name: &str, outcome: &SyncOutcome) {
+        let count = match outcome.action {
+            SyncAction::Created => &mut self.created,
+            SyncAction::Updated => &mut self.updated,
+            SyncAction::Unchanged => &mut self.unchanged,
+            SyncAction::Deleted => &mut self.deleted,
+            SyncAction::Skipped => &mut self.skipped,
+        };
+        *count += 1;
 
-        if !(cache.is_some() && no_changes) {
-            let cache = match cache {
-                Some(mut cache) => {
-                    cache.post = new_post;
-                    cache
-                }
-                None => Cache { post: new_post },
-            };
+        self.directories.push(DirectorySummary {
+            name: name.into(),
+            result: outcome.action.as_str(),
+            post_id: outcome.post_id.clone(),
+            error: None,
+        });
+    }
 
-            let mut cache_str = String::new();
-            cache_str.push_str("# This file was autogenerated by imgchest-sync.\n");
-            cache_str.push_str("# DO NOT EDIT.\n");
-            cache_str.push('\n');
-            cache_str += &toml::to_string(&cache)?;
+    /// Record a directory that failed to sync.
+    fn record_failure(&mut self, name: &str, error: &anyhow::Error) {
+        self.failed += 1;
+        self.directories.push(DirectorySummary {
+            name: name.into(),
+            result: "failed",
+            post_id: None,
+            error: Some(format!("{error:#}")),
+        });
+    }
+}
 
-            crate::util::write_string_safe(cache_path, &cache_str)
-                .await
-                .context("failed to write new cache")?;
+impl SyncAction {
+    /// Get this as a str.
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Unchanged => "unchanged",
+            Self::Deleted => "deleted",
+            Self::Skipped => "skipped",
         }
     }
-
-    Ok(())
 }
 
-async fn create_post_from_post_config(
-    dir_path: &Utf8Path,
-    post_config: &PostConfig<'_>,
-) -> anyhow::Result<Post> {
-    let dir_name = dir_path.file_name().context("missing dir name")?;
+/// Where to get the descriptions of files without one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptionSource {
+    /// The file name, without its extension.
+    Filename,
 
-    let title = post_config.title().unwrap_or(dir_name).into();
-    let privacy = match post_config.privacy().unwrap_or(PostConfigPrivacy::Hidden) {
-        PostConfigPrivacy::Public => PostPrivacy::Public,
-        PostConfigPrivacy::Hidden => PostPrivacy::Hidden,
-        PostConfigPrivacy::Secret => PostPrivacy::Secret,
-    };
-    let nsfw = post_config.nsfw().unwrap_or(false);
-    let files = {
-        let files_config = post_config.files();
+    /// The file name, without its extension, with underscores as spaces and in title case.
+    HumanizedFilename,
+}
 
-        let mut futures = Vec::with_capacity(files_config.len());
-        for file in files_config.iter() {
-            let (tx, rx) = tokio::sync::oneshot::channel();
+impl std::str::FromStr for DescriptionSource {
+    type Err = anyhow::Error;
 
-            let description: String = file.description().unwrap_or("").into();
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "filename" => Ok(Self::Filename),
+            "humanized-filename" => Ok(Self::HumanizedFilename),
+            _ => anyhow::bail!("\"{input}\" is not a valid description source"),
+        }
+    }
+}
 
-            let path = Utf8Path::new(file.path());
-            let path: Utf8PathBuf = if path.is_relative() {
-                dir_path.join(path)
-            } else {
-                path.into()
-            };
+/// What to diff the local post against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffBase {
+    /// Use the cache, or download the remote post if there is no cache.
+    Auto,
 
-            rayon::spawn(move || {
-                let sha256_result = hash_file_at_path(&path)
-                    .with_context(|| format!("failed to hash file at \"{path}\""));
-                let result = sha256_result.map(|sha256| PostFile {
-                    description,
-                    sha256,
-                    path: Some(path),
-                    id: None,
-                });
+    /// Use the cache, failing if there is none.
+    Cache,
 
-                let _ = tx.send(result).is_ok();
-            });
+    /// Always download the remote post.
+    Remote,
+}
 
-            futures.push(rx);
-        }
+impl std::str::FromStr for DiffBase {
+    type Err = anyhow::Error;
 
-        let mut files = Vec::with_capacity(files_config.len());
-        for future in futures {
-            let file = future.await??;
-            files.push(file);
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "auto" => Ok(Self::Auto),
+            "cache" => Ok(Self::Cache),
+            "remote" => Ok(Self::Remote),
+            _ => anyhow::bail!("\"{input}\" is not a valid diff base"),
         }
-        files
-    };
-
-    Ok(Post {
-        title,
-        privacy,
-        nsfw,
-        files,
-    })
+    }
 }
 
-fn hash_file_at_path(path: &Utf8Path) -> anyhow::Result<String> {
-    let mut file =
-        std::fs::File::open(path).with_context(|| format!("failed to open \"{path}\""))?;
+/// What to do when both the local config and the remote post changed since the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Skip the directory with a warning.
+    Abort,
 
-    let mut hasher = Sha256::new();
-    std::io::copy(&mut file, &mut hasher)?;
-    let hash = hasher.finalize();
-    let hex_hash = base16ct::lower::encode_string(&hash);
+    /// Sync the local config, overwriting the remote changes.
+    LocalWins,
 
-    anyhow::Ok(hex_hash)
+    /// Pull the remote changes into the cache and skip the directory.
+    RemoteWins,
 }
 
-async fn create_post_from_online(client: &imgchest::Client, id: &str) -> anyhow::Result<Post> {
-    let imgchest_post = client.get_post(id).await?;
-
-    let title = imgchest_post
-        .title
-        .map(String::from)
-        .unwrap_or_else(String::new);
-    let privacy = match imgchest_post.privacy {
-        imgchest::PostPrivacy::Public => PostPrivacy::Public,
-        imgchest::PostPrivacy::Hidden => PostPrivacy::Hidden,
-        imgchest::PostPrivacy::Secret => PostPrivacy::Secret,
-    };
-    let nsfw = imgchest_post.nsfw;
-    let files = {
-        let mut files = Vec::new();
-        for image in Vec::from(imgchest_post.images).into_iter() {
-            let description = image
-                .description
-                .map(String::from)
-                .unwrap_or_else(String::new);
+impl std::str::FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
 
-            let handle = tokio::runtime::Handle::current();
-            let mut image_response = client
-                .client
-                .get(&*image.link)
-                .send()
-                .await?
-                .error_for_status()?;
-            let sha256 = tokio::task::spawn_blocking(move || {
-                let mut hasher = Sha256::new();
-                while let Some(chunk) = handle.block_on(image_response.chunk())? {
-                    hasher.update(chunk);
-                }
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "abort" => Ok(Self::Abort),
+            "local-wins" => Ok(Self::LocalWins),
+            "remote-wins" => Ok(Self::RemoteWins),
+            _ => anyhow::bail!("\"{input}\" is not a valid conflict policy"),
+        }
+    }
+}
 
-                let hash = hasher.finalize();
-                let hex_hash = base16ct::lower::encode_string(&hash);
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(description = "a CLI to sync folders to imgchest.com")]
+pub struct Options {
+    #[argh(
+        option,
+        long = "token",
+        short = 't',
+        description = "the API token to use"
+    )]
+    pub token: Option<String>,
 
-                anyhow::Ok(hex_hash)
-            })
-            .await??;
+    #[argh(
+        option,
+        long = "token-file",
+        description = "a file to read the API token from"
+    )]
+    pub token_file: Option<Utf8PathBuf>,
 
-            files.push(PostFile {
-                description,
-                sha256,
-                path: None,
-                id: Some(image.id.into()),
-            });
-        }
-        files
-    };
+    #[argh(
+        switch,
+        long = "token-stdin",
+        description = "whether to read the API token from stdin, so that it is never written to disk or passed as an argument"
+    )]
+    pub token_stdin: bool,
 
-    Ok(Post {
-        title,
-        privacy,
-        nsfw,
-        files,
-    })
-}
+    #[argh(
+        option,
+        long = "input",
+        short = 'i',
+        description = "the directory to sync posts from"
+    )]
+    pub input: Option<Utf8PathBuf>,
 
-async fn update_online_post(
-    client: &imgchest::Client,
+    #[argh(
+        option,
+        long = "config",
+        description = "sync a single post from this config file instead of --input. Use \"-\" to read it from stdin"
+    )]
+    pub config: Option<Utf8PathBuf>,
+
+    #[argh(
+        option,
+        long = "dir",
+        description = "the directory used to resolve relative paths in --config. Defaults to the directory of the config file"
+    )]
+    pub dir: Option<Utf8PathBuf>,
+
+    #[argh(
+        option,
+        long = "cache",
+        description = "the cache file to use with --config. If omitted, no cache is used"
+    )]
+    pub cache: Option<Utf8PathBuf>,
+
+    #[argh(
+        option,
+        long = "output-config",
+        description = "the file to write the updated config to when reading it from stdin with --config -, instead of printing it to stdout"
+    )]
+    pub output_config: Option<Utf8PathBuf>,
+
+    #[argh(
+        option,
+        long = "cache-dir",
+        description = "a directory to store all cache files in, instead of next to each post config"
+    )]
+    pub cache_dir: Option<Utf8PathBuf>,
+
+    #[argh(
+        option,
+        long = "hash-cache-db",
+        description = "a sqlite database to store the hashes of local files in, keyed by absolute path, size, and modification time. This is shared by every post folder, and is checked after the hash cache of each folder"
+    )]
+    pub hash_cache_db: Option<Utf8PathBuf>,
+
+    #[argh(
+        switch,
+        long = "no-read-cache",
+        description = "avoid reading the cache"
+    )]
+    pub no_read_cache: bool,
+
+    #[argh(
+        switch,
+        long = "purge-cache-on-mismatch",
+        description = "whether to delete cache files that fail to parse"
+    )]
+    pub purge_cache_on_mismatch: bool,
+
+    #[argh(
+        switch,
+        long = "backup-purged-cache",
+        description = "whether purged cache files should be moved to a \".bak\" file instead of deleted"
+    )]
+    pub backup_purged_cache: bool,
+
+    #[argh(
+        switch,
+        long = "print-diffs",
+        description = "whether the generated post diffs should be printed"
+    )]
+    pub print_diffs: bool,
+
+    #[argh(
+        switch,
+        long = "print-plan",
+        description = "whether the API calls needed to update each post should be printed"
+    )]
+    pub print_plan: bool,
+
+    #[argh(
+        switch,
+        long = "interactive",
+        description = "whether to prompt before applying each post's diffs"
+    )]
+    pub interactive: bool,
+
+    #[argh(
+        switch,
+        long = "check-remote",
+        description = "whether to warn when the remote post title, privacy, or nsfw differ from the cache"
+    )]
+    pub check_remote: bool,
+
+    #[argh(
+        option,
+        long = "on-conflict",
+        description = "what to do when both the local config and the remote post changed since the cache. \"abort\" skips the directory, \"local-wins\" overwrites the remote post, and \"remote-wins\" pulls the remote changes into the cache and skips the directory. Requires --check-remote. Defaults to \"abort\""
+    )]
+    pub on_conflict: Option<ConflictPolicy>,
+
+    #[argh(
+        option,
+        long = "order-mode",
+        default = "OrderMode::Strict",
+        description = "how file order is synced. \"strict\" reuploads files to match the local order, while \"set\" keeps the remote order and only adds and removes files. Defaults to \"strict\""
+    )]
+    pub order_mode: OrderMode,
+
+    #[argh(
+        option,
+        long = "randomize-order",
+        hidden_help,
+        description = "shuffle the files of each post with the given seed before diffing, to test how reorders are handled. Requires --i-know-what-im-doing"
+    )]
+    pub randomize_order: Option<u64>,
+
+    #[argh(
+        switch,
+        long = "i-know-what-im-doing",
+        hidden_help,
+        description = "whether to allow options meant for testing, which can reorder remote posts"
+    )]
+    pub i_know_what_im_doing: bool,
+
+    #[argh(
+        switch,
+        long = "stats",
+        description = "whether timing and bandwidth stats should be printed for each directory"
+    )]
+    pub stats: bool,
+
+    #[argh(
+        switch,
+        long = "verbose",
+        description = "whether to print the entries of the input directory that are skipped, like files and directories without a post config"
+    )]
+    pub verbose: bool,
+
+    #[argh(
+        switch,
+        long = "warn-missing-config",
+        description = "whether to warn about directories in the input directory without a post config"
+    )]
+    pub warn_missing_config: bool,
+
+    #[argh(
+        switch,
+        long = "trace-http",
+        description = "whether every api call and file download should be logged, with sensitive headers redacted"
+    )]
+    pub trace_http: bool,
+
+    #[argh(
+        option,
+        long = "filter-regex",
+        description = "only process directory entry names accepted by the provided regex"
+    )]
+    pub filter_regex: Option<String>,
+
+    #[argh(
+        option,
+        long = "resume-from",
+        description = "skip directory entries, in name order, until the one with this name"
+    )]
+    pub resume_from: Option<String>,
+
+    #[argh(
+        switch,
+        long = "natural-sort",
+        description = "whether directories should be processed in natural order, where \"2\" sorts before \"10\""
+    )]
+    pub natural_sort: bool,
+
+    #[argh(
+        option,
+        long = "max-images",
+        description = "skip posts with more than this many images"
+    )]
+    pub max_images: Option<usize>,
+
+    #[argh(
+        option,
+        long = "max-description-length",
+        description = "fail before making any API calls if a file description is longer than this many characters"
+    )]
+    pub max_description_length: Option<usize>,
+
+    #[argh(
+        option,
+        long = "max-title-length",
+        description = "fail before making any API calls if a post title is longer than this many characters"
+    )]
+    pub max_title_length: Option<usize>,
+
+    #[argh(
+        switch,
+        long = "truncate-titles",
+        description = "shorten titles longer than --max-title-length with an ellipsis, instead of failing"
+    )]
+    pub truncate_titles: bool,
+
+    #[argh(
+        option,
+        long = "max-removal-percent",
+        default = "50",
+        description = "skip posts whose number of files would drop by more than this percent, unless --allow-large-removals is passed. Defaults to 50"
+    )]
+    pub max_removal_percent: u8,
+
+    #[argh(
+        switch,
+        long = "allow-large-removals",
+        description = "allow updates that remove more files than --max-removal-percent"
+    )]
+    pub allow_large_removals: bool,
+
+    #[argh(
+        option,
+        long = "privacy-override",
+        description = "override the privacy of every synced post"
+    )]
+    pub privacy_override: Option<PostConfigPrivacy>,
+
+    #[argh(
+        switch,
+        long = "persist-overrides",
+        description = "whether overrides should be written back to the post configs"
+    )]
+    pub persist_overrides: bool,
+
+    #[argh(
+        option,
+        long = "hash-jobs",
+        description = "the max number of files to hash at once. Defaults to the number of cpus"
+    )]
+    pub hash_jobs: Option<usize>,
+
+    #[argh(
+        option,
+        long = "allowed-extensions",
+        description = "a comma-separated list of file extensions to accept. By default, every extension is accepted, with a warning for ones imgchest is not known to support"
+    )]
+    pub allowed_extensions: Option<String>,
+
+    #[argh(
+        option,
+        long = "files",
+        description = "a comma-separated list of file names to sync with --config. Every other file keeps its remote state. This can produce unexpected diffs if the file order changed"
+    )]
+    pub files: Option<String>,
+
+    #[argh(
+        option,
+        long = "api-base",
+        description = "the base url to download files from instead of imgchest.com. Only usable with --dry-run, compare, or status. May also be set with IMGCHEST_SYNC_API_BASE. Unsupported"
+    )]
+    pub api_base: Option<String>,
+
+    #[argh(
+        switch,
+        long = "force-recreate",
+        description = "delete and recreate existing posts instead of updating them. Requires --yes"
+    )]
+    pub force_recreate: bool,
+
+    #[argh(
+        switch,
+        long = "only-new",
+        description = "only create new posts, skipping any post that already has an id"
+    )]
+    pub only_new: bool,
+
+    #[argh(
+        switch,
+        long = "keep-remote-empty-descriptions",
+        description = "leave remote file descriptions untouched when the local description is empty, instead of clearing them"
+    )]
+    pub keep_remote_empty_descriptions: bool,
+
+    #[argh(
+        switch,
+        long = "clear-descriptions",
+        description = "clear removed file descriptions by setting them to a single space, instead of re-uploading the file. This relies on undocumented API behavior"
+    )]
+    pub clear_descriptions: bool,
+
+    #[argh(
+        switch,
+        long = "no-descriptions",
+        description = "ignore file descriptions for this run. Remote descriptions are left as they are, and new files are uploaded without them"
+    )]
+    pub no_descriptions: bool,
+
+    #[argh(
+        option,
+        long = "description-from",
+        description = "use the file name as the description of files without one. \"filename\" uses the file name without its extension, while \"humanized-filename\" also replaces underscores with spaces and uses title case"
+    )]
+    pub description_from: Option<DescriptionSource>,
+
+    #[argh(
+        switch,
+        long = "trust-config",
+        description = "when there is no cache, assume the remote files match the config instead of downloading them. May produce incorrect diffs if the remote post was edited externally"
+    )]
+    pub trust_config: bool,
+
+    #[argh(
+        option,
+        long = "diff-base",
+        default = "DiffBase::Auto",
+        description = "what existing posts are diffed against. \"cache\" fails without a cache, \"remote\" always downloads the remote post, and \"auto\" uses the cache if there is one. Defaults to \"auto\""
+    )]
+    pub diff_base: DiffBase,
+
+    #[argh(
+        switch,
+        long = "expand-env",
+        description = "expand environment variable references in post config titles, descriptions, and paths, and in user config tokens"
+    )]
+    pub expand_env: bool,
+
+    #[argh(
+        switch,
+        long = "hash-only",
+        description = "only hash local files and store the hashes in the hash cache, without any network activity"
+    )]
+    pub hash_only: bool,
+
+    #[argh(
+        switch,
+        long = "allow-path-escape",
+        description = "whether relative file paths may resolve outside of their post directory, like through \"..\" or symlinks"
+    )]
+    pub allow_path_escape: bool,
+
+    #[argh(
+        switch,
+        long = "allow-file-commands",
+        description = "whether files of post configs may be generated by running their command. Commands are never run by subcommands"
+    )]
+    pub allow_file_commands: bool,
+
+    #[argh(
+        switch,
+        long = "metadata-only",
+        description = "only sync the title, privacy, and nsfw of posts with an id, without hashing or diffing files"
+    )]
+    pub metadata_only: bool,
+
+    #[argh(
+        switch,
+        long = "concurrent-hash-and-upload",
+        description = "whether new posts should be hashed and uploaded in batches, uploading each batch while the next one is hashed. Files are only checked one batch at a time"
+    )]
+    pub concurrent_hash_and_upload: bool,
+
+    #[argh(
+        switch,
+        long = "refresh-cache",
+        description = "only rebuild the cache of each post with an id from its remote state, without syncing"
+    )]
+    pub refresh_cache: bool,
+
+    #[argh(
+        switch,
+        long = "dry-run",
+        description = "print what would change for each post, without making changes or writing any files"
+    )]
+    pub dry_run: bool,
+
+    #[argh(
+        switch,
+        long = "skip-unreadable",
+        description = "skip files that cannot be read or hashed instead of failing the post. Skipped files are removed from the online post"
+    )]
+    pub skip_unreadable: bool,
+
+    #[argh(
+        switch,
+        long = "strict",
+        description = "fail posts with file paths that only differ in case, instead of warning"
+    )]
+    pub strict: bool,
+
+    #[argh(
+        option,
+        long = "max-gif-size",
+        description = "reject animated gifs larger than this many bytes before uploading them, as imgchest may reject or flatten them"
+    )]
+    pub max_gif_size: Option<u64>,
+
+    #[argh(
+        switch,
+        long = "auto-orient",
+        description = "bake the exif orientation of jpeg, png, tiff, and webp images into their pixels before hashing and uploading them"
+    )]
+    pub auto_orient: bool,
+
+    #[argh(
+        option,
+        long = "delay-between-dirs",
+        description = "the number of milliseconds to wait between syncing directories"
+    )]
+    pub delay_between_dirs: Option<u64>,
+
+    #[argh(
+        option,
+        long = "limit",
+        description = "stop after processing this many directories"
+    )]
+    pub limit: Option<usize>,
+
+    #[argh(
+        switch,
+        long = "verify-token-scope",
+        description = "before the first change to an existing post, check that the token can modify posts by rewriting the post's privacy to its current value"
+    )]
+    pub verify_token_scope: bool,
+
+    #[argh(
+        option,
+        long = "output-ids",
+        description = "write a map of each synced directory name to its post id and url to this file after the run. It is json if the file ends in .json, and toml otherwise"
+    )]
+    pub output_ids: Option<Utf8PathBuf>,
+
+    #[argh(
+        option,
+        long = "metrics-file",
+        description = "write metrics of the run to this path in the Prometheus text format, like for the node_exporter textfile collector"
+    )]
+    pub metrics_file: Option<Utf8PathBuf>,
+
+    #[argh(
+        option,
+        long = "summary-json",
+        description = "write a json summary of the run, with counts and the result of each synced directory, to this path"
+    )]
+    pub summary_json: Option<Utf8PathBuf>,
+
+    #[argh(
+        option,
+        long = "fail-fast-after",
+        description = "keep syncing after a directory fails to sync, until this many directories have failed. By default, the run stops at the first failure. Only syncing is covered, so --refresh-cache and publish still stop at the first failure"
+    )]
+    pub fail_fast_after: Option<usize>,
+
+    #[argh(
+        option,
+        long = "dump-post-json",
+        description = "a directory to write the final post of each synced directory to, with its ids and hashes, as json named after the directory"
+    )]
+    pub dump_post_json: Option<Utf8PathBuf>,
+
+    #[argh(
+        option,
+        long = "post-hook",
+        description = "a shell command to run in each post directory after it is synced. It is not run for skipped posts"
+    )]
+    pub post_hook: Option<String>,
+
+    #[argh(
+        switch,
+        long = "hook-must-succeed",
+        description = "stop the run if the post hook fails, instead of warning"
+    )]
+    pub hook_must_succeed: bool,
+
+    #[argh(
+        option,
+        long = "create-as",
+        description = "create new posts with this privacy, regardless of their config. They are marked as drafts, which keep their privacy until the publish subcommand is run"
+    )]
+    pub create_as: Option<PostConfigPrivacy>,
+
+    #[argh(
+        option,
+        long = "max-upload-bytes-per-sec",
+        description = "the max upload bandwidth, in bytes per second, across all posts. This is best-effort, as uploads are paced by their file sizes"
+    )]
+    pub max_upload_bytes_per_sec: Option<u64>,
+
+    #[argh(
+        switch,
+        long = "yes",
+        short = 'y',
+        description = "confirm destructive operations"
+    )]
+    pub yes: bool,
+
+    #[argh(subcommand)]
+    subcommand: Option<Subcommand>,
+}
+
+#[derive(Debug, argh::FromArgs, argh::ArgsInfo)]
+#[argh(subcommand)]
+enum Subcommand {
+    Config(self::commands::config::Options),
+    Completions(self::commands::completions::Options),
+    Publish(self::commands::publish::Options),
+    Dedup(self::commands::dedup::Options),
+    Compare(self::commands::compare::Options),
+    Export(self::commands::export::Options),
+    Import(self::commands::import::Options),
+    Tokens(self::commands::tokens::Options),
+    Status(self::commands::status::Options),
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = std::env::args_os()
+        .map(|arg| {
+            arg.into_string().map_err(|arg| {
+                anyhow::anyhow!(
+                    "argument \"{}\" is not valid unicode",
+                    arg.to_string_lossy()
+                )
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let args = crate::util::expand_argfiles(args)?;
+    let options = parse_options(&args);
+
+    let tokio_rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+
+    tokio_rt.block_on(async_main(options))
+}
+
+/// Parse options from args, exiting on failure like `argh::from_env`.
+fn parse_options(args: &[String]) -> Options {
+    let cmd = args
+        .first()
+        .and_then(|arg| Utf8Path::new(arg).file_name())
+        .unwrap_or("imgchest-sync");
+    let args: Vec<&str> = args.iter().skip(1).map(|arg| arg.as_str()).collect();
+
+    <Options as argh::FromArgs>::from_args(&[cmd], &args).unwrap_or_else(|early_exit| {
+        std::process::exit(match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                0
+            }
+            Err(()) => {
+                eprintln!(
+                    "{}\nRun {cmd} --help for more information.",
+                    early_exit.output
+                );
+                1
+            }
+        })
+    })
+}
+
+async fn async_main(options: Options) -> anyhow::Result<()> {
+    // Completions should work even if the user config is missing or broken.
+    if let Some(Subcommand::Completions(options)) = options.subcommand {
+        return self::commands::completions::exec(options).await;
+    }
+
+    let project_dirs =
+        ProjectDirs::from("", "", "imgchest-sync").context("failed to get config directory")?;
+    let config_dir = project_dirs.config_dir();
+    tokio::fs::create_dir_all(&config_dir)
+        .await
+        .context("failed to create config directory")?;
+    let config_path = config_dir.join("config.toml");
+    let config = {
+        let config_str = crate::util::try_read_to_string(&config_path)
+            .await?
+            .unwrap_or(String::new());
+        UserConfig::new(&config_str).context("failed to parse user config")?
+    };
+
+    // Exporting only reads local files, so it does not need a token.
+    if let Some(Subcommand::Export(export_options)) = options.subcommand.as_ref() {
+        let default_privacy = config
+            .default_privacy()
+            .unwrap_or(PostConfigPrivacy::Hidden);
+        let nsfw_marker = config.nsfw_marker().unwrap_or(false);
+        return self::commands::export::exec(
+            &options,
+            export_options,
+            default_privacy,
+            nsfw_marker,
+        )
+        .await;
+    }
+
+    match options.subcommand {
+        Some(Subcommand::Config(options)) => {
+            self::commands::config::exec(options, &config_path, config).await?;
+        }
+        Some(Subcommand::Import(options)) => {
+            self::commands::import::exec(options).await?;
+        }
+        Some(Subcommand::Tokens(ref tokens_options)) => {
+            let default_token = resolve_default_token(&options, &config).await?;
+            let named_tokens = config
+                .tokens()
+                .map(|(account, token)| {
+                    let token =
+                        expand_config_value(token, options.expand_env).with_context(|| {
+                            format!("failed to expand token for account \"{account}\"")
+                        })?;
+                    anyhow::Ok((String::from(account), token))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let credential_tokens = collect_post_credentials(&options)
+                .await?
+                .into_iter()
+                .map(|credential| {
+                    let token = get_credential_token(&credential);
+                    (credential, token)
+                })
+                .collect::<Vec<_>>();
+            self::commands::tokens::exec(
+                tokens_options,
+                default_token,
+                &named_tokens,
+                &credential_tokens,
+            )?;
+        }
+        Some(Subcommand::Completions(_) | Subcommand::Export(_)) => unreachable!(),
+        Some(
+            Subcommand::Publish(_)
+            | Subcommand::Dedup(_)
+            | Subcommand::Compare(_)
+            | Subcommand::Status(_),
+        )
+        | None => {
+            let http_headers = config
+                .http_headers()
+                .context("failed to build http headers from user config")?;
+
+            let client = crate::api::Client::new(options.trace_http, &http_headers)?;
+            let token = resolve_default_token(&options, &config).await?;
+            match token {
+                Some((token, _source)) => client.set_token(&token),
+                None => {
+                    // Hashing does not need the API.
+                    ensure!(
+                        options.hash_only
+                            || matches!(
+                                options.subcommand,
+                                Some(Subcommand::Dedup(_) | Subcommand::Status(_))
+                            ),
+                        "missing API token. Specify it with the --token, --token-stdin, or --token-file flags, the IMGCHEST_SYNC_TOKEN environment variable, or in the user config."
+                    );
+                }
+            }
+
+            let account_clients = config
+                .tokens()
+                .map(|(account, token)| {
+                    let token =
+                        expand_config_value(token, options.expand_env).with_context(|| {
+                            format!("failed to expand token for account \"{account}\"")
+                        })?;
+                    let client = crate::api::Client::new(options.trace_http, &http_headers)?;
+                    client.set_token(&token);
+                    anyhow::Ok((String::from(account), client))
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            let default_privacy = config
+                .default_privacy()
+                .unwrap_or(PostConfigPrivacy::Hidden);
+            let nsfw_marker = config.nsfw_marker().unwrap_or(false);
+
+            exec(
+                options,
+                client,
+                account_clients,
+                http_headers,
+                default_privacy,
+                nsfw_marker,
+            )
+            .await?
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the default token, and where it came from.
+///
+/// Precedence: --token, --token-stdin, --token-file, IMGCHEST_SYNC_TOKEN, then the user config.
+async fn resolve_default_token(
+    options: &Options,
+    config: &UserConfig,
+) -> anyhow::Result<Option<(String, TokenSource)>> {
+    // Stdin can only be read once.
+    ensure!(
+        !(options.token_stdin
+            && options
+                .config
+                .as_deref()
+                .is_some_and(|config| config == "-")),
+        "--token-stdin cannot be used when reading the config from stdin"
+    );
+    ensure!(
+        !(options.token_stdin && options.interactive),
+        "--token-stdin cannot be used with --interactive"
+    );
+    ensure!(
+        !(options.token_stdin && options.token.is_some()),
+        "--token-stdin cannot be used with --token"
+    );
+
+    let token = match (options.token.as_deref(), options.token_file.as_deref()) {
+        (Some(token), _) => Some((String::from(token), TokenSource::Flag)),
+        (None, _) if options.token_stdin => {
+            let token = tokio::task::spawn_blocking(|| {
+                let mut token = String::new();
+                std::io::Read::read_to_string(&mut std::io::stdin(), &mut token)?;
+                anyhow::Ok(token)
+            })
+            .await?
+            .context("failed to read token from stdin")?;
+            let token = token.trim();
+            ensure!(!token.is_empty(), "token from stdin is empty");
+            Some((String::from(token), TokenSource::Stdin))
+        }
+        (None, Some(token_file)) => {
+            let token = tokio::fs::read_to_string(token_file)
+                .await
+                .with_context(|| format!("failed to read token file \"{token_file}\""))?;
+            let token = token.trim();
+            ensure!(!token.is_empty(), "token file \"{token_file}\" is empty");
+            Some((String::from(token), TokenSource::File))
+        }
+        (None, None) => match std::env::var("IMGCHEST_SYNC_TOKEN") {
+            Ok(token) if !token.is_empty() => Some((token, TokenSource::Env)),
+            _ => config
+                .token()
+                .map(|token| expand_config_value(token, options.expand_env))
+                .transpose()
+                .context("failed to expand token")?
+                .map(|token| (token, TokenSource::UserConfig)),
+        },
+    };
+
+    Ok(token)
+}
+
+async fn exec(
+    mut options: Options,
+    client: crate::api::Client,
+    account_clients: HashMap<String, crate::api::Client>,
+    http_headers: HeaderMap,
+    default_privacy: PostConfigPrivacy,
+    nsfw_marker: bool,
+) -> anyhow::Result<()> {
+    ensure!(
+        !options.force_recreate || options.yes,
+        "--force-recreate deletes existing posts. Pass --yes to confirm"
+    );
+    ensure!(
+        !(options.force_recreate && options.only_new),
+        "--force-recreate cannot be used with --only-new"
+    );
+    ensure!(
+        options.fail_fast_after != Some(0),
+        "--fail-fast-after must be at least 1"
+    );
+    ensure!(
+        !(options.metadata_only
+            && (options.force_recreate
+                || options.hash_only
+                || options.files.is_some()
+                || options.randomize_order.is_some())),
+        "--metadata-only cannot be used with --force-recreate, --hash-only, --files, or --randomize-order"
+    );
+    ensure!(
+        !(options.keep_remote_empty_descriptions && options.clear_descriptions),
+        "--keep-remote-empty-descriptions cannot be used with --clear-descriptions"
+    );
+    ensure!(
+        !(options.no_descriptions && options.clear_descriptions),
+        "--no-descriptions cannot be used with --clear-descriptions"
+    );
+    ensure!(
+        !(options.diff_base == DiffBase::Cache && options.no_read_cache),
+        "--diff-base cache cannot be used with --no-read-cache"
+    );
+    ensure!(
+        !options.truncate_titles || options.max_title_length.is_some(),
+        "--truncate-titles requires --max-title-length"
+    );
+    ensure!(
+        !matches!(options.max_title_length, Some(0..4)),
+        "--max-title-length must be at least 4"
+    );
+    ensure!(
+        options.on_conflict.is_none() || options.check_remote,
+        "--on-conflict requires --check-remote"
+    );
+    ensure!(
+        options.randomize_order.is_none() || options.i_know_what_im_doing,
+        "--randomize-order reorders remote posts and requires --i-know-what-im-doing"
+    );
+    ensure!(
+        !(options.trust_config && options.diff_base != DiffBase::Auto),
+        "--trust-config can only be used with --diff-base auto"
+    );
+    ensure!(
+        !(options.no_descriptions && options.description_from.is_some()),
+        "--no-descriptions cannot be used with --description-from"
+    );
+    ensure!(
+        options.max_removal_percent <= 100,
+        "--max-removal-percent must be at most 100"
+    );
+    ensure!(
+        !(options.refresh_cache && options.hash_only),
+        "--refresh-cache cannot be used with --hash-only"
+    );
+    ensure!(
+        !(options.force_recreate && options.dry_run),
+        "--force-recreate cannot be used with --dry-run"
+    );
+    ensure!(
+        !(options.refresh_cache && options.dry_run),
+        "--refresh-cache cannot be used with --dry-run"
+    );
+    ensure!(
+        !(options.refresh_cache && options.only_new),
+        "--refresh-cache cannot be used with --only-new"
+    );
+    let publish = matches!(options.subcommand, Some(Subcommand::Publish(_)));
+    ensure!(
+        !(publish && (options.hash_only || options.refresh_cache)),
+        "the publish subcommand cannot be used with --hash-only or --refresh-cache"
+    );
+    ensure!(
+        !(options.files.is_some() && options.config.is_none()),
+        "--files can only be used with --config"
+    );
+    ensure!(
+        !(options.metrics_file.is_some() && options.config.is_some()),
+        "--metrics-file cannot be used with --config"
+    );
+    ensure!(
+        !(options.summary_json.is_some() && options.config.is_some()),
+        "--summary-json cannot be used with --config"
+    );
+    let compare = matches!(options.subcommand, Some(Subcommand::Compare(_)));
+    ensure!(
+        !(compare && (options.hash_only || options.refresh_cache)),
+        "the compare subcommand cannot be used with --hash-only or --refresh-cache"
+    );
+    let status = matches!(options.subcommand, Some(Subcommand::Status(_)));
+    ensure!(
+        !(status && (options.hash_only || options.refresh_cache)),
+        "the status subcommand cannot be used with --hash-only or --refresh-cache"
+    );
+    if options.api_base.is_none() {
+        options.api_base = std::env::var("IMGCHEST_SYNC_API_BASE").ok();
+    }
+    if let Some(api_base) = options.api_base.as_deref() {
+        ensure!(
+            api_base.starts_with("http://") || api_base.starts_with("https://"),
+            "api base \"{api_base}\" is not an http or https url"
+        );
+
+        // The imgchest client always uses the official API endpoint,
+        // so only downloads can be redirected.
+        // Refuse anything that could modify posts, as those calls would still reach imgchest.
+        ensure!(
+            options.dry_run || compare || status,
+            "--api-base only redirects file downloads, so it can only be used with --dry-run or the compare or status subcommands"
+        );
+        eprintln!("warning: the imgchest client does not support a custom api base, so only file downloads will use \"{api_base}\"");
+    }
+    let dedup = matches!(options.subcommand, Some(Subcommand::Dedup(_)));
+    ensure!(
+        !(dedup && (options.hash_only || options.refresh_cache)),
+        "the dedup subcommand cannot be used with --hash-only or --refresh-cache"
+    );
+    ensure!(
+        !(dedup && options.config.is_some()),
+        "the dedup subcommand scans an input directory, so it cannot be used with --config"
+    );
+
+    let hash_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.hash_jobs.unwrap_or(0))
+        .thread_name(|i| format!("imgchest-sync-hash-{i}"))
+        .build()
+        .context("failed to create hash thread pool")?;
+    let hash_db = open_hash_db(&options)?;
+
+    if let Some(cache_dir) = options.cache_dir.as_ref() {
+        tokio::fs::create_dir_all(cache_dir)
+            .await
+            .context("failed to create cache directory")?;
+    }
+
+    ensure!(
+        options.max_upload_bytes_per_sec != Some(0),
+        "--max-upload-bytes-per-sec must be at least 1"
+    );
+    let upload_limiter = options.max_upload_bytes_per_sec.map(BandwidthLimiter::new);
+
+    let ctx = SyncContext {
+        options,
+        client,
+        account_clients,
+        hasher: Hasher {
+            pool: hash_pool,
+            db: hash_db,
+        },
+        http_headers,
+        upload_limiter,
+        default_privacy,
+        nsfw_marker,
+        verified_accounts: std::sync::Mutex::new(HashMap::new()),
+        credential_tokens: std::sync::Mutex::new(HashMap::new()),
+        interrupted: Arc::new(AtomicBool::new(false)),
+    };
+
+    // A single post has no later directories to skip, so Ctrl-C keeps its default behavior.
+    if ctx.options.config.is_none() {
+        spawn_interrupt_handler(ctx.interrupted.clone());
+    }
+
+    run(&ctx).await
+}
+
+/// Run the mode selected by the options on each post directory.
+async fn run<A>(ctx: &SyncContext<A>) -> anyhow::Result<()>
+where
+    A: Api,
+{
+    let options = &ctx.options;
+    match options.subcommand.as_ref() {
+        Some(Subcommand::Dedup(dedup_options)) => match &dedup_options.subcommand {
+            self::commands::dedup::Subcommand::Report(report_options) => {
+                self::commands::dedup::exec(ctx, report_options.format).await
+            }
+        },
+        Some(Subcommand::Status(_)) => self::commands::status::exec(ctx).await,
+        Some(Subcommand::Compare(_)) => self::commands::compare::exec(ctx).await,
+        Some(Subcommand::Publish(_)) => self::commands::publish::exec(ctx).await,
+        _ if options.hash_only => exec_hash_only(ctx).await,
+        _ if options.refresh_cache => exec_refresh_cache(ctx).await,
+        _ => exec_sync(ctx).await,
+    }
+}
+
+/// Sync each post directory.
+async fn exec_sync<A>(ctx: &SyncContext<A>) -> anyhow::Result<()>
+where
+    A: Api,
+{
+    let options = &ctx.options;
+    let mut post_dirs = PostDirs::new(ctx).await?;
+
+    let run_start = Instant::now();
+    let mut metrics = Metrics::default();
+    let mut summary = RunSummary::default();
+    let mut output_ids = BTreeMap::new();
+    let mut failed = Vec::new();
+    while let Some(mut post_dir) = post_dirs.next().await? {
+        post_dirs.delay().await;
+
+        println!("syncing \"{}\"", post_dir.label);
+
+        let outcome = match sync_post(
+            ctx,
+            &post_dir.path,
+            &mut post_dir.config,
+            post_dir.config_path.as_deref(),
+            post_dir.cache_path.as_deref(),
+        )
+        .await
+        {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                failed.push(post_dir.label.clone());
+                metrics.posts_failed += 1;
+                summary.record_failure(&post_dir.name, &error);
+
+                // Below the threshold, failures are assumed to be specific to the directory.
+                if options
+                    .fail_fast_after
+                    .is_some_and(|max_failures| failed.len() < max_failures)
+                {
+                    eprintln!("  failed to sync \"{}\": {error:#}", post_dir.label);
+                    continue;
+                }
+
+                // The posts synced before the failure still exist, so their ids are kept.
+                if let (Some(output_ids_path), false) =
+                    (options.output_ids.as_deref(), options.dry_run)
+                {
+                    write_output_ids(output_ids_path, &output_ids).await?;
+                }
+                if let (Some(metrics_path), false) =
+                    (options.metrics_file.as_deref(), options.dry_run)
+                {
+                    metrics.directories_processed = post_dirs.num_processed() as u64;
+                    write_metrics(metrics_path, metrics, post_dirs.total_stats(), run_start)
+                        .await?;
+                }
+                if let (Some(summary_path), false) =
+                    (options.summary_json.as_deref(), options.dry_run)
+                {
+                    summary.directories_processed = post_dirs.num_processed();
+                    summary.disabled = post_dirs.num_disabled_skipped();
+                    write_summary(summary_path, summary, post_dirs.total_stats(), run_start)
+                        .await?;
+                }
+                if options.fail_fast_after.is_some() {
+                    return Err(error.context(format!(
+                        "aborting after {} directories failed, out of {} processed",
+                        failed.len(),
+                        post_dirs.num_processed()
+                    )));
+                }
+                return Err(error);
+            }
+        };
+        summary.record(&post_dir.name, &outcome);
+        if let Some(dump_dir) = options.dump_post_json.as_deref() {
+            write_post_dump(dump_dir, &post_dir.name, &outcome.posts).await?;
+        }
+        match outcome.action {
+            SyncAction::Created => metrics.posts_created += 1,
+            SyncAction::Updated => metrics.posts_updated += 1,
+            SyncAction::Unchanged | SyncAction::Deleted | SyncAction::Skipped => {}
+        }
+
+        if !outcome.quit && !options.dry_run {
+            run_post_hook(options, &post_dir.path, &outcome).await?;
+        }
+
+        if let (Some(post_id), false) = (
+            outcome.post_id.as_deref(),
+            outcome.action == SyncAction::Deleted,
+        ) {
+            output_ids.insert(
+                post_dir.name,
+                OutputId {
+                    id: post_id.into(),
+                    url: get_post_url(post_id),
+                },
+            );
+        }
+
+        post_dirs.add_stats(outcome.stats);
+
+        if outcome.quit {
+            break;
+        }
+    }
+
+    // Dry runs would only list the posts that already exist, clobbering the file from the last real run.
+    if let (Some(output_ids_path), false) = (options.output_ids.as_deref(), options.dry_run) {
+        write_output_ids(output_ids_path, &output_ids).await?;
+    }
+
+    // Dry runs count the posts they would create or update, which monitoring would report as real.
+    if let (Some(metrics_path), false) = (options.metrics_file.as_deref(), options.dry_run) {
+        metrics.directories_processed = post_dirs.num_processed() as u64;
+        write_metrics(metrics_path, metrics, post_dirs.total_stats(), run_start).await?;
+    }
+
+    if let (Some(summary_path), false) = (options.summary_json.as_deref(), options.dry_run) {
+        summary.directories_processed = post_dirs.num_processed();
+        summary.disabled = post_dirs.num_disabled_skipped();
+        write_summary(summary_path, summary, post_dirs.total_stats(), run_start).await?;
+    }
+
+    post_dirs.finish()?;
+
+    if !failed.is_empty() {
+        eprintln!("{} directories failed to sync:", failed.len());
+        for name in failed.iter() {
+            eprintln!("  {name}");
+        }
+    }
+    ensure!(
+        failed.is_empty(),
+        "{} directories failed to sync, out of {} processed",
+        failed.len(),
+        post_dirs.num_processed()
+    );
+
+    post_dirs.print_total_stats();
+
+    Ok(())
+}
+
+/// Hash the local files of each post directory, without using the network.
+async fn exec_hash_only<A>(ctx: &SyncContext<A>) -> anyhow::Result<()> {
+    let mut post_dirs = PostDirs::new(ctx).await?;
+    while let Some(mut post_dir) = post_dirs.next().await? {
+        let cache_path = post_dir.cache_path.as_deref().context(
+            "--hash-only with --config requires a cache. Specify it with --cache or --cache-dir",
+        )?;
+
+        println!("hashing \"{}\"", post_dir.label);
+
+        let stats = hash_post(ctx, &post_dir.path, &mut post_dir.config, cache_path).await?;
+        post_dirs.add_stats(stats);
+    }
+    post_dirs.finish()?;
+    post_dirs.print_total_stats();
+
+    Ok(())
+}
+
+/// Rebuild the cache of each post directory from its remote state.
+async fn exec_refresh_cache<A>(ctx: &SyncContext<A>) -> anyhow::Result<()>
+where
+    A: Api,
+{
+    let mut post_dirs = PostDirs::new(ctx).await?;
+    while let Some(mut post_dir) = post_dirs.next().await? {
+        let cache_path = post_dir.cache_path.as_deref().context(
+            "--refresh-cache with --config requires a cache. Specify it with --cache or --cache-dir",
+        )?;
+        post_dirs.delay().await;
+
+        println!("refreshing cache of \"{}\"", post_dir.label);
+
+        let stats = refresh_cache(ctx, &post_dir.path, &mut post_dir.config, cache_path).await?;
+        post_dirs.add_stats(stats);
+    }
+    post_dirs.finish()?;
+    post_dirs.print_total_stats();
+
+    Ok(())
+}
+
+/// Handle Ctrl-C for the rest of the run.
+///
+/// The first interrupt sets the flag, so that no new directories are started,
+/// while the current directory finishes its API calls and writes its cache.
+/// A second interrupt exits immediately.
+fn spawn_interrupt_handler(interrupted: Arc<AtomicBool>) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        interrupted.store(true, Ordering::Relaxed);
+        eprintln!("interrupted, finishing the current directory before stopping. Press Ctrl-C again to exit immediately");
+
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        eprintln!("interrupted again, exiting immediately. A post that was being updated may be partially updated without a cache, and will be recovered from its update journal on the next sync");
+        std::process::exit(130);
+    });
+}
+
+/// Get the path of the cache file for a post directory.
+///
+/// If a cache dir is given, the cache file is stored there,
+/// named by the hash of the canonical post directory path.
+/// Otherwise, it is stored in the post directory.
+fn get_cache_path(
+    dir_path: &Utf8Path,
+    cache_dir: Option<&Utf8Path>,
+) -> anyhow::Result<Utf8PathBuf> {
+    let cache_dir = match cache_dir {
+        Some(cache_dir) => cache_dir,
+        None => return Ok(dir_path.join(".imgchest-sync-cache.toml")),
+    };
+
+    let dir_path = dir_path
+        .canonicalize_utf8()
+        .with_context(|| format!("failed to canonicalize \"{dir_path}\""))?;
+    let hash = hash_bytes(dir_path.as_str().as_bytes());
+
+    Ok(cache_dir.join(format!("{hash}.toml")))
+}
+
+/// Get the url of a post.
+fn get_post_url(post_id: &str) -> String {
+    format!("https://imgchest.com/p/{post_id}")
+}
+
+/// Write the ids of synced posts, keyed by directory name.
+///
+/// The file is json if it ends in ".json", and toml otherwise.
+async fn write_output_ids(
+    path: &Utf8Path,
+    output_ids: &BTreeMap<String, OutputId>,
+) -> anyhow::Result<()> {
+    let raw = match path.extension() {
+        Some("json") => serde_json::to_string_pretty(output_ids)?,
+        _ => toml::to_string(output_ids)?,
+    };
+
+    crate::util::write_string_safe(path, &raw)
+        .await
+        .context("failed to write output ids file")?;
+
+    Ok(())
+}
+
+/// Write the metrics of a run, stamped with the current time.
+async fn write_metrics(
+    path: &Utf8Path,
+    mut metrics: Metrics,
+    total_stats: &Stats,
+    run_start: Instant,
+) -> anyhow::Result<()> {
+    metrics.timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    metrics.duration = run_start.elapsed();
+    metrics.bytes_uploaded = total_stats.bytes_uploaded;
+
+    crate::util::write_string_safe(path, &metrics.to_prometheus())
+        .await
+        .context("failed to write metrics file")?;
+
+    Ok(())
+}
+
+/// Write the summary of a run as json.
+async fn write_summary(
+    path: &Utf8Path,
+    mut summary: RunSummary,
+    total_stats: &Stats,
+    run_start: Instant,
+) -> anyhow::Result<()> {
+    summary.duration_seconds = run_start.elapsed().as_secs_f64();
+    summary.bytes_uploaded = total_stats.bytes_uploaded;
+
+    let raw = serde_json::to_string_pretty(&summary)?;
+    crate::util::write_string_safe(path, &raw)
+        .await
+        .context("failed to write summary file")?;
+
+    Ok(())
+}
+
+/// Write the synced posts of a directory to "<name>.json" in the dump directory.
+///
+/// A post is written as an object, while the posts of an album are written as an array.
+/// Nothing is written if no posts were synced.
+async fn write_post_dump(dump_dir: &Utf8Path, name: &str, posts: &[Post]) -> anyhow::Result<()> {
+    let raw = match posts {
+        [] => return Ok(()),
+        [post] => serde_json::to_string_pretty(post)?,
+        posts => serde_json::to_string_pretty(posts)?,
+    };
+
+    tokio::fs::create_dir_all(dump_dir)
+        .await
+        .context("failed to create post dump directory")?;
+    crate::util::write_string_safe(&dump_dir.join(format!("{name}.json")), &raw)
+        .await
+        .context("failed to write post dump")?;
+
+    Ok(())
+}
+
+/// Run the post hook for a synced post, if one was given.
+///
+/// Skipped posts were not synced, so the hook is not run for them.
+/// Hook failures are only warnings, unless --hook-must-succeed was given.
+async fn run_post_hook(
+    options: &Options,
+    dir_path: &Utf8Path,
+    outcome: &SyncOutcome,
+) -> anyhow::Result<()> {
+    let post_hook = match options.post_hook.as_deref() {
+        Some(post_hook) if outcome.action != SyncAction::Skipped => post_hook,
+        _ => return Ok(()),
+    };
+
+    let (shell, shell_arg) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut command = std::process::Command::new(shell);
+    command
+        .arg(shell_arg)
+        .arg(post_hook)
+        .current_dir(dir_path)
+        .stdin(std::process::Stdio::null())
+        .env("IMGCHEST_SYNC_DIR", dir_path)
+        .env(
+            "IMGCHEST_SYNC_DIR_NAME",
+            dir_path.file_name().unwrap_or_default(),
+        )
+        .env("IMGCHEST_SYNC_ACTION", outcome.action.as_str());
+    if let Some(post_id) = outcome.post_id.as_deref() {
+        command
+            .env("IMGCHEST_SYNC_POST_ID", post_id)
+            .env("IMGCHEST_SYNC_POST_URL", get_post_url(post_id));
+    }
+
+    let result = tokio::task::spawn_blocking(move || command.status())
+        .await?
+        .context("failed to run post hook")
+        .and_then(|status| {
+            ensure!(status.success(), "post hook exited with {status}");
+            Ok(())
+        });
+    match result {
+        Ok(()) => Ok(()),
+        Err(error) if options.hook_must_succeed => Err(error),
+        Err(error) => {
+            eprintln!("  warning: {error:#}");
+            Ok(())
+        }
+    }
+}
+
+/// Hash the local files of a post, storing the hashes in the hash cache next to the given cache path.
+///
+/// The posts of an album are hashed separately, into the hash caches next to their own caches.
+/// This does not use the network.
+async fn hash_post<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    config: &mut Config,
+    cache_path: &Utf8Path,
+) -> anyhow::Result<Stats> {
+    let mut stats = Stats::new();
+
+    let post_config = config.post_mut();
+    if !post_config.album() {
+        let num_files =
+            hash_post_files(ctx, dir_path, &post_config, cache_path, &mut stats).await?;
+        println!("  hashed {num_files} files");
+
+        return Ok(stats);
+    }
+
+    let chunks = get_album_chunks(&ctx.options, dir_path, &post_config)?;
+    let num_chunks = chunks.len();
+    for (i, mut chunk_config) in chunks.into_iter().enumerate() {
+        let chunk_cache_path = get_album_cache_path(cache_path, i);
+        let num_files = hash_post_files(
+            ctx,
+            dir_path,
+            &chunk_config.post_mut(),
+            &chunk_cache_path,
+            &mut stats,
+        )
+        .await?;
+        println!(
+            "  hashed {num_files} files of album post {}/{num_chunks}",
+            i + 1
+        );
+    }
+
+    Ok(stats)
+}
+
+/// Hash the local files of a single post into the hash cache next to the given cache path,
+/// returning the number of files hashed.
+async fn hash_post_files<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    post_config: &PostConfig<'_>,
+    cache_path: &Utf8Path,
+    stats: &mut Stats,
+) -> anyhow::Result<usize> {
+    let options = &ctx.options;
+
+    let hash_cache_path = get_hash_cache_path(cache_path);
+    let mut hash_cache = match options.no_read_cache {
+        false => HashCache::load(&hash_cache_path).await?.unwrap_or_default(),
+        true => HashCache::default(),
+    };
+
+    let timer = Timer::start();
+    let (post, _, _) = create_post_from_post_config(
+        dir_path,
+        post_config,
+        options,
+        ctx.default_privacy,
+        ctx.nsfw_marker,
+        &ctx.hasher,
+        Some(&mut hash_cache),
+    )
+    .await?;
+    timer.stop(&mut stats.hash_time);
+
+    hash_cache.save(&hash_cache_path).await?;
+
+    Ok(post.files.len())
+}
+
+/// Rebuild the cache of a post from its remote state, without syncing it.
+///
+/// The posts of an album each get their own cache rebuilt.
+async fn refresh_cache<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    config: &mut Config,
+    cache_path: &Utf8Path,
+) -> anyhow::Result<Stats>
+where
+    A: Api,
+{
+    let mut stats = Stats::new();
+
+    let post_config = config.post_mut();
+    if !post_config.album() {
+        match refresh_post_cache(ctx, &post_config, cache_path, &mut stats).await? {
+            Some(num_files) => println!("  refreshed cache with {num_files} files"),
+            None => println!("  post has no id, skipping"),
+        }
+
+        return Ok(stats);
+    }
+
+    let chunks = get_album_chunks(&ctx.options, dir_path, &post_config)?;
+    let num_chunks = chunks.len();
+    for (i, mut chunk_config) in chunks.into_iter().enumerate() {
+        let chunk_cache_path = get_album_cache_path(cache_path, i);
+        let album_post = format!("album post {}/{num_chunks}", i + 1);
+        match refresh_post_cache(ctx, &chunk_config.post_mut(), &chunk_cache_path, &mut stats)
+            .await?
+        {
+            Some(num_files) => {
+                println!("  refreshed cache of {album_post} with {num_files} files");
+            }
+            None => println!("  {album_post} has no id, skipping"),
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Rebuild the cache of a single post from its remote state,
+/// returning the number of files of the post, or `None` if it has no id.
+async fn refresh_post_cache<A>(
+    ctx: &SyncContext<A>,
+    post_config: &PostConfig<'_>,
+    cache_path: &Utf8Path,
+    stats: &mut Stats,
+) -> anyhow::Result<Option<usize>>
+where
+    A: Api,
+{
+    let options = &ctx.options;
+
+    let id = match post_config.id() {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let client = get_post_client(ctx, post_config)?;
+    let client = &*client;
+
+    let timer = Timer::start();
+    let post = create_post_from_online(client, id, options.api_base.as_deref(), &ctx.http_headers)
+        .await
+        .context("failed to create post from online")?;
+    timer.stop(&mut stats.download_time);
+    stats.api_calls += 1;
+
+    let num_files = post.files.len();
+    let cache = Cache {
+        diff_version: DIFF_VERSION,
+        post,
+    };
+    write_cache(cache_path, &cache).await?;
+
+    Ok(Some(num_files))
+}
+
+/// Prepare a local post for diffing against an old post, returning the options to diff them with.
+///
+/// With --order-mode set, the local files are anchored to the old order.
+/// Drafts keep the old privacy until they are published.
+fn prepare_post_diff(
+    options: &Options,
+    old_post: &Post,
+    new_post: &mut Post,
+    draft: bool,
+) -> DiffOptions {
+    let diff_options = get_diff_options(options);
+    if options.order_mode == OrderMode::Set {
+        anchor_post_order(old_post, new_post, diff_options);
+    }
+    if draft {
+        new_post.privacy = old_post.privacy;
+    }
+
+    diff_options
+}
+
+/// Diff a local post against an old post, to report what syncing it would change.
+///
+/// Unreadable files keep their old copy, like when syncing.
+fn diff_local_post(
+    options: &Options,
+    old_post: &Post,
+    new_post: &mut Post,
+    unreadable: &[UnreadableFile],
+    draft: bool,
+) -> anyhow::Result<Vec<PostDiff>> {
+    retain_unreadable_files(old_post, new_post, unreadable);
+    let diff_options = prepare_post_diff(options, old_post, new_post, draft);
+
+    generate_post_diffs(old_post, new_post, diff_options).context("failed to generate post diffs")
+}
+
+/// Get the options for diffing posts.
+fn get_diff_options(options: &Options) -> DiffOptions {
+    // Without descriptions, every new description is empty,
+    // so keeping remote empty descriptions leaves every remote description as it is.
+    DiffOptions {
+        keep_remote_empty_descriptions: options.keep_remote_empty_descriptions
+            || options.no_descriptions,
+        clear_descriptions: options.clear_descriptions,
+    }
+}
+
+/// Sync a post, given its config.
+///
+/// If `config_path` is `None`, the updated config is printed to stdout instead.
+/// If `cache_path` is `None`, no cache is read or written.
+///
+/// Returns the stats for this sync, and whether the user asked to stop.
+async fn sync_post<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    config: &mut Config,
+    config_path: Option<&Utf8Path>,
+    cache_path: Option<&Utf8Path>,
+) -> anyhow::Result<SyncOutcome>
+where
+    A: Api,
+{
+    if !config.post_mut().enabled() {
+        println!("  post is disabled, skipping");
+        return Ok(SyncOutcome {
+            stats: Stats::new(),
+            quit: false,
+            action: SyncAction::Skipped,
+            post_id: None,
+            posts: Vec::new(),
+        });
+    }
+
+    if config.post_mut().album() {
+        return sync_album(ctx, dir_path, config, config_path.into(), cache_path).await;
+    }
+
+    sync_single_post(ctx, dir_path, config, config_path.into(), cache_path).await
+}
+
+/// Sync an album, which is split into a post for each chunk of its files.
+///
+/// Each post is synced and cached separately.
+async fn sync_album<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    config: &mut Config,
+    config_target: ConfigTarget<'_>,
+    cache_path: Option<&Utf8Path>,
+) -> anyhow::Result<SyncOutcome>
+where
+    A: Api,
+{
+    let options = &ctx.options;
+    let mut stats = Stats::new();
+
+    let post_config = config.post_mut();
+    let mut ids: Vec<String> = post_config.ids().into_iter().map(String::from).collect();
+
+    if post_config.deleted() {
+        if ids.is_empty() {
+            println!("  album is marked as deleted, skipping");
+            return Ok(SyncOutcome {
+                stats,
+                quit: false,
+                action: SyncAction::Skipped,
+                post_id: None,
+                posts: Vec::new(),
+            });
+        }
+
+        if options.dry_run {
+            println!("  would delete {} album posts", ids.len());
+            return Ok(SyncOutcome {
+                stats,
+                quit: false,
+                action: SyncAction::Skipped,
+                post_id: ids.first().cloned(),
+                posts: Vec::new(),
+            });
+        }
+
+        ensure!(
+            options.yes,
+            "album is marked as deleted. Pass --yes to confirm deleting its {} posts",
+            ids.len()
+        );
+
+        let post_id = ids.first().cloned();
+        delete_album_posts(ctx, config, config_target, cache_path, &mut ids, &mut stats).await?;
+
+        return Ok(SyncOutcome {
+            stats,
+            quit: false,
+            action: SyncAction::Deleted,
+            post_id,
+            posts: Vec::new(),
+        });
+    }
+
+    // Every post is deleted before any post is created again,
+    // so the album is recreated like a new album.
+    if options.force_recreate && !ids.is_empty() {
+        delete_album_posts(ctx, config, config_target, cache_path, &mut ids, &mut stats).await?;
+    }
+
+    let chunks = get_album_chunks(options, dir_path, &config.post_mut())?;
+    let num_chunks = chunks.len();
+    if ids.len() > num_chunks && !options.yes {
+        eprintln!(
+            "  album only needs {num_chunks} posts, so {} posts are unused. They keep their old files and titles until the album grows. Pass --yes to delete them",
+            ids.len() - num_chunks
+        );
+    }
+
+    let mut action = SyncAction::Unchanged;
+    let mut quit = false;
+    let mut synced_all = true;
+    let mut posts = Vec::new();
+    for (i, mut chunk_config) in chunks.into_iter().enumerate() {
+        println!("  syncing album post {}/{num_chunks}", i + 1);
+
+        let chunk_cache_path = cache_path.map(|cache_path| get_album_cache_path(cache_path, i));
+        let outcome = sync_single_post(
+            ctx,
+            dir_path,
+            &mut chunk_config,
+            ConfigTarget::Discard,
+            chunk_cache_path.as_deref(),
+        )
+        .await?;
+        stats += outcome.stats;
+        action = merge_album_actions(action, outcome.action);
+        posts.extend(outcome.posts);
+
+        // A post created with --create-as makes the whole album a draft.
+        if chunk_config.post_mut().draft() && !config.post_mut().draft() {
+            config.post_mut().set_draft(true);
+            write_config(config, config_target).await?;
+        }
+
+        match outcome.post_id {
+            Some(post_id) if ids.get(i) != Some(&post_id) => {
+                // Chunks are synced in order, so new posts are always appended.
+                if i < ids.len() {
+                    ids[i] = post_id;
+                } else {
+                    ids.push(post_id);
+                }
+                config.post_mut().set_ids(&ids);
+                write_config(config, config_target).await?;
+            }
+            Some(_) => {}
+            None if i >= ids.len() && !options.dry_run => {
+                eprintln!(
+                    "  album post {} was not created, so later posts are skipped",
+                    i + 1
+                );
+                synced_all = false;
+                break;
+            }
+            None => {}
+        }
+
+        if outcome.quit {
+            quit = true;
+            synced_all = false;
+            break;
+        }
+    }
+
+    // Unused posts are only deleted once every used post is synced,
+    // since their files were moved into the used posts.
+    if ids.len() > num_chunks && options.yes && synced_all {
+        if options.dry_run {
+            println!(
+                "  would delete {} unused album posts",
+                ids.len() - num_chunks
+            );
+        } else {
+            let client = get_post_client(ctx, &config.post_mut())?;
+            while ids.len() > num_chunks {
+                let id = ids.last().context("missing album post id")?;
+                println!("  deleting unused album post \"{id}\"");
+                let timer = Timer::start();
+                client
+                    .delete_post(id)
+                    .await
+                    .context("failed to delete post")?;
+                timer.stop(&mut stats.upload_time);
+                stats.api_calls += 1;
+
+                if let Some(cache_path) = cache_path {
+                    remove_cache_file(&get_album_cache_path(cache_path, ids.len() - 1)).await?;
+                }
+                ids.pop();
+                config.post_mut().set_ids(&ids);
+                write_config(config, config_target).await?;
+            }
+            action = merge_album_actions(action, SyncAction::Updated);
+        }
+    }
+
+    Ok(SyncOutcome {
+        stats,
+        quit,
+        action,
+        post_id: ids.into_iter().next(),
+        posts,
+    })
+}
+
+/// Delete every post of an album, removing their ids from the config and their caches.
+///
+/// Posts are deleted from the back and the config is written after each one,
+/// so that a failed delete does not leave deleted posts in the config.
+async fn delete_album_posts<A>(
+    ctx: &SyncContext<A>,
+    config: &mut Config,
+    config_target: ConfigTarget<'_>,
+    cache_path: Option<&Utf8Path>,
+    ids: &mut Vec<String>,
+    stats: &mut Stats,
+) -> anyhow::Result<()>
+where
+    A: Api,
+{
+    let client = get_post_client(ctx, &config.post_mut())?;
+    let client = &*client;
+    while let Some(id) = ids.last() {
+        println!("  deleting post \"{id}\"");
+        let timer = Timer::start();
+        client
+            .delete_post(id)
+            .await
+            .context("failed to delete post")?;
+        timer.stop(&mut stats.upload_time);
+        stats.api_calls += 1;
+
+        ids.pop();
+        config.post_mut().set_ids(ids);
+        write_config(config, config_target).await?;
+
+        if let Some(cache_path) = cache_path {
+            remove_cache_file(&get_album_cache_path(cache_path, ids.len())).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Split an album into a post config for each of its posts, titled as they are synced.
+fn get_album_chunks(
+    options: &Options,
+    dir_path: &Utf8Path,
+    post_config: &PostConfig<'_>,
+) -> anyhow::Result<Vec<Config>> {
+    let title = match post_config.title() {
+        Some(title) => String::from(title),
+        None => dir_path.file_name().context("missing dir name")?.into(),
+    };
+    let chunk_size = post_config
+        .album_chunk_size()
+        .unwrap_or(DEFAULT_ALBUM_CHUNK_SIZE);
+    let mut chunks = post_config.album_chunks(&title, chunk_size);
+    ensure!(!chunks.is_empty(), "album has no enabled files");
+
+    // Shorten the title before the " (k/n)" suffix is added, so that the suffix is not cut off.
+    if let (Some(max_length), true) = (options.max_title_length, options.truncate_titles) {
+        let num_chunks = chunks.len();
+        if num_chunks > 1 {
+            let suffix_length = format!(" ({num_chunks}/{num_chunks})").len();
+            ensure!(
+                max_length > suffix_length,
+                "--max-title-length is too short for the \" ({num_chunks}/{num_chunks})\" suffix of album posts"
+            );
+            let title = expand_config_value(&title, options.expand_env)
+                .context("failed to expand title")?;
+            let title = fit_title(dir_path, title, max_length - suffix_length, true)?;
+            chunks = post_config.album_chunks(&title, chunk_size);
+        }
+    }
+
+    Ok(chunks)
+}
+
+/// Combine the actions of the posts of an album into one action for the album.
+fn merge_album_actions(a: SyncAction, b: SyncAction) -> SyncAction {
+    let rank = |action: SyncAction| match action {
+        SyncAction::Unchanged => 0,
+        SyncAction::Skipped => 1,
+        SyncAction::Updated => 2,
+        SyncAction::Created => 3,
+        SyncAction::Deleted => 4,
+    };
+
+    if rank(b) > rank(a) {
+        b
+    } else {
+        a
+    }
+}
+
+/// Get the path of the cache file of a post of an album, given the path of the album cache.
+fn get_album_cache_path(cache_path: &Utf8Path, index: usize) -> Utf8PathBuf {
+    cache_path.with_extension(format!("{}.toml", index + 1))
+}
+
+/// Check that a token can modify posts, by rewriting the privacy of a post to its current value.
+///
+/// This only runs with --verify-token-scope, and only once per account.
+/// New posts are not probed, as creating them is the first write.
+async fn verify_token_scope<A>(
+    ctx: &SyncContext<A>,
+    client: &A,
+    account: Option<&str>,
+    id: &str,
+    stats: &mut Stats,
+) -> anyhow::Result<()>
+where
+    A: Api,
+{
+    if !ctx.options.verify_token_scope {
+        return Ok(());
+    }
+
+    let account = account.map(String::from);
+    match ctx.verified_accounts.lock().unwrap().get(&account) {
+        Some(Ok(())) => return Ok(()),
+        Some(Err(error)) => bail!("{error}"),
+        None => {}
+    }
+
+    let remote_post = client
+        .get_post(id)
+        .await
+        .context("failed to get post to verify token scope")?;
+    stats.api_calls += 1;
+
+    let update = PostUpdate {
+        privacy: Some(remote_post.privacy),
+        ..PostUpdate::default()
+    };
+    let result = client.update_post(id, update).await.with_context(|| {
+        let account = match account.as_deref() {
+            Some(account) => format!("the token for account \"{account}\""),
+            None => String::from("the default token"),
+        };
+        format!("{account} cannot modify posts. Probing post \"{id}\" failed")
+    });
+    stats.api_calls += 1;
+
+    // Failures are remembered too, so a read-only token is not probed again for every folder.
+    let cached = result
+        .as_ref()
+        .map(|_| ())
+        .map_err(|error| format!("{error:#}"));
+    ctx.verified_accounts
+        .lock()
+        .unwrap()
+        .insert(account, cached);
+
+    result
+}
+
+/// The imgchest client of a post.
+enum PostClient<'a, A> {
+    /// A client shared by all posts.
+    Shared(&'a A),
+
+    /// A client made for a keyring credential.
+    Credential(A),
+}
+
+impl<A> std::ops::Deref for PostClient<'_, A> {
+    type Target = A;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Shared(client) => client,
+            Self::Credential(client) => client,
+        }
+    }
+}
+
+/// Get the imgchest client for the account or credential of a post.
+fn get_post_client<'a, A>(
+    ctx: &'a SyncContext<A>,
+    post_config: &PostConfig<'_>,
+) -> anyhow::Result<PostClient<'a, A>>
+where
+    A: Api,
+{
+    if let Some(credential) = post_config.credential() {
+        let mut credential_tokens = ctx.credential_tokens.lock().unwrap();
+        let token = match credential_tokens.get(credential) {
+            Some(token) => token,
+            None => {
+                let token = get_credential_token(credential)?;
+                credential_tokens
+                    .entry(String::from(credential))
+                    .or_insert(token)
+            }
+        };
+        return Ok(PostClient::Credential(ctx.client.with_token(token)));
+    }
+
+    match post_config.account() {
+        Some(account) => ctx
+            .account_clients
+            .get(account)
+            .map(PostClient::Shared)
+            .with_context(|| format!("missing token for account \"{account}\" in user config")),
+        None => Ok(PostClient::Shared(&ctx.client)),
+    }
+}
+
+/// Collect the keyring credentials of the post configs of --config, or the folders of --input.
+///
+/// A config read from stdin is skipped, as is any folder without a config.
+async fn collect_post_credentials(options: &Options) -> anyhow::Result<BTreeSet<String>> {
+    let mut config_paths = Vec::new();
+    match (options.config.as_deref(), options.input.as_deref()) {
+        (Some(config_arg), _) => {
+            if config_arg != "-" {
+                config_paths.push(config_arg.to_path_buf());
+            }
+        }
+        (None, Some(input)) => {
+            let mut dir_iter = tokio::fs::read_dir(input).await?;
+            while let Some(entry) = dir_iter.next_entry().await? {
+                if !entry.file_type().await?.is_dir() {
+                    continue;
+                }
+                let entry_path = Utf8PathBuf::try_from(entry.path())?;
+                config_paths.push(entry_path.join("imgchest-sync.toml"));
+            }
+        }
+        (None, None) => {}
+    }
+
+    let mut credentials = BTreeSet::new();
+    for config_path in config_paths {
+        let Some(config_raw) = crate::util::try_read_to_string(&config_path)
+            .await
+            .with_context(|| format!("failed to read \"{config_path}\""))?
+        else {
+            continue;
+        };
+        let mut config = Config::new(&config_raw)
+            .with_context(|| format!("failed to parse \"{config_path}\""))?;
+        if let Some(credential) = config.post_mut().credential() {
+            credentials.insert(String::from(credential));
+        }
+    }
+
+    Ok(credentials)
+}
+
+/// Read the token of a credential, of the form "<service>:<user>", from the keyring.
+fn get_credential_token(credential: &str) -> anyhow::Result<String> {
+    let (service, user) = credential
+        .split_once(':')
+        .context("credential is not of the form \"<service>:<user>\"")?;
+    let entry = keyring::Entry::new(service, user)
+        .with_context(|| format!("failed to open keyring entry for credential \"{credential}\""))?;
+
+    match entry.get_password() {
+        Ok(token) => Ok(token),
+        Err(keyring::Error::NoEntry) => {
+            bail!("credential \"{credential}\" was not found in the keyring")
+        }
+        Err(error) => Err(error).with_context(|| {
+            format!("failed to read credential \"{credential}\" from the keyring")
+        }),
+    }
+}
+
+/// Sync a single post, given its config.
+///
+/// See `sync_post`.
+async fn sync_single_post<A>(
+    ctx: &SyncContext<A>,
+    dir_path: &Utf8Path,
+    config: &mut Config,
+    config_target: ConfigTarget<'_>,
+    cache_path: Option<&Utf8Path>,
+) -> anyhow::Result<SyncOutcome>
+where
+    A: Api,
+{
+    let options = &ctx.options;
+    let mut stats = Stats::new();
+
+    let mut cache = None;
+    if let (false, Some(cache_path)) = (options.no_read_cache, cache_path) {
+        cache = match crate::util::try_read_to_string(cache_path)
+            .await
+            .context("failed to read cache file")?
+        {
+            Some(cache_raw) => {
+                match toml::from_str::<Cache>(&cache_raw).context("failed to parse cache file") {
+                    Ok(cache) if cache.diff_version != DIFF_VERSION => {
+                        eprintln!("  cache was made with a different version of the diff logic, ignoring it");
+                        None
+                    }
+                    Ok(cache) => Some(cache),
+                    Err(error) => {
+                        eprintln!("  {error:?}");
+                        // Dry runs must never destroy the cache.
+                        if options.purge_cache_on_mismatch && !options.dry_run {
+                            purge_cache(cache_path, options.backup_purged_cache).await?;
+                        }
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+    }
+
+    let mut post_config = config.post_mut();
+    let mut config_dirty = false;
+
+    let client = get_post_client(ctx, &post_config)?;
+    let client = &*client;
+    let account = post_config
+        .account()
+        .or(post_config.credential())
+        .map(String::from);
+
+    match plan_post_action(&post_config) {
+        PostAction::Sync => {}
+        PostAction::Skip => {
+            println!("  post is marked as deleted, skipping");
+            return Ok(SyncOutcome {
+                stats,
+                quit: false,
+                action: SyncAction::Skipped,
+                post_id: None,
+                posts: Vec::new(),
+            });
+        }
+        PostAction::Delete { id } if options.dry_run => {
+            println!("  would delete post \"{id}\"");
+            return Ok(SyncOutcome {
+                stats,
+                quit: false,
+                action: SyncAction::Skipped,
+                post_id: Some(id),
+                posts: Vec::new(),
+            });
+        }
+        PostAction::Delete { id } => {
+            ensure!(
+                options.yes,
+                "post \"{id}\" is marked as deleted. Pass --yes to confirm deleting it"
+            );
+
+            verify_token_scope(ctx, client, account.as_deref(), &id, &mut stats).await?;
+
+            println!("  deleting post \"{id}\"");
+            let timer = Timer::start();
+            client
+                .delete_post(&id)
+                .await
+                .context("failed to delete post")?;
+            timer.stop(&mut stats.upload_time);
+            stats.api_calls += 1;
+
+            post_config.set_id(None);
+            write_config(config, config_target).await?;
+
+            // The cache describes a post that no longer exists.
+            if let Some(cache_path) = cache_path {
+                remove_cache_file(cache_path).await?;
+            }
+
+            return Ok(SyncOutcome {
+                stats,
+                quit: false,
+                action: SyncAction::Deleted,
+                post_id: Some(id),
+                posts: Vec::new(),
+            });
+        }
+    }
+
+    if options.persist_overrides {
+        if let Some(privacy) = options.privacy_override {
+            if post_config.privacy() != Some(privacy) {
+                post_config.set_privacy(Some(privacy));
+                config_dirty = true;
+            }
+        }
+    }
+
+    if options.metadata_only && post_config.id().is_none() {
+        eprintln!("  post has no id, and --metadata-only cannot create posts. Skipping...");
+        return Ok(SyncOutcome {
+            stats,
+            quit: false,
+            action: SyncAction::Skipped,
+            post_id: None,
+            posts: Vec::new(),
+        });
+    }
+
+    // The hash cache is only used if it exists, which can be done with --hash-only.
+    let hash_cache_path = cache_path.map(get_hash_cache_path);
+    let mut hash_cache = match (
+        options.no_read_cache || options.metadata_only,
+        hash_cache_path.as_deref(),
+    ) {
+        (false, Some(hash_cache_path)) => HashCache::load(hash_cache_path).await?,
+        _ => None,
+    };
+
+    // With --concurrent-hash-and-upload, files are hashed one batch at a time,
+    // so every file is checked here, before anything is uploaded.
+    if !options.metadata_only {
+        let paths = resolve_post_file_paths(dir_path, &post_config, options)?;
+        check_duplicate_files(&paths, options).await?;
+    }
+
+    // New posts upload every file, so nothing is lost by uploading before every file is hashed.
+    // Only the first batch is hashed here, and the rest are hashed while earlier batches upload.
+    let mut pending_batches = Vec::new();
+    if options.concurrent_hash_and_upload
+        && post_config.id().is_none()
+        && !options.dry_run
+        && !options.metadata_only
+    {
+        pending_batches = post_config.file_batches(CREATE_BATCH_SIZE);
+    }
+    let mut first_batch = (!pending_batches.is_empty()).then(|| pending_batches.remove(0));
+
+    let timer = Timer::start();
+    let (mut new_post, mut hash_cache_dirty, unreadable) =
+        if let Some(first_batch) = first_batch.as_mut() {
+            create_post_from_post_config(
+                dir_path,
+                &first_batch.post_mut(),
+                options,
+                ctx.default_privacy,
+                ctx.nsfw_marker,
+                &ctx.hasher,
+                hash_cache.as_mut(),
+            )
+            .await?
+        } else if options.metadata_only {
+            // The files are copied from the old post before diffing.
+            let (title, privacy, nsfw) = create_post_metadata_from_post_config(
+                dir_path,
+                &post_config,
+                options,
+                ctx.default_privacy,
+                ctx.nsfw_marker,
+            )
+            .await?;
+            let post = Post {
+                title,
+                privacy,
+                nsfw,
+                files: Vec::new(),
+            };
+            (post, false, Vec::new())
+        } else {
+            create_post_from_post_config(
+                dir_path,
+                &post_config,
+                options,
+                ctx.default_privacy,
+                ctx.nsfw_marker,
+                &ctx.hasher,
+                hash_cache.as_mut(),
+            )
+            .await?
+        };
+    timer.stop(&mut stats.hash_time);
+
+    if let (Some(hash_cache), Some(hash_cache_path), true, false) = (
+        hash_cache.as_ref(),
+        hash_cache_path.as_deref(),
+        hash_cache_dirty,
+        options.dry_run,
+    ) {
+        hash_cache.save(hash_cache_path).await?;
+    }
+
+    if let Some(max_images) = options.max_images {
+        let num_images = new_post.files.len()
+            + pending_batches
+                .iter_mut()
+                .map(|batch| batch.post_mut().files().len())
+                .sum::<usize>();
+        if num_images > max_images {
+            eprintln!("  post has {num_images} images, which is more than the max of {max_images}. Skipping...");
+            return Ok(SyncOutcome {
+                stats,
+                quit: false,
+                action: SyncAction::Skipped,
+                post_id: post_config.id().map(String::from),
+                posts: Vec::new(),
+            });
+        }
+    }
+
+    // A journal is newer than any cache, as the cache is removed when an update starts.
+    let mut recovered = false;
+    if let (false, Some(cache_path), Some(id)) =
+        (options.no_read_cache, cache_path, post_config.id())
+    {
+        if let Some(journal_cache) =
+            recover_from_journal(client, id, cache_path, &mut stats).await?
+        {
+            cache = Some(journal_cache);
+            recovered = true;
+        }
+    }
+
+    if options.force_recreate {
+        if let Some(id) = post_config.id().map(String::from) {
+            verify_token_scope(ctx, client, account.as_deref(), &id, &mut stats).await?;
+
+            println!("  deleting post \"{id}\"");
+            let timer = Timer::start();
+            client
+                .delete_post(&id)
+                .await
+                .context("failed to delete post")?;
+            timer.stop(&mut stats.upload_time);
+            stats.api_calls += 1;
+            post_config.set_id(None);
+            cache = None;
+
+            // Persist the removed id now.
+            // If recreating the post fails, the config should not refer to a deleted post.
+            write_config(config, config_target).await?;
+            post_config = config.post_mut();
+        }
+    }
+
+    let mut no_changes = false;
+    let action;
+    match post_config.id() {
+        Some(id) => {
+            // The cache is still written after syncing against the remote post.
+            if options.diff_base == DiffBase::Remote {
+                cache = None;
+            }
+
+            let online_post;
+            let mut drifted_remote_post = None;
+            let old_post = match cache.as_ref() {
+                Some(cache) => {
+                    if options.check_remote {
+                        let timer = Timer::start();
+                        let remote_post = client
+                            .get_post(id)
+                            .await
+                            .context("failed to get remote post")?;
+                        timer.stop(&mut stats.download_time);
+                        stats.api_calls += 1;
+
+                        if warn_remote_drift(&cache.post, &remote_post) {
+                            drifted_remote_post = Some(remote_post);
+                        }
+                    }
+
+                    &cache.post
+                }
+                None if options.diff_base == DiffBase::Cache => {
+                    bail!("post \"{id}\" has no cache, which --diff-base cache requires. Sync it with --diff-base auto to rebuild the cache");
+                }
+                None if options.trust_config => {
+                    eprintln!("  no cache, trusting that the remote files match the config. Diffs may be incorrect if the remote post was edited externally");
+                    let timer = Timer::start();
+                    let post = create_post_from_online_trusted(client, id, &new_post)
+                        .await
+                        .context("failed to create post from online")?;
+                    timer.stop(&mut stats.download_time);
+                    stats.api_calls += 1;
+
+                    online_post = post;
+                    &online_post
+                }
+                None => {
+                    let timer = Timer::start();
+                    let post = create_post_from_online(
+                        client,
+                        id,
+                        options.api_base.as_deref(),
+                        &ctx.http_headers,
+                    )
+                    .await
+                    .context("failed to create post from online")?;
+                    timer.stop(&mut stats.download_time);
+                    stats.api_calls += 1;
+
+                    online_post = post;
+                    &online_post
+                }
+            };
+
+            retain_unreadable_files(old_post, &mut new_post, &unreadable);
+
+            if let Some(files) = options.files.as_deref() {
+                let file_names: Vec<&str> = files
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|file_name| !file_name.is_empty())
+                    .collect();
+                retain_unlisted_files(old_post, &mut new_post, &file_names);
+            }
+
+            if let Some(seed) = options.randomize_order {
+                shuffle_post_files(&mut new_post, seed);
+            }
+
+            if options.metadata_only {
+                new_post.files = old_post.files.clone();
+            }
+
+            let diff_options =
+                prepare_post_diff(options, old_post, &mut new_post, post_config.draft());
+
+            let diffs = generate_post_diffs(old_post, &new_post, diff_options)
+                .context("failed to generate post diffs")?;
+            ensure!(
+                !options.metadata_only
+                    || diffs.iter().all(|diff| matches!(
+                        diff,
+                        PostDiff::EditTitle { .. }
+                            | PostDiff::EditPrivacy { .. }
+                            | PostDiff::EditNsfw { .. }
+                            | PostDiff::RetainFile { .. }
+                    )),
+                "--metadata-only generated file diffs"
+            );
+            let diff_empty = diffs
+                .iter()
+                .all(|diff| matches!(diff, PostDiff::RetainFile { .. }));
+
+            if let (false, Some(remote_post)) = (diff_empty, drifted_remote_post) {
+                match options.on_conflict.unwrap_or(ConflictPolicy::Abort) {
+                    ConflictPolicy::Abort => {
+                        eprintln!("  both the local config and the remote post changed since the cache. Pass --on-conflict to choose which wins. Skipping...");
+                        return Ok(SyncOutcome {
+                            stats,
+                            quit: false,
+                            action: SyncAction::Skipped,
+                            post_id: Some(id.into()),
+                            posts: Vec::new(),
+                        });
+                    }
+                    ConflictPolicy::LocalWins => {
+                        eprintln!("  both the local config and the remote post changed since the cache, overwriting the remote post");
+                    }
+                    ConflictPolicy::RemoteWins => {
+                        let post_id = String::from(id);
+                        if options.dry_run {
+                            println!("  both the local config and the remote post changed since the cache, would pull the remote post into the config and cache. Skipping...");
+                        } else {
+                            println!("  both the local config and the remote post changed since the cache, pulling the remote post into the config and cache. Skipping...");
+                            let mut post = old_post.clone();
+                            post.title = remote_post.title.clone().unwrap_or_default();
+                            post.privacy = remote_post.privacy;
+                            post.nsfw = remote_post.nsfw;
+                            let cache = Cache {
+                                diff_version: DIFF_VERSION,
+                                post,
+                            };
+
+                            // Without this, the next sync would see the local config as changed and overwrite the remote post.
+                            post_config.set_title(remote_post.title.as_deref());
+                            if !post_config.draft() {
+                                post_config
+                                    .set_privacy(Some(convert_post_privacy(remote_post.privacy)));
+                            }
+                            post_config.set_nsfw(Some(remote_post.nsfw));
+
+                            // The cache was read, so there is a cache path.
+                            write_config_and_cache(
+                                Some((config, config_target)),
+                                cache_path.map(|cache_path| (cache_path, &cache)),
+                            )
+                            .await?;
+                        }
+                        return Ok(SyncOutcome {
+                            stats,
+                            quit: false,
+                            action: SyncAction::Skipped,
+                            post_id: Some(post_id),
+                            posts: Vec::new(),
+                        });
+                    }
+                }
+            }
+
+            // Only the drop in the number of files counts,
+            // so reordering files never looks like a large removal.
+            let num_removed = old_post.files.len().saturating_sub(new_post.files.len());
+            if !options.allow_large_removals
+                && is_large_removal(
+                    old_post.files.len(),
+                    num_removed,
+                    options.max_removal_percent,
+                )
+            {
+                eprintln!(
+                    "  post would remove {num_removed} of its {} files, which is more than the max of {}%. Pass --allow-large-removals to proceed. Skipping...",
+                    old_post.files.len(),
+                    options.max_removal_percent
+                );
+                return Ok(SyncOutcome {
+                    stats,
+                    quit: false,
+                    action: SyncAction::Skipped,
+                    post_id: Some(id.into()),
+                    posts: Vec::new(),
+                });
+            }
+
+            let exact_dry_run = options.dry_run && options.diff_base == DiffBase::Remote;
+            if options.print_diffs || ((options.interactive || exact_dry_run) && !diff_empty) {
+                println!("  diffs: [");
+                for diff in diffs.iter() {
+                    println!("    {diff:?},");
+                }
+                println!("  ]");
+            }
+
+            let plan = plan_online_post_update(diffs, old_post, &mut new_post)?;
+            if (options.print_plan || options.dry_run) && !diff_empty {
+                let calls = plan.describe_calls(&new_post);
+                println!("  plan ({} api calls): [", calls.len());
+                for call in calls.iter() {
+                    println!("    {call},");
+                }
+                println!("  ]");
+            }
+
+            if options.interactive && !diff_empty {
+                loop {
+                    let answer = crate::util::prompt("  apply, skip, or quit? [a/s/q] ").await?;
+                    match answer.trim() {
+                        "a" | "apply" => break,
+                        "s" | "skip" => {
+                            println!("  skipping");
+                            return Ok(SyncOutcome {
+                                stats,
+                                quit: false,
+                                action: SyncAction::Skipped,
+                                post_id: Some(id.into()),
+                                posts: Vec::new(),
+                            });
+                        }
+                        "q" | "quit" => {
+                            return Ok(SyncOutcome {
+                                stats,
+                                quit: true,
+                                action: SyncAction::Skipped,
+                                post_id: Some(id.into()),
+                                posts: Vec::new(),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if !diff_empty && options.dry_run {
+                // The cache is only removed once an update starts, so it survives dry runs.
+                println!("  would update post");
+                return Ok(SyncOutcome {
+                    stats,
+                    quit: false,
+                    action: SyncAction::Skipped,
+                    post_id: Some(id.into()),
+                    posts: Vec::new(),
+                });
+            }
+
+            if !diff_empty {
+                verify_token_scope(ctx, client, account.as_deref(), id, &mut stats).await?;
+
+                println!("  updating post");
+                action = SyncAction::Updated;
+                let timer = Timer::start();
+                stats +=
+                    update_online_post(ctx, client, id, plan, old_post, &mut new_post, cache_path)
+                        .await?;
+                timer.stop(&mut stats.upload_time);
+            } else {
+                println!("  no changes");
+
+                // Copy file ids, and any kept remote descriptions
+                for (new_file, old_file) in new_post.files.iter_mut().zip(old_post.files.iter()) {
+                    let id = old_file.id.as_ref().context("missing old id")?.clone();
+                    new_file.id = Some(id);
+                    if new_file.description.is_empty() {
+                        new_file.description = old_file.description.clone();
+                    }
+                }
+
+                no_changes = true;
+                action = SyncAction::Unchanged;
+            }
+        }
+        None if options.dry_run => {
+            println!("  would create post with {} files", new_post.files.len());
+            return Ok(SyncOutcome {
+                stats,
+                quit: false,
+                action: SyncAction::Skipped,
+                post_id: None,
+                posts: Vec::new(),
+            });
+        }
+        None => {
+            if let Some(privacy) = options.create_as {
+                new_post.privacy = convert_config_privacy(privacy);
+                post_config.set_draft(true);
+            }
+
+            let timer = Timer::start();
+            println!("  creating new post");
+            let mut pending_batches = pending_batches.into_iter();
+            let mut imgchest_post: Option<RemotePost> = None;
+            let mut batch_start = 0;
+            while batch_start < new_post.files.len() {
+                let batch_end =
+                    std::cmp::min(batch_start + CREATE_BATCH_SIZE, new_post.files.len());
+                let next_batch = pending_batches.next();
+                let has_next_batch = next_batch.is_some();
+
+                let upload = upload_new_post_batch(
+                    ctx,
+                    client,
+                    imgchest_post.as_ref(),
+                    &mut new_post,
+                    batch_start..batch_end,
+                    &mut stats,
+                );
+                let mut hash_stats = Stats::new();
+                let hash = async {
+                    let timer = Timer::start();
+                    let result = match next_batch {
+                        Some(mut next_batch) => create_post_from_post_config(
+                            dir_path,
+                            &next_batch.post_mut(),
+                            options,
+                            ctx.default_privacy,
+                            ctx.nsfw_marker,
+                            &ctx.hasher,
+                            hash_cache.as_mut(),
+                        )
+                        .await
+                        .map(Some),
+                        None => Ok(None),
+                    };
+                    timer.stop(&mut hash_stats.hash_time);
+
+                    result
+                };
+                let (batch_post, next_post) = futures_util::future::join(upload, hash).await;
+                stats += hash_stats;
+                let batch_post = batch_post?;
+
+                // Persist the uploaded files before the next batch.
+                // If a later batch fails, the next sync diffs against this cache and only uploads the missing files.
+                if batch_end < new_post.files.len() || has_next_batch {
+                    post_config.set_id(Some(&batch_post.id));
+                    let cache = cache_path
+                        .map(|cache_path| (cache_path, partial_cache(&new_post, &batch_post)));
+                    write_config_and_cache(
+                        Some((config, config_target)),
+                        cache
+                            .as_ref()
+                            .map(|(cache_path, cache)| (*cache_path, cache)),
+                    )
+                    .await?;
+                    post_config = config.post_mut();
+                }
+
+                imgchest_post = Some(batch_post);
+
+                if let Some((next_post, next_hash_cache_dirty, _)) = next_post? {
+                    new_post.files.extend(next_post.files);
+                    hash_cache_dirty |= next_hash_cache_dirty;
+                }
+                batch_start = batch_end;
+            }
+            let imgchest_post = imgchest_post.context("missing new post")?;
+
+            if let (Some(hash_cache), Some(hash_cache_path), true) = (
+                hash_cache.as_ref(),
+                hash_cache_path.as_deref(),
+                hash_cache_dirty,
+            ) {
+                hash_cache.save(hash_cache_path).await?;
+            }
+
+            // Set descriptions
+            ensure!(new_post.files.len() == imgchest_post.images.len());
+            let description_updates = generate_description_updates(
+                &new_post.files,
+                imgchest_post.images.iter().map(|image| {
+                    (
+                        image.id.as_str(),
+                        image.description.as_deref().unwrap_or(""),
+                    )
+                }),
+            );
+            if !description_updates.is_empty() {
+                client
+                    .update_files_bulk(description_updates)
+                    .await
+                    .context("failed to set file descriptions")?;
+                stats.api_calls += 1;
+            }
+            timer.stop(&mut stats.upload_time);
+
+            post_config.set_id(Some(&imgchest_post.id));
+            action = SyncAction::Created;
+
+            ensure!(imgchest_post.images.len() == new_post.files.len());
+            for (file, imgchest_image) in new_post.files.iter_mut().zip(imgchest_post.images) {
+                file.id = Some(imgchest_image.id);
+            }
+
+            config_dirty = true;
+        }
+    }
+
+    let post_id = post_config.id().map(String::from);
+
+    if options.dry_run {
+        return Ok(SyncOutcome {
+            stats,
+            quit: false,
+            action,
+            post_id,
+            posts: Vec::new(),
+        });
+    }
+
+    let mut posts = Vec::new();
+    if options.dump_post_json.is_some() {
+        posts.push(new_post.clone());
+    }
+
+    // The config and cache are written together, so the cache does not go stale next to a new config.
+    let cache = match cache_path {
+        Some(cache_path) if !(cache.is_some() && no_changes) || recovered => {
+            let cache = match cache {
+                Some(mut cache) => {
+                    cache.post = new_post;
+                    cache
+                }
+                None => Cache {
+                    diff_version: DIFF_VERSION,
+                    post: new_post,
+                },
+            };
+
+            Some((cache_path, cache))
+        }
+        _ => None,
+    };
+    write_config_and_cache(
+        config_dirty.then_some((&*config, config_target)),
+        cache
+            .as_ref()
+            .map(|(cache_path, cache)| (*cache_path, cache)),
+    )
+    .await?;
+
+    Ok(SyncOutcome {
+        stats,
+        quit: false,
+        action,
+        post_id,
+        posts,
+    })
+}
+
+/// Upload a batch of the files of a new post.
+///
+/// The first batch creates the post, and later batches are added to the post made by the first batch.
+/// Returns the remote post after the batch is uploaded.
+async fn upload_new_post_batch<A>(
+    ctx: &SyncContext<A>,
+    client: &A,
+    imgchest_post: Option<&RemotePost>,
+    new_post: &mut Post,
+    batch: std::ops::Range<usize>,
+    stats: &mut Stats,
+) -> anyhow::Result<RemotePost>
+where
+    A: Api,
+{
+    let mut files = Vec::with_capacity(batch.len());
+    let mut total_size = 0;
+    for file in new_post.files[batch.clone()].iter_mut() {
+        let (file, size) = open_upload_file(file).await?;
+        total_size += size;
+
+        files.push(file);
+    }
+    stats.bytes_uploaded += total_size;
+
+    if let Some(upload_limiter) = ctx.upload_limiter.as_ref() {
+        upload_limiter.acquire(total_size).await;
+    }
+    let batch_post = match imgchest_post {
+        None => {
+            let create_post = NewPost {
+                title: new_post.title.clone(),
+                privacy: new_post.privacy,
+                nsfw: new_post.nsfw,
+                files,
+            };
+            client
+                .create_post(create_post)
+                .await
+                .context("failed to create new post")?
+        }
+        Some(imgchest_post) => {
+            println!("  adding files {}-{}", batch.start + 1, batch.end);
+            client
+                .add_post_images(&imgchest_post.id, files)
+                .await
+                .context("failed to add files to new post")?
+        }
+    };
+    stats.api_calls += 1;
+    ensure!(batch_post.images.len() == batch.end);
+
+    Ok(batch_post)
+}
+
+/// Make a cache for a post that was only partly created.
+///
+/// The cached post has the files that were uploaded, with the descriptions that they have remotely.
+fn partial_cache(new_post: &Post, remote_post: &RemotePost) -> Cache {
+    let files = new_post
+        .files
+        .iter()
+        .zip(remote_post.images.iter())
+        .map(|(file, image)| PostFile {
+            description: image.description.clone().unwrap_or_default(),
+            sha256: file.sha256.clone(),
+            id: Some(image.id.clone()),
+            path: file.path.clone(),
+            data: None,
+            orient: false,
+            upload_name: None,
+        })
+        .collect();
+    Cache {
+        diff_version: DIFF_VERSION,
+        post: Post {
+            title: new_post.title.clone(),
+            privacy: new_post.privacy,
+            nsfw: new_post.nsfw,
+            files,
+        },
+    }
+}
+
+/// Check whether removing the given number of files removes more than the given percent of the old files.
+fn is_large_removal(old_len: usize, num_removed: usize, max_percent: u8) -> bool {
+    num_removed * 100 > old_len * usize::from(max_percent)
+}
+
+/// An action to take for a post, before syncing it.
+#[derive(Debug, PartialEq, Eq)]
+enum PostAction {
+    /// Sync the post as normal.
+    Sync,
+
+    /// The post is marked as deleted, but was never created or is already deleted.
+    Skip,
+
+    /// The post is marked as deleted and should be deleted online.
+    Delete { id: String },
+}
+
+/// Determine what to do with a post, based on its config.
+fn plan_post_action(post_config: &PostConfig) -> PostAction {
+    if !post_config.deleted() {
+        return PostAction::Sync;
+    }
+
+    match post_config.id() {
+        Some(id) => PostAction::Delete { id: id.into() },
+        None => PostAction::Skip,
+    }
+}
+
+/// Get a config value, expanding environment variable references if enabled.
+fn expand_config_value(value: &str, expand_env: bool) -> Result<String, SyncError> {
+    if expand_env {
+        crate::util::expand_env(value)
+    } else {
+        Ok(value.into())
+    }
+}
+
+/// Remove a cache file and its update journal, if they exist.
+async fn remove_cache_file(cache_path: &Utf8Path) -> anyhow::Result<()> {
+    match tokio::fs::remove_file(cache_path).await {
+        Ok(()) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error).context("failed to remove cache file"),
+    }
+
+    remove_journal(cache_path).await
+}
+
+/// Remove the update journal of a cache file, if it exists.
+async fn remove_journal(cache_path: &Utf8Path) -> anyhow::Result<()> {
+    match tokio::fs::remove_file(get_journal_path(cache_path)).await {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error).context("failed to remove update journal"),
+    }
+}
+
+/// Recover the state of a post from the journal of an interrupted update, if there is one.
+///
+/// This only needs to get the remote post, instead of downloading every remote file.
+/// Returns `None` if there is no journal, or if it does not match the remote post.
+async fn recover_from_journal<A>(
+    client: &A,
+    id: &str,
+    cache_path: &Utf8Path,
+    stats: &mut Stats,
+) -> anyhow::Result<Option<Cache>>
+where
+    A: Api,
+{
+    let journal = match Journal::load(&get_journal_path(cache_path)).await {
+        Ok(Some(journal)) => journal,
+        Ok(None) => return Ok(None),
+        Err(error) => {
+            eprintln!("  {error:?}");
+            return Ok(None);
+        }
+    };
+    if journal.diff_version != DIFF_VERSION || journal.post_id != id {
+        eprintln!(
+            "  update journal is for a different post or version of the diff logic, ignoring it"
+        );
+        return Ok(None);
+    }
+
+    let remote_post = client
+        .get_post(id)
+        .await
+        .context("failed to get post to recover interrupted update")?;
+    stats.api_calls += 1;
+
+    match journal.reconcile(&remote_post) {
+        Some(post) => {
+            println!("  recovered the post from the journal of an interrupted update");
+            Ok(Some(Cache {
+                diff_version: DIFF_VERSION,
+                post,
+            }))
+        }
+        None => {
+            eprintln!("  update journal does not match the remote post, ignoring it");
+            Ok(None)
+        }
+    }
+}
+
+/// Remove a cache file that could not be parsed, optionally backing it up.
+async fn purge_cache(cache_path: &Utf8Path, backup: bool) -> anyhow::Result<()> {
+    if backup {
+        let backup_path = nd_util::with_push_extension(cache_path, "bak");
+        tokio::fs::rename(cache_path, &backup_path)
+            .await
+            .context("failed to back up cache file")?;
+        eprintln!("  moved cache file to \"{}\"", backup_path.display());
+    } else {
+        tokio::fs::remove_file(cache_path)
+            .await
+            .context("failed to remove cache file")?;
+        eprintln!("  removed cache file");
+    }
+
+    Ok(())
+}
+
+/// Format a cache file.
+fn format_cache(cache: &Cache) -> anyhow::Result<String> {
+    let mut cache_str = String::new();
+    cache_str.push_str("# This file was autogenerated by imgchest-sync.\n");
+    cache_str.push_str("# DO NOT EDIT.\n");
+    cache_str.push('\n');
+    cache_str += &toml::to_string(cache)?;
+
+    Ok(cache_str)
+}
+
+/// Write a cache file.
+async fn write_cache(cache_path: &Utf8Path, cache: &Cache) -> anyhow::Result<()> {
+    let cache_str = format_cache(cache)?;
+    crate::util::write_string_safe(cache_path, &cache_str)
+        .await
+        .context("failed to write new cache")?;
+
+    // The cache is up to date, so the journal of any interrupted update is stale.
+    remove_journal(cache_path).await
+}
+
+/// Where an updated post config is written.
+#[derive(Debug, Clone, Copy)]
+enum ConfigTarget<'a> {
+    /// Write it to a file.
+    File(&'a Utf8Path),
+
+    /// Print it to stdout.
+    Stdout,
+
+    /// Drop it, as the caller persists any changes itself.
+    Discard,
+}
+
+impl<'a> From<Option<&'a Utf8Path>> for ConfigTarget<'a> {
+    fn from(config_path: Option<&'a Utf8Path>) -> Self {
+        match config_path {
+            Some(config_path) => Self::File(config_path),
+            None => Self::Stdout,
+        }
+    }
+}
+
+/// Write a config to the given target.
+async fn write_config(config: &Config, config_target: ConfigTarget<'_>) -> anyhow::Result<()> {
+    match config_target {
+        ConfigTarget::File(config_path) => {
+            crate::util::write_string_safe(config_path, &config.to_string())
+                .await
+                .context("failed to write new config")?;
+        }
+        ConfigTarget::Stdout => {
+            println!("  updated config:");
+            print!("{config}");
+        }
+        ConfigTarget::Discard => {}
+    }
+
+    Ok(())
+}
+
+/// Write a config and a cache together, if given.
+///
+/// If the config goes to a file, both files are renamed into place only after both are written.
+/// The cache is renamed first, and the config is written even if the cache fails,
+/// since a config with a new post id but no cache is safe:
+/// the cache is rebuilt from the remote post, while a lost id makes the next sync create a duplicate post.
+async fn write_config_and_cache(
+    config: Option<(&Config, ConfigTarget<'_>)>,
+    cache: Option<(&Utf8Path, &Cache)>,
+) -> anyhow::Result<()> {
+    let mut files = Vec::with_capacity(2);
+
+    let cache_str;
+    if let Some((cache_path, cache)) = cache {
+        cache_str = format_cache(cache)?;
+        files.push((cache_path.as_std_path(), cache_str.as_str()));
+    }
+
+    let config_file = match config {
+        Some((config, ConfigTarget::File(config_path))) => Some((config_path, config.to_string())),
+        Some((config, config_target)) => {
+            write_config(config, config_target).await?;
+            None
+        }
+        None => None,
+    };
+    if let Some((config_path, config_str)) = config_file.as_ref() {
+        files.push((config_path.as_std_path(), config_str.as_str()));
+    }
+
+    if let Err(error) = crate::util::write_many_safe(&files).await {
+        // Fall back to a config without a cache, so the config never loses a new post id.
+        if let (Some((config_path, config_str)), Some((cache_path, _cache))) =
+            (config_file.as_ref(), cache)
+        {
+            // This is best-effort, as the config must be written either way.
+            let _ = tokio::fs::remove_file(cache_path).await;
+            crate::util::write_string_safe(config_path, config_str)
+                .await
+                .context("failed to write new config")?;
+        }
+
+        return Err(error).context("failed to write new config and cache");
+    }
+
+    // The cache is up to date, so the journal of any interrupted update is stale.
+    if let Some((cache_path, _cache)) = cache {
+        remove_journal(cache_path).await?;
+    }
+
+    Ok(())
+}
+
+/// Get the title, privacy, and nsfw of a post from a post config, without touching its files.
+async fn create_post_metadata_from_post_config(
+    dir_path: &Utf8Path,
+    post_config: &PostConfig<'_>,
+    options: &Options,
+    default_privacy: PostConfigPrivacy,
+    nsfw_marker: bool,
+) -> anyhow::Result<(String, PostPrivacy, bool)> {
+    let dir_name = dir_path.file_name().context("missing dir name")?;
+
+    let title = match post_config.title() {
+        Some(title) => {
+            expand_config_value(title, options.expand_env).context("failed to expand title")?
+        }
+        None => dir_name.into(),
+    };
+    let title = match options.max_title_length {
+        Some(max_length) => fit_title(dir_path, title, max_length, options.truncate_titles)?,
+        None => title,
+    };
+    let privacy = options
+        .privacy_override
+        .or_else(|| post_config.privacy())
+        .unwrap_or(default_privacy);
+    let privacy = convert_config_privacy(privacy);
+    // An explicit config value takes precedence over the marker.
+    let nsfw = match post_config.nsfw() {
+        Some(nsfw) => nsfw,
+        None if nsfw_marker => tokio::fs::try_exists(dir_path.join(".nsfw"))
+            .await
+            .context("failed to check for nsfw marker")?,
+        None => false,
+    };
+
+    Ok((title, privacy, nsfw))
+}
+
+/// A file that was left out of a post, either with --skip-unreadable or because it is generated in a subcommand.
+#[derive(Debug)]
+struct UnreadableFile {
+    /// The index of the file among the enabled files of the post config with allowed extensions.
+    index: usize,
+
+    /// The path of the file, if it is not generated.
+    path: Option<Utf8PathBuf>,
+}
+
+/// Create a post from a post config, hashing the local files.
+///
+/// If a hash cache is given, unchanged files use their cached hash and new hashes are added to it.
+/// Files missing from the hash cache are then looked up in the hash database, if given.
+/// Returns the post, whether the hash cache changed, and the files that were left out.
+/// These are the files skipped with --skip-unreadable, and generated files in subcommands.
+async fn create_post_from_post_config(
+    dir_path: &Utf8Path,
+    post_config: &PostConfig<'_>,
+    options: &Options,
+    default_privacy: PostConfigPrivacy,
+    nsfw_marker: bool,
+    hasher: &Hasher,
+    hash_cache: Option<&mut HashCache>,
+) -> anyhow::Result<(Post, bool, Vec<UnreadableFile>)> {
+    let allowed_extensions = get_allowed_extensions(options, post_config);
+
+    let (title, privacy, nsfw) = create_post_metadata_from_post_config(
+        dir_path,
+        post_config,
+        options,
+        default_privacy,
+        nsfw_marker,
+    )
+    .await?;
+    let canonical_dir_path = match options.allow_path_escape {
+        true => None,
+        false => Some(
+            tokio::fs::canonicalize(dir_path)
+                .await
+                .context("failed to canonicalize post directory")?,
+        ),
+    };
+    let mut hash_cache_dirty = false;
+    let mut unreadable = Vec::new();
+    let files = {
+        let files_config = post_config.files();
+
+        let mut futures = Vec::with_capacity(files_config.len());
+        let mut file_paths = Vec::with_capacity(files_config.len());
+        let mut content_types = Vec::with_capacity(files_config.len());
+        let mut hash_cache_inserts = Vec::new();
+        let mut skipped_generated = Vec::new();
+        for file in files_config.iter() {
+            if file.disabled() {
+                continue;
+            }
+
+            // Files without an allowed extension are left out of the post entirely,
+            // like sidecar descriptions or .DS_Store files.
+            let file_label = match file.command() {
+                Some(_) => String::from(file.name().context("missing generated file name")?),
+                None => {
+                    expand_config_value(file.path().context("missing path")?, options.expand_env)
+                        .context("failed to expand path")?
+                }
+            };
+            if !is_upload_extension_allowed(
+                Utf8Path::new(&file_label),
+                file.content_type(),
+                allowed_extensions.as_deref(),
+            ) {
+                println!("  note: \"{file_label}\" does not have an allowed extension, so it is left out");
+                continue;
+            }
+            content_types.push(file.content_type());
+
+            let (tx, rx) = tokio::sync::oneshot::channel();
+
+            let description = match (options.no_descriptions, file.description()) {
+                (true, _) => String::new(),
+                (false, Some(description)) => expand_config_value(description, options.expand_env)
+                    .context("failed to expand description")?,
+                (false, None) => match (options.description_from, file.path().or(file.name())) {
+                    (Some(description_from), Some(file_label)) => {
+                        let file_label = expand_config_value(file_label, options.expand_env)
+                            .context("failed to expand path")?;
+                        let file_stem = Utf8Path::new(&file_label).file_stem().unwrap_or("");
+                        match description_from {
+                            DescriptionSource::Filename => file_stem.into(),
+                            DescriptionSource::HumanizedFilename => humanize_file_stem(file_stem),
+                        }
+                    }
+                    _ => String::new(),
+                },
+            };
+            if let Some(max_length) = options.max_description_length {
+                let file_label = file.path().or(file.name()).unwrap_or_default();
+                ensure_description_length(file_label, &description, max_length)?;
+            }
+            let id = file.id().map(String::from);
+
+            if let Some(command) = file.command() {
+                let file_name = file.name().context("missing generated file name")?;
+                // A post config may come from anyone who can write to the input directory,
+                // so its commands only run when asked for, and never in read-only subcommands.
+                // Subcommands leave the file out like an unreadable file, so the old copy is kept when diffing.
+                if options.subcommand.is_some() {
+                    println!("  note: \"{file_name}\" is generated by a command, which subcommands do not run, so it is left out");
+                    skipped_generated.push(futures.len());
+                    let _ = tx.send(Err(anyhow::anyhow!("\"{file_name}\" was not generated")));
+                    futures.push(rx);
+                    file_paths.push(None);
+                    continue;
+                }
+                ensure!(
+                    options.allow_file_commands,
+                    "\"{file_name}\" is generated by a command, which only runs with --allow-file-commands"
+                );
+
+                let file_name = String::from(file_name);
+                let command: Vec<String> = command.into_iter().map(String::from).collect();
+                let dir_path = dir_path.to_path_buf();
+                hasher.pool.spawn(move || {
+                    let result = run_file_command(&dir_path, &command)
+                        .with_context(|| format!("failed to generate \"{file_name}\""))
+                        .map(|bytes| PostFile {
+                            description,
+                            sha256: hash_bytes(&bytes),
+                            path: None,
+                            id,
+                            data: Some(PostFileData { file_name, bytes }),
+                            orient: false,
+                            upload_name: None,
+                        });
+
+                    let _ = tx.send(result).is_ok();
+                });
+
+                futures.push(rx);
+                file_paths.push(None);
+                continue;
+            }
+
+            let path_key = file_label;
+            let path = Utf8Path::new(&path_key);
+            if let (true, Some(canonical_dir_path)) =
+                (path.is_relative(), canonical_dir_path.as_deref())
+            {
+                ensure_path_in_dir(canonical_dir_path, path).await?;
+            }
+            let path = resolve_file_path(dir_path, &path_key);
+
+            file_paths.push(Some(path.clone()));
+
+            if let Some(max_gif_size) = options.max_gif_size {
+                if let Err(error) = ensure_animated_gif_size(&path, max_gif_size).await {
+                    let _ = tx.send(Err(error));
+                    futures.push(rx);
+                    continue;
+                }
+            }
+
+            if hash_cache.is_some() {
+                let metadata = match tokio::fs::metadata(&path)
+                    .await
+                    .with_context(|| format!("failed to get metadata for \"{path}\""))
+                {
+                    Ok(metadata) => metadata,
+                    Err(error) => {
+                        let _ = tx.send(Err(error));
+                        futures.push(rx);
+                        continue;
+                    }
+                };
+                let cached = hash_cache.as_deref().and_then(|hash_cache| {
+                    hash_cache.get(&path_key, &metadata, options.auto_orient)
+                });
+                if let Some((sha256, orient)) = cached {
+                    let _ = tx.send(Ok(PostFile {
+                        description,
+                        sha256: sha256.into(),
+                        path: Some(path),
+                        id,
+                        data: None,
+                        orient,
+                        upload_name: None,
+                    }));
+                    futures.push(rx);
+                    continue;
+                }
+
+                hash_cache_inserts.push((futures.len(), path_key.clone(), metadata));
+            }
+
+            let auto_orient = options.auto_orient;
+            let hash_db = hasher.db.clone();
+            let read_hash_db = !options.no_read_cache;
+            let write_hash_db = !options.dry_run && options.subcommand.is_none();
+            hasher.pool.spawn(move || {
+                let hash = match hash_db.as_deref() {
+                    Some(hash_db) => hash_local_file_with_db(
+                        hash_db,
+                        &path,
+                        auto_orient,
+                        read_hash_db,
+                        write_hash_db,
+                    ),
+                    None => hash_local_file(&path, auto_orient),
+                };
+                let result = hash.map(|(sha256, orient)| PostFile {
+                    description,
+                    sha256,
+                    path: Some(path),
+                    id,
+                    data: None,
+                    orient,
+                    upload_name: None,
+                });
+
+                let _ = tx.send(result).is_ok();
+            });
+
+            futures.push(rx);
+        }
+
+        // Wait for every file, so that all failures are reported at once.
+        let mut results = Vec::with_capacity(futures.len());
+        for future in futures {
+            let result = future
+                .await
+                .context("file task exited without a result")
+                .and_then(|result| result);
+            results.push(result);
+        }
+
+        if let Some(hash_cache) = hash_cache {
+            for (index, path_key, metadata) in hash_cache_inserts {
+                if let Ok(file) = &results[index] {
+                    let sha256 = file.sha256.clone();
+                    let oriented = options.auto_orient.then_some(file.orient);
+                    hash_cache_dirty |= hash_cache.insert(path_key, &metadata, sha256, oriented);
+                }
+            }
+        }
+
+        for (result, content_type) in results.iter_mut().zip(content_types) {
+            if let (Ok(file), Some(content_type)) = (result.as_mut(), content_type) {
+                if let Err(error) = apply_content_type(file, content_type) {
+                    *result = Err(error);
+                }
+            }
+        }
+
+        let mut files = Vec::with_capacity(results.len());
+        let mut errors = Vec::new();
+        for (index, (result, path)) in results.into_iter().zip(file_paths).enumerate() {
+            match result {
+                Ok(file) => files.push(file),
+                Err(_) if skipped_generated.contains(&index) => {
+                    unreadable.push(UnreadableFile { index, path });
+                }
+                Err(error) => {
+                    errors.push(error);
+                    unreadable.push(UnreadableFile { index, path });
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            if options.skip_unreadable {
+                for error in errors.iter() {
+                    eprintln!("  skipping unreadable file: {error:#}");
+                }
+            } else {
+                let mut message = format!("failed to read {} files:", errors.len());
+                for error in errors.iter() {
+                    message.push_str(&format!("\n  {error:#}"));
+                }
+                bail!(message);
+            }
+        }
+        ensure!(
+            !files.is_empty() || !skipped_generated.is_empty(),
+            "every file of the post is disabled, unreadable, or without an allowed extension"
+        );
+
+        files
+    };
+
+    Ok((
+        Post {
+            title,
+            privacy,
+            nsfw,
+            files,
+        },
+        hash_cache_dirty,
+        unreadable,
+    ))
+}
+
+/// Make a file upload under a name with an extension matching its content type,
+/// since imgchest goes by the extension.
+///
+/// Files on disk with a mismatched extension are given an upload name,
+/// which is only used when they are uploaded.
+fn apply_content_type(
+    file: &mut PostFile,
+    content_type: PostConfigContentType,
+) -> anyhow::Result<()> {
+    let file_name = Utf8Path::new(upload_file_name(file)?);
+    let upload_name = match content_type_file_name(file_name, content_type) {
+        Some(upload_name) => upload_name.into_string(),
+        None => return Ok(()),
+    };
+
+    match file.data.as_mut() {
+        Some(data) => {
+            data.file_name = upload_name;
+        }
+        None => {
+            file.upload_name = Some(upload_name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the name that a file is uploaded under for its content type,
+/// if its extension does not match the content type.
+fn content_type_file_name(
+    file_name: &Utf8Path,
+    content_type: PostConfigContentType,
+) -> Option<Utf8PathBuf> {
+    let extensions = content_type.extensions();
+    let extension = file_name.extension().map(str::to_ascii_lowercase);
+    if extension.is_some_and(|extension| extensions.contains(&extension.as_str())) {
+        return None;
+    }
+
+    Some(file_name.with_extension(extensions[0]))
+}
+
+/// Get the name that a post file is uploaded under.
+fn upload_file_name(file: &PostFile) -> anyhow::Result<&str> {
+    match (
+        file.data.as_ref(),
+        file.upload_name.as_deref(),
+        file.path.as_deref(),
+    ) {
+        (Some(data), _, _) => Ok(&data.file_name),
+        (None, Some(upload_name), _) => Ok(upload_name),
+        (None, None, Some(path)) => path.file_name().context("missing file name"),
+        (None, None, None) => bail!("missing path"),
+    }
+}
+
+/// Turn a file stem into a caption, by replacing underscores with spaces and using title case.
+fn humanize_file_stem(file_stem: &str) -> String {
+    let mut output = String::with_capacity(file_stem.len());
+    for word in file_stem.split(|c: char| c == '_' || c.is_whitespace()) {
+        let mut chars = word.chars();
+        let first = match chars.next() {
+            Some(first) => first,
+            None => continue,
+        };
+
+        if !output.is_empty() {
+            output.push(' ');
+        }
+        output.extend(first.to_uppercase());
+        output.push_str(chars.as_str());
+    }
+
+    output
+}
+
+/// Resolve a file path of a post config against the post directory.
+fn resolve_file_path(dir_path: &Utf8Path, path: &str) -> Utf8PathBuf {
+    let path = Utf8Path::new(path);
+    if path.is_relative() {
+        normalize_path(&dir_path.join(path))
+    } else {
+        path.into()
+    }
+}
+
+/// Resolve the paths of the enabled files of a post config that are not generated.
+fn resolve_post_file_paths(
+    dir_path: &Utf8Path,
+    post_config: &PostConfig<'_>,
+    options: &Options,
+) -> anyhow::Result<Vec<Utf8PathBuf>> {
+    let mut paths = Vec::new();
+    for file in post_config.files().iter() {
+        if file.disabled() || file.command().is_some() {
+            continue;
+        }
+        let path = expand_config_value(file.path().context("missing path")?, options.expand_env)
+            .context("failed to expand path")?;
+        paths.push(resolve_file_path(dir_path, &path));
+    }
+
+    Ok(paths)
+}
+
+/// Warn about file paths that may be the same file, failing instead with --strict.
+///
+/// This only needs the paths, so it runs before any file is hashed or uploaded.
+async fn check_duplicate_files(paths: &[Utf8PathBuf], options: &Options) -> anyhow::Result<()> {
+    // On case-insensitive filesystems, these are the same file.
+    for (first, second) in find_case_collisions(paths) {
+        let message = format!("\"{first}\" and \"{second}\" only differ in case, so they may be the same file on case-insensitive filesystems");
+        ensure!(!options.strict, "{message}");
+        eprintln!("  warning: {message}");
+    }
+
+    // Entries for the same file upload it more than once, which is almost always a copy-paste mistake.
+    for (first, second) in find_duplicate_paths(paths).await {
+        let message = if first == second {
+            format!("\"{first}\" is listed more than once, so it will be uploaded more than once")
+        } else {
+            format!("\"{first}\" and \"{second}\" are the same file, so it will be uploaded more than once")
+        };
+        ensure!(!options.strict, "{message}");
+        eprintln!("  warning: {message}");
+    }
+
+    Ok(())
+}
+
+/// Find pairs of different file paths that are equal when ignoring case.
+fn find_case_collisions(paths: &[Utf8PathBuf]) -> Vec<(&Utf8Path, &Utf8Path)> {
+    let mut seen: HashMap<String, &Utf8Path> = HashMap::new();
+    let mut collisions = Vec::new();
+    for path in paths.iter().map(Utf8PathBuf::as_path) {
+        let key = path.as_str().to_lowercase();
+        match seen.get(&key) {
+            Some(first) if *first != path => collisions.push((*first, path)),
+            Some(_) => {}
+            None => {
+                seen.insert(key, path);
+            }
+        }
+    }
+
+    collisions
+}
+
+/// Find pairs of file paths that resolve to the same canonical path.
+///
+/// Paths that cannot be canonicalized are compared as they are.
+async fn find_duplicate_paths(paths: &[Utf8PathBuf]) -> Vec<(&Utf8Path, &Utf8Path)> {
+    let mut seen: HashMap<std::path::PathBuf, &Utf8Path> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for path in paths.iter().map(Utf8PathBuf::as_path) {
+        let key = tokio::fs::canonicalize(path)
+            .await
+            .unwrap_or_else(|_| path.into());
+        match seen.get(&key) {
+            Some(first) => duplicates.push((*first, path)),
+            None => {
+                seen.insert(key, path);
+            }
+        }
+    }
+
+    duplicates
+}
+
+/// Convert a post config privacy into a post privacy.
+fn convert_config_privacy(privacy: PostConfigPrivacy) -> PostPrivacy {
+    match privacy {
+        PostConfigPrivacy::Public => PostPrivacy::Public,
+        PostConfigPrivacy::Hidden => PostPrivacy::Hidden,
+        PostConfigPrivacy::Secret => PostPrivacy::Secret,
+    }
+}
+
+/// Convert a post privacy into a post config privacy.
+fn convert_post_privacy(privacy: PostPrivacy) -> PostConfigPrivacy {
+    match privacy {
+        PostPrivacy::Public => PostConfigPrivacy::Public,
+        PostPrivacy::Hidden => PostConfigPrivacy::Hidden,
+        PostPrivacy::Secret => PostConfigPrivacy::Secret,
+    }
+}
+
+/// Warn if the remote post metadata differs from the cached post.
+///
+/// Returns whether the remote post drifted.
+fn warn_remote_drift(cached_post: &Post, remote_post: &RemotePost) -> bool {
+    let mut drifted = Vec::new();
+
+    let remote_title = remote_post.title.as_deref().unwrap_or("");
+    if cached_post.title != remote_title {
+        drifted.push(format!(
+            "title: cached \"{}\", remote \"{remote_title}\"",
+            cached_post.title
+        ));
+    }
+
+    let remote_privacy = remote_post.privacy;
+    if cached_post.privacy != remote_privacy {
+        drifted.push(format!(
+            "privacy: cached {:?}, remote {remote_privacy:?}",
+            cached_post.privacy
+        ));
+    }
+
+    if cached_post.nsfw != remote_post.nsfw {
+        drifted.push(format!(
+            "nsfw: cached {}, remote {}",
+            cached_post.nsfw, remote_post.nsfw
+        ));
+    }
+
+    if drifted.is_empty() {
+        return false;
+    }
+
+    eprintln!("  warning: the remote post has changed since it was cached");
+    for drifted in drifted {
+        eprintln!("    {drifted}");
+    }
+
+    true
+}
+
+/// Get the size of the file at the given path.
+async fn file_size(path: &Utf8Path) -> anyhow::Result<u64> {
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("failed to get metadata for \"{path}\""))?;
+
+    Ok(metadata.len())
+}
+
+/// Get the file extensions allowed for a post.
+///
+/// The --allowed-extensions flag takes priority over the "extensions" key of the post config.
+/// If neither is set, every extension is allowed.
+fn get_allowed_extensions(options: &Options, post_config: &PostConfig<'_>) -> Option<Vec<String>> {
+    let extensions = match (
+        options.allowed_extensions.as_deref(),
+        post_config.extensions(),
+    ) {
+        (Some(allowed_extensions), _) => allowed_extensions.split(',').collect(),
+        (None, Some(extensions)) => extensions,
+        (None, None) => return None,
+    };
+
+    let extensions = extensions
+        .into_iter()
+        .map(|extension| {
+            extension
+                .trim()
+                .trim_start_matches('.')
+                .to_ascii_lowercase()
+        })
+        .filter(|extension| !extension.is_empty())
+        .collect();
+
+    Some(extensions)
+}
+
+/// Ensure that a description is not longer than the max length, in characters.
+fn ensure_description_length(
+    file_label: &str,
+    description: &str,
+    max_length: usize,
+) -> Result<(), SyncError> {
+    let length = description.chars().count();
+    if length > max_length {
+        return Err(SyncError::DescriptionTooLong {
+            file: file_label.into(),
+            length,
+            max_length,
+        });
+    }
+
+    Ok(())
+}
+
+/// Ensure that a title is not longer than the max length, in characters.
+///
+/// If truncate is true, long titles are shortened to the max length, ending with an ellipsis.
+fn fit_title(
+    dir_path: &Utf8Path,
+    title: String,
+    max_length: usize,
+    truncate: bool,
+) -> Result<String, SyncError> {
+    let length = title.chars().count();
+    if length <= max_length {
+        return Ok(title);
+    }
+
+    if !truncate {
+        return Err(SyncError::TitleTooLong {
+            dir: dir_path.to_string(),
+            length,
+            max_length,
+        });
+    }
+
+    let mut truncated: String = title.chars().take(max_length.saturating_sub(1)).collect();
+    truncated.push('…');
+    Ok(truncated)
+}
+
+/// Remove "." components from a path, and resolve ".." components without touching the filesystem.
+fn normalize_path(path: &Utf8Path) -> Utf8PathBuf {
+    let mut normalized = Utf8PathBuf::new();
+    for component in path.components() {
+        match component {
+            Utf8Component::CurDir => {}
+            Utf8Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Utf8Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// Ensure that a relative file path does not resolve to a path outside of a canonical post directory.
+///
+/// Symlinks are followed. Missing files are resolved without touching the filesystem.
+async fn ensure_path_in_dir(
+    canonical_dir_path: &std::path::Path,
+    path: &Utf8Path,
+) -> Result<(), SyncError> {
+    let joined = canonical_dir_path.join(path);
+    let resolved = match tokio::fs::canonicalize(&joined).await {
+        Ok(resolved) => resolved,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            match Utf8Path::from_path(&joined) {
+                Some(joined) => normalize_path(joined).into_std_path_buf(),
+                None => joined,
+            }
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    if !resolved.starts_with(canonical_dir_path) {
+        return Err(SyncError::PathEscapesDir {
+            path: path.to_string(),
+            resolved: resolved.display().to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Check whether the extension of a file is one of the allowed extensions.
+fn is_extension_allowed(path: &Utf8Path, allowed_extensions: &[String]) -> bool {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    allowed_extensions.contains(&extension)
+}
+
+/// Check the extension that a file is uploaded with,
+/// which comes from its content type if it has one.
+///
+/// Without allowed extensions, every file is allowed,
+/// but files that imgchest is not known to support get a warning,
+/// since imgchest gives a generic error for unsupported files.
+fn is_upload_extension_allowed(
+    path: &Utf8Path,
+    content_type: Option<PostConfigContentType>,
+    allowed_extensions: Option<&[String]>,
+) -> bool {
+    let upload_path = content_type
+        .and_then(|content_type| content_type_file_name(path, content_type))
+        .unwrap_or_else(|| path.to_path_buf());
+    match allowed_extensions {
+        Some(allowed_extensions) => is_extension_allowed(&upload_path, allowed_extensions),
+        None => {
+            let supported = upload_path.extension().is_some_and(|extension| {
+                SUPPORTED_EXTENSIONS
+                    .iter()
+                    .any(|supported| extension.eq_ignore_ascii_case(supported))
+            });
+            if !supported {
+                eprintln!("  warning: imgchest may not support files like \"{path}\"");
+            }
+
+            true
+        }
+    }
+}
+
+/// Ensure that a file is not an animated gif that is larger than the max size.
+///
+/// Only gifs over the max size are decoded, and only until a second frame is found.
+async fn ensure_animated_gif_size(path: &Utf8Path, max_size: u64) -> anyhow::Result<()> {
+    let is_gif = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+    if !is_gif {
+        return Ok(());
+    }
+
+    let size = file_size(path).await?;
+    if size <= max_size {
+        return Ok(());
+    }
+
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        use image::AnimationDecoder;
+
+        let file =
+            std::fs::File::open(&path).with_context(|| format!("failed to open \"{path}\""))?;
+        let decoder = image::codecs::gif::GifDecoder::new(std::io::BufReader::new(file))
+            .with_context(|| format!("failed to decode gif \"{path}\""))?;
+        let num_frames = decoder.into_frames().take(2).count();
+        ensure!(
+            num_frames < 2,
+            "\"{path}\" is an animated gif of {size} bytes, which is more than the max of {max_size}. imgchest may reject it or flatten it to a single frame"
+        );
+
+        Ok(())
+    })
+    .await?
+}
+
+/// Run a command in the given directory, returning its stdout.
+fn run_file_command(dir_path: &Utf8Path, command: &[String]) -> anyhow::Result<Vec<u8>> {
+    let (program, args) = command.split_first().context("missing command")?;
+    let output = std::process::Command::new(program)
+        .args(args)
+        .current_dir(dir_path)
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .with_context(|| format!("failed to run \"{program}\""))?;
+    ensure!(
+        output.status.success(),
+        "\"{program}\" exited with {}",
+        output.status
+    );
+    ensure!(
+        !output.stdout.is_empty(),
+        "\"{program}\" produced no output"
+    );
+
+    Ok(output.stdout)
+}
+
+/// Hash bytes, returning the sha256 as a hex string.
+fn hash_bytes(bytes: &[u8]) -> String {
+    let hash = Sha256::digest(bytes);
+    base16ct::lower::encode_string(&hash)
+}
+
+/// Open a post file for uploading.
+///
+/// Files on disk are streamed by the client, so they are never fully loaded into memory,
+/// unless they need to be reoriented.
+/// In-memory data is moved into the upload file instead of being copied,
+/// so large generated files are not held twice while many uploads are in flight.
+///
+/// Returns the upload file and its size in bytes.
+async fn open_upload_file(file: &mut PostFile) -> anyhow::Result<(imgchest::UploadPostFile, u64)> {
+    if let Some(data) = file.data.take() {
+        let size = u64::try_from(data.bytes.len())?;
+        let upload_file = imgchest::UploadPostFile::from_bytes(&data.file_name, data.bytes);
+        return Ok((upload_file, size));
+    }
+
+    let path = file.path.as_ref().context("missing path")?;
+    if file.orient {
+        let (path_clone, sha256) = (path.clone(), file.sha256.clone());
+        let bytes = tokio::task::spawn_blocking(move || read_oriented_file(&path_clone, &sha256))
+            .await
+            .context("reorient task panicked")??;
+        let size = u64::try_from(bytes.len())?;
+        let upload_file = imgchest::UploadPostFile::from_bytes(upload_file_name(file)?, bytes);
+        return Ok((upload_file, size));
+    }
+
+    let upload_file = match file.upload_name.as_deref() {
+        Some(upload_name) => {
+            let path_file = tokio::fs::File::open(path)
+                .await
+                .with_context(|| format!("failed to open \"{path}\" for upload"))?;
+            imgchest::UploadPostFile::from_file(path_file, upload_name)
+        }
+        None => imgchest::UploadPostFile::from_path(path)
+            .await
+            .with_context(|| format!("failed to open \"{path}\" for upload"))?,
+    };
+    let size = file_size(path).await?;
+
+    Ok((upload_file, size))
+}
+
+/// Hash a local file.
+///
+/// With auto_orient, images with an exif orientation are reoriented first,
+/// and the reoriented bytes are hashed instead.
+/// The reoriented bytes are not kept, so that many large images are not held in memory at once.
+/// They are made again by `read_oriented_file` if the file is uploaded.
+///
+/// Returns the hash, and whether the file was reoriented.
+fn hash_local_file(path: &Utf8Path, auto_orient: bool) -> anyhow::Result<(String, bool)> {
+    if auto_orient {
+        let bytes = orient_file_at_path(path)
+            .with_context(|| format!("failed to reorient image at \"{path}\""))?;
+        if let Some(bytes) = bytes {
+            return Ok((hash_bytes(&bytes), true));
+        }
+    }
+
+    let sha256 =
+        hash_file_at_path(path).with_context(|| format!("failed to hash file at \"{path}\""))?;
+    Ok((sha256, false))
+}
+
+/// Hash a local file like `hash_local_file`, reusing its hash from the hash database if it did not change.
+///
+/// New hashes are stored if write is true.
+/// Database errors are only warnings, so that a broken database never makes files unreadable.
+fn hash_local_file_with_db(
+    hash_db: &HashDb,
+    path: &Utf8Path,
+    auto_orient: bool,
+    read: bool,
+    write: bool,
+) -> anyhow::Result<(String, bool)> {
+    let absolute_path = std::path::absolute(path)
+        .with_context(|| format!("failed to get absolute path of \"{path}\""))?;
+    let absolute_path = Utf8PathBuf::try_from(absolute_path)?;
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("failed to get metadata for \"{path}\""))?;
+
+    if read {
+        match hash_db.get(&absolute_path, &metadata, auto_orient) {
+            Ok(Some(hash)) => return Ok(hash),
+            Ok(None) => {}
+            Err(error) => eprintln!("  warning: {error:#}"),
+        }
+    }
+
+    let (sha256, orient) = hash_local_file(path, auto_orient)?;
+    if write {
+        let oriented = auto_orient.then_some(orient);
+        if let Err(error) = hash_db.insert(&absolute_path, &metadata, &sha256, oriented) {
+            eprintln!("  warning: {error:#}");
+        }
+    }
+
+    Ok((sha256, orient))
+}
+
+/// Open the hash database, if --hash-cache-db was given.
+fn open_hash_db(options: &Options) -> anyhow::Result<Option<Arc<HashDb>>> {
+    options
+        .hash_cache_db
+        .as_deref()
+        .map(|path| HashDb::open(path).map(Arc::new))
+        .transpose()
+}
+
+/// Reorient a file that was hashed with --auto-orient, for uploading or exporting it.
+///
+/// This fails if the reoriented bytes do not match the hash,
+/// which happens if the file changed after it was hashed.
+fn read_oriented_file(path: &Utf8Path, sha256: &str) -> anyhow::Result<Vec<u8>> {
+    let bytes = orient_file_at_path(path)
+        .with_context(|| format!("failed to reorient image at \"{path}\""))?
+        .with_context(|| format!("\"{path}\" no longer needs to be reoriented"))?;
+    ensure!(
+        hash_bytes(&bytes) == sha256,
+        "\"{path}\" changed after it was hashed"
+    );
+
+    Ok(bytes)
+}
+
+/// Bake the exif orientation of an image into its pixels, re-encoding it in the same format.
+///
+/// Jpegs are re-encoded at `AUTO_ORIENT_JPEG_QUALITY`, so some quality is lost.
+/// The icc profile is kept, but the rest of the exif data is dropped.
+/// The output is deterministic, so the same file always reorients to the same hash.
+///
+/// Returns `None` if the file is not a supported image, or if it does not need to be reoriented.
+fn orient_file_at_path(path: &Utf8Path) -> anyhow::Result<Option<Vec<u8>>> {
+    use image::ImageDecoder;
+    use image::ImageEncoder;
+
+    let bytes = std::fs::read(path).with_context(|| format!("failed to read \"{path}\""))?;
+    let reader = image::ImageReader::new(std::io::Cursor::new(&bytes)).with_guessed_format()?;
+    let format = match reader.format() {
+        Some(format) => format,
+        None => return Ok(None),
+    };
+    let mut decoder = match reader.into_decoder() {
+        Ok(decoder) => decoder,
+        Err(image::ImageError::Unsupported(_)) => return Ok(None),
+        Err(error) => return Err(error).context("failed to decode image"),
+    };
+
+    let orientation = decoder
+        .orientation()
+        .context("failed to read image orientation")?;
+    if orientation == image::metadata::Orientation::NoTransforms {
+        return Ok(None);
+    }
+
+    let icc_profile = decoder
+        .icc_profile()
+        .context("failed to read image icc profile")?;
+    let mut image = image::DynamicImage::from_decoder(decoder).context("failed to decode image")?;
+    image.apply_orientation(orientation);
+
+    let mut oriented = Vec::new();
+    let mut writer = std::io::Cursor::new(&mut oriented);
+    let result = match format {
+        image::ImageFormat::Jpeg => {
+            let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(
+                writer,
+                AUTO_ORIENT_JPEG_QUALITY,
+            );
+            if let Some(icc_profile) = icc_profile {
+                encoder
+                    .set_icc_profile(icc_profile)
+                    .context("failed to set image icc profile")?;
+            }
+            image.write_with_encoder(encoder)
+        }
+        image::ImageFormat::Png => {
+            let mut encoder = image::codecs::png::PngEncoder::new(writer);
+            if let Some(icc_profile) = icc_profile {
+                encoder
+                    .set_icc_profile(icc_profile)
+                    .context("failed to set image icc profile")?;
+            }
+            image.write_with_encoder(encoder)
+        }
+        image::ImageFormat::WebP => {
+            let mut encoder = image::codecs::webp::WebPEncoder::new_lossless(writer);
+            if let Some(icc_profile) = icc_profile {
+                encoder
+                    .set_icc_profile(icc_profile)
+                    .context("failed to set image icc profile")?;
+            }
+            image.write_with_encoder(encoder)
+        }
+        _ => image.write_to(&mut writer, format),
+    };
+    result.context("failed to encode image")?;
+
+    Ok(Some(oriented))
+}
+
+fn hash_file_at_path(path: &Utf8Path) -> anyhow::Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("failed to open \"{path}\""))?;
+
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    let hash = hasher.finalize();
+    let hex_hash = base16ct::lower::encode_string(&hash);
+
+    anyhow::Ok(hex_hash)
+}
+
+async fn create_post_from_online(
+    client: &impl Api,
+    id: &str,
+    download_base: Option<&str>,
+    http_headers: &HeaderMap,
+) -> anyhow::Result<Post> {
+    let imgchest_post = client.get_post(id).await.context("failed to get post")?;
+
+    let title = imgchest_post.title.unwrap_or_else(String::new);
+    let privacy = imgchest_post.privacy;
+    let nsfw = imgchest_post.nsfw;
+    let files = {
+        let mut files = Vec::new();
+        for image in imgchest_post.images {
+            let description = image.description.unwrap_or_else(String::new);
+
+            let link = match download_base {
+                Some(download_base) => crate::util::rebase_url(&image.link, download_base),
+                None => image.link,
+            };
+
+            let handle = tokio::runtime::Handle::current();
+            let request = client
+                .http_client()
+                .get(link)
+                .headers(http_headers.clone())
+                .build()?;
+            if client.trace_http() {
+                crate::api::trace_http_request(&request);
+            }
+            let image_response = client.http_client().execute(request).await?;
+            if client.trace_http() {
+                crate::api::trace_http_response(&image_response);
+            }
+            let mut image_response = image_response.error_for_status()?;
+            let sha256 = tokio::task::spawn_blocking(move || {
+                let mut hasher = Sha256::new();
+                while let Some(chunk) = handle.block_on(image_response.chunk())? {
+                    hasher.update(chunk);
+                }
+
+                let hash = hasher.finalize();
+                let hex_hash = base16ct::lower::encode_string(&hash);
+
+                anyhow::Ok(hex_hash)
+            })
+            .await??;
+
+            files.push(PostFile {
+                description,
+                sha256,
+                path: None,
+                id: Some(image.id),
+                data: None,
+                orient: false,
+                upload_name: None,
+            });
+        }
+        files
+    };
+
+    Ok(Post {
+        title,
+        privacy,
+        nsfw,
+        files,
+    })
+}
+
+/// Create a post from the online post's metadata, without downloading any files.
+///
+/// Remote files are assumed to have the same contents as the local files at the same index.
+/// Remote files without a local counterpart get an empty hash, so they are always removed.
+async fn create_post_from_online_trusted(
+    client: &impl Api,
     id: &str,
+    local_post: &Post,
+) -> anyhow::Result<Post> {
+    let imgchest_post = client.get_post(id).await.context("failed to get post")?;
+
+    let title = imgchest_post.title.unwrap_or_else(String::new);
+    let privacy = imgchest_post.privacy;
+    let nsfw = imgchest_post.nsfw;
+    let files = imgchest_post
+        .images
+        .into_iter()
+        .enumerate()
+        .map(|(index, image)| {
+            let description = image.description.unwrap_or_else(String::new);
+            let sha256 = local_post
+                .files
+                .get(index)
+                .map(|file| file.sha256.clone())
+                .unwrap_or_else(String::new);
+
+            PostFile {
+                description,
+                sha256,
+                path: None,
+                id: Some(image.id),
+                data: None,
+                orient: false,
+                upload_name: None,
+            }
+        })
+        .collect();
+
+    Ok(Post {
+        title,
+        privacy,
+        nsfw,
+        files,
+    })
+}
+
+/// The API calls needed to update an online post, in the order they are made.
+#[derive(Debug)]
+struct UpdatePlan {
+    /// The post metadata update.
+    post_update: Option<PostUpdate>,
+
+    /// The indicies of the new post files to upload.
+    files_to_add: Vec<usize>,
+
+    /// The ids of the remote files to delete.
+    files_to_remove: Vec<String>,
+
+    /// The description updates for retained files.
+    ///
+    /// Uploaded files with descriptions are updated in the same call.
+    file_updates: Vec<imgchest::FileUpdate>,
+}
+
+impl UpdatePlan {
+    /// Describe each API call of this plan, in order.
+    fn describe_calls(&self, new_post: &Post) -> Vec<String> {
+        let mut calls = Vec::new();
+
+        if let Some(post_update) = self.post_update.as_ref() {
+            let mut fields = Vec::new();
+            if post_update.title.is_some() {
+                fields.push("title");
+            }
+            if post_update.privacy.is_some() {
+                fields.push("privacy");
+            }
+            if post_update.nsfw.is_some() {
+                fields.push("nsfw");
+            }
+            calls.push(format!("update_post ({})", fields.join(", ")));
+        }
+
+        if !self.files_to_add.is_empty() {
+            calls.push(format!(
+                "add_post_images ({} images)",
+                self.files_to_add.len()
+            ));
+        }
+
+        for id in self.files_to_remove.iter() {
+            calls.push(format!("delete_file ({id})"));
+        }
+
+        let num_added_descriptions = self
+            .files_to_add
+            .iter()
+            .filter(|index| !new_post.files[**index].description.is_empty())
+            .count();
+        let num_file_updates = self.file_updates.len() + num_added_descriptions;
+        if num_file_updates != 0 {
+            calls.push(format!("update_files_bulk ({num_file_updates} files)"));
+        }
+
+        calls
+    }
+}
+
+/// Translate post diffs into the API calls needed to apply them.
+///
+/// This also copies the ids of retained files into the new post.
+fn plan_online_post_update(
     diffs: Vec<PostDiff>,
     old_post: &Post,
     new_post: &mut Post,
-    cache_path: &Utf8Path,
-) -> anyhow::Result<()> {
-    let mut update_post_builder = None;
+) -> anyhow::Result<UpdatePlan> {
+    let mut post_update = None;
     let mut files_to_remove = Vec::new();
-    let mut files_to_add_indicies = Vec::new();
     let mut files_to_add = Vec::new();
     let mut file_updates = Vec::new();
+    let mut num_retained = 0;
     for diff in diffs {
         match diff {
             PostDiff::EditTitle { title } => {
-                update_post_builder
-                    .get_or_insert_with(imgchest::UpdatePostBuilder::new)
-                    .title(title);
+                post_update.get_or_insert_with(PostUpdate::default).title = Some(title);
             }
             PostDiff::EditPrivacy { privacy } => {
-                update_post_builder
-                    .get_or_insert_with(imgchest::UpdatePostBuilder::new)
-                    .privacy(match privacy {
-                        PostPrivacy::Public => imgchest::PostPrivacy::Public,
-                        PostPrivacy::Hidden => imgchest::PostPrivacy::Hidden,
-                        PostPrivacy::Secret => imgchest::PostPrivacy::Secret,
-                    });
+                post_update.get_or_insert_with(PostUpdate::default).privacy = Some(privacy);
             }
             PostDiff::EditNsfw { nsfw } => {
-                update_post_builder
-                    .get_or_insert_with(imgchest::UpdatePostBuilder::new)
-                    .nsfw(nsfw);
+                post_update.get_or_insert_with(PostUpdate::default).nsfw = Some(nsfw);
             }
             PostDiff::EditFileDescription { index, description } => {
                 let id = old_post.files[index]
@@ -495,6 +4695,18 @@ async fn update_online_post(
                     .as_ref()
                     .context("old post missing id")?
                     .clone();
+
+                // Descriptions are edited right after their file is retained.
+                // The retain may have copied the old description, so restore the new one.
+                new_post.files[num_retained - 1]
+                    .description
+                    .clone_from(&description);
+
+                let description = if description.is_empty() {
+                    String::from(CLEARED_DESCRIPTION)
+                } else {
+                    description
+                };
                 file_updates.push(imgchest::FileUpdate { id, description });
             }
             PostDiff::RetainFile { index } => {
@@ -503,304 +4715,3308 @@ async fn update_online_post(
                     .as_ref()
                     .context("old post missing id")?
                     .clone();
-                new_post.files[index].id = Some(id);
+
+                // Retained files make up a prefix of the new post, in order.
+                let new_file = &mut new_post.files[num_retained];
+                new_file.id = Some(id);
+
+                // An empty description only survives diffing if the remote one is kept.
+                if new_file.description.is_empty() {
+                    new_file.description = old_post.files[index].description.clone();
+                }
+
+                num_retained += 1;
             }
             PostDiff::AddFile { index } => {
-                let path = new_post.files[index]
-                    .path
-                    .as_ref()
-                    .context("missing path")?;
-                let file = imgchest::UploadPostFile::from_path(path)
-                    .await
-                    .with_context(|| format!("failed to open \"{path}\" for upload"))?;
-                files_to_add.push(file);
-                files_to_add_indicies.push(index);
+                files_to_add.push(index);
             }
             PostDiff::RemoveFile { index } => {
                 let id = old_post.files[index]
                     .id
                     .as_ref()
-                    .context("missing id of file to remove")?;
+                    .context("missing id of file to remove")?
+                    .clone();
+                files_to_remove.push(id);
+            }
+            PostDiff::MoveFile { from, to } => {
+                // The API has no way to reorder files,
+                // so the file is uploaded again at its new position.
+                let id = old_post.files[from]
+                    .id
+                    .as_ref()
+                    .context("missing id of file to move")?
+                    .clone();
+                files_to_add.push(to);
                 files_to_remove.push(id);
             }
         }
     }
 
-    // Nuke the cache.
-    // We cannot perform the diff atomically.
-    // If the update is interrupted, the cache will reflect bad data.
-    match tokio::fs::remove_file(&cache_path).await {
-        Ok(()) => {}
-        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
-        Err(error) => {
-            return Err(error).context("failed to remove cache file");
+    Ok(UpdatePlan {
+        post_update,
+        files_to_add,
+        files_to_remove,
+        file_updates,
+    })
+}
+
+async fn update_online_post<A>(
+    ctx: &SyncContext<A>,
+    client: &A,
+    id: &str,
+    plan: UpdatePlan,
+    old_post: &Post,
+    new_post: &mut Post,
+    cache_path: Option<&Utf8Path>,
+) -> anyhow::Result<Stats>
+where
+    A: Api,
+{
+    let mut stats = Stats::new();
+    let UpdatePlan {
+        post_update,
+        files_to_add: files_to_add_indicies,
+        files_to_remove,
+        mut file_updates,
+    } = plan;
+
+    let mut files_to_add = Vec::with_capacity(files_to_add_indicies.len());
+    let mut total_size = 0;
+    for index in files_to_add_indicies.iter() {
+        let (file, size) = open_upload_file(&mut new_post.files[*index]).await?;
+        total_size += size;
+        files_to_add.push(file);
+    }
+    stats.bytes_uploaded += total_size;
+
+    // We cannot perform the update atomically,
+    // so the cache is replaced by a journal of the update.
+    // If the update is interrupted, the next sync recovers the post from the journal.
+    let mut journal = None;
+    if let Some(cache_path) = cache_path {
+        remove_cache_file(cache_path).await?;
+
+        let new_journal = Journal {
+            diff_version: DIFF_VERSION,
+            post_id: id.into(),
+            post: old_post.clone(),
+            pending_files: Vec::new(),
+        };
+        let journal_path = get_journal_path(cache_path);
+        new_journal.save(&journal_path).await?;
+        journal = Some((new_journal, journal_path));
+    }
+
+    if let Some(post_update) = post_update {
+        client
+            .update_post(id, post_update)
+            .await
+            .context("failed to update post")?;
+        stats.api_calls += 1;
+    }
+
+    if !files_to_add.is_empty() {
+        if let Some((journal, journal_path)) = journal.as_mut() {
+            journal.pending_files = files_to_add_indicies
+                .iter()
+                .map(|index| {
+                    let mut file = new_post.files[*index].clone();
+                    file.id = None;
+                    file
+                })
+                .collect();
+            journal.save(journal_path).await?;
+        }
+
+        if let Some(upload_limiter) = ctx.upload_limiter.as_ref() {
+            upload_limiter.acquire(total_size).await;
+        }
+        let imgchest_post = client
+            .add_post_images(id, files_to_add)
+            .await
+            .context("failed to add images")?;
+        stats.api_calls += 1;
+
+        if let Some((journal, journal_path)) = journal.as_mut() {
+            let added_images = &imgchest_post.images[old_post.files.len()..];
+            journal.add_pending_files(added_images.iter().map(|image| image.id.as_str()));
+            journal.save(journal_path).await?;
+        }
+        for (i, file_index) in files_to_add_indicies.into_iter().enumerate() {
+            let imgchest_image = &imgchest_post.images[old_post.files.len() + i];
+            let new_post_file = &mut new_post.files[file_index];
+
+            let id = imgchest_image.id.clone();
+            let description = &new_post_file.description;
+
+            new_post_file.id = Some(id.clone());
+
+            // If the new description is empty,
+            // do nothing.
+            // We just created a new post,
+            // so it must be empty already.
+            if !description.is_empty() {
+                file_updates.push(imgchest::FileUpdate {
+                    id,
+                    description: description.clone(),
+                });
+            }
+        }
+    }
+
+    // This needs to happen after we add our files, in case the post is empied.
+    for id in files_to_remove.iter() {
+        client
+            .delete_file(id)
+            .await
+            .with_context(|| format!("failed to delete file \"{id}\""))?;
+        stats.api_calls += 1;
+
+        if let Some((journal, journal_path)) = journal.as_mut() {
+            journal.remove_file(id);
+            journal.save(journal_path).await?;
+        }
+    }
+
+    if !file_updates.is_empty() {
+        client
+            .update_files_bulk(file_updates)
+            .await
+            .context("failed to update file descriptions")?;
+        stats.api_calls += 1;
+    }
+
+    Ok(stats)
+}
+
+/// Generate the description updates needed to make the remote files match the new files.
+///
+/// The remote files are given as (id, description) pairs, in the same order as the new files.
+/// Remote files that already have the new description are skipped,
+/// so that resuming an interrupted sync does not resend descriptions.
+fn generate_description_updates<'a, I>(
+    new_files: &[PostFile],
+    remote_files: I,
+) -> Vec<imgchest::FileUpdate>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    new_files
+        .iter()
+        .zip(remote_files)
+        .filter(|(file, (_id, remote_description))| file.description != *remote_description)
+        .map(|(file, (id, _remote_description))| imgchest::FileUpdate {
+            id: id.into(),
+            description: file.description.clone(),
+        })
+        .collect()
+}
+
+/// Put the old files back in place of the files of the new post that could not be read,
+/// so that a file that is only unreadable for now is not removed from the remote post.
+///
+/// Old files are matched by path, or by index if the old post has no paths, like a remote post.
+/// Unreadable files without an old file are left out, as they were never uploaded.
+fn retain_unreadable_files(old: &Post, new: &mut Post, unreadable: &[UnreadableFile]) {
+    let mut num_missing = 0;
+    for file in unreadable.iter() {
+        let old_file = match file.path.as_ref() {
+            Some(path) => old
+                .files
+                .iter()
+                .find(|old_file| old_file.path.as_ref() == Some(path)),
+            None => None,
+        }
+        .or_else(|| {
+            old.files
+                .get(file.index)
+                .filter(|old_file| old_file.path.is_none())
+        });
+
+        match old_file {
+            Some(old_file) => {
+                let index = std::cmp::min(file.index - num_missing, new.files.len());
+                new.files.insert(index, old_file.clone());
+            }
+            None => {
+                num_missing += 1;
+            }
+        }
+    }
+}
+
+/// Limit the file changes of the new post to the files with the given names.
+///
+/// Every other file of the new post is replaced with the old file at the same index,
+/// keeping the local path so that it can still be reuploaded if needed.
+/// Old files past the end of the new post are kept as well.
+/// If the file order changed, the files at each index may not correspond,
+/// so this can produce unexpected diffs.
+fn retain_unlisted_files(old: &Post, new: &mut Post, file_names: &[&str]) {
+    for file_name in file_names.iter() {
+        let is_known = new
+            .files
+            .iter()
+            .any(|file| file.path.as_deref().and_then(|path| path.file_name()) == Some(*file_name));
+        if !is_known {
+            eprintln!("  warning: \"{file_name}\" is not a file of this post");
+        }
+    }
+
+    let len = std::cmp::max(old.files.len(), new.files.len());
+    let mut new_files = std::mem::take(&mut new.files).into_iter();
+    for index in 0..len {
+        let new_file = new_files.next();
+        let is_listed = new_file
+            .as_ref()
+            .and_then(|file| file.path.as_deref())
+            .and_then(|path| path.file_name())
+            .is_some_and(|file_name| file_names.contains(&file_name));
+
+        match (new_file, old.files.get(index)) {
+            (Some(new_file), _) if is_listed => new.files.push(new_file),
+            (new_file, Some(old_file)) => {
+                let mut file = old_file.clone();
+                if let Some(new_file) = new_file {
+                    file.path = new_file.path;
+                    file.data = new_file.data;
+                    file.orient = new_file.orient;
+                    file.upload_name = new_file.upload_name;
+                }
+                new.files.push(file);
+            }
+            (_, None) => {}
+        }
+    }
+}
+
+/// Reorder the files of the new post to follow the old post, where possible.
+///
+/// Files that exist in both posts are moved to the front, in the order of the old post.
+/// The remaining new files follow, in their original order.
+/// Diffing the result only adds and removes files, instead of reuploading files to reorder them.
+fn anchor_post_order(old: &Post, new: &mut Post, diff_options: DiffOptions) {
+    let mut new_files: Vec<Option<PostFile>> = std::mem::take(&mut new.files)
+        .into_iter()
+        .map(Some)
+        .collect();
+
+    let mut files = Vec::with_capacity(new_files.len());
+    for old_file in old.files.iter() {
+        // A file that needs its description cleared must be recreated,
+        // so it cannot be anchored.
+        let matching_file = new_files.iter_mut().find(|new_file| {
+            new_file.as_ref().is_some_and(|new_file| {
+                new_file.sha256 == old_file.sha256
+                    && (new_file.id.is_none() || new_file.id == old_file.id)
+                    && (new_file.description == old_file.description
+                        || !new_file.description.is_empty()
+                        || diff_options.keep_remote_empty_descriptions)
+            })
+        });
+
+        if let Some(matching_file) = matching_file {
+            files.extend(matching_file.take());
+        }
+    }
+    files.extend(new_files.into_iter().flatten());
+
+    new.files = files;
+}
+
+/// Shuffle the files of a post, in the same way for the same seed.
+///
+/// This is only used to test how the diff logic handles reorders.
+fn shuffle_post_files(post: &mut Post, seed: u64) {
+    // A splitmix64 generator is good enough to pick permutations,
+    // and keeps the order stable across platforms and releases.
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+
+    for i in (1..post.files.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        post.files.swap(i, j);
+    }
+}
+
+/// Generate the diffs that turn the old post into the new post.
+fn generate_post_diffs(
+    old: &Post,
+    new: &Post,
+    diff_options: DiffOptions,
+) -> Result<Vec<PostDiff>, SyncError> {
+    if old.files.is_empty() {
+        return Err(SyncError::OldPostEmpty);
+    }
+    if new.files.is_empty() {
+        return Err(SyncError::NewPostEmpty);
+    }
+
+    let mut diffs = Vec::new();
+    if old.title != new.title {
+        diffs.push(PostDiff::EditTitle {
+            title: new.title.clone(),
+        });
+    }
+    if old.privacy != new.privacy {
+        diffs.push(PostDiff::EditPrivacy {
+            privacy: new.privacy,
+        });
+    }
+    if old.nsfw != new.nsfw {
+        diffs.push(PostDiff::EditNsfw { nsfw: new.nsfw });
+    }
+
+    // Ideally, we would diff and only upload what is changed.
+    // However, the imgchest api is horriblly handicapped:
+    // 1. We cannot reorder files without deleting and recreating them.
+    // 2. We can only change a file description, not remove it.
+    // 3. We cannot insert files at arbitrary indicies.
+    //
+    // While diffing would give us an advantage in some cases,
+    // most of the time we would just throw out our calculations
+    // or be forced to use some heurisitics to convert our diffs into
+    // something the API can use.
+    //
+    // As a result, we will use a simpler, faster algorithm.
+    // We will walk the old files, retaining those that match the next new file
+    // and removing those that do not.
+    // This way, deleting files locally does not force re-uploading every file after them.
+    // The retained files form a prefix of the new post.
+    // Then, add the rest of the files from the new post.
+
+    let mut remove_diffs = Vec::new();
+    let mut old_index = 0;
+    let mut new_index = 0;
+    while let (Some(old_file), Some(new_file)) =
+        (old.files.get(old_index), new.files.get(new_index))
+    {
+        // We know that the description may need an update.
+        // However, the API does not allow clearing a description.
+        // In this case, we are forced to recreate the file,
+        // unless the user opted in to clearing with a placeholder.
+        let new_description_empty = new_file.description.is_empty();
+        let description_matches = old_file.description == new_file.description
+            || (diff_options.keep_remote_empty_descriptions && new_description_empty)
+            || (diff_options.clear_descriptions
+                && new_description_empty
+                && old_file.description.trim().is_empty());
+        let must_recreate_description =
+            !description_matches && new_description_empty && !diff_options.clear_descriptions;
+
+        // If the new file specifies the id of the file it corresponds to, it must match as well.
+        let id_matches = new_file.id.is_none() || new_file.id == old_file.id;
+
+        if old_file.sha256 != new_file.sha256 || !id_matches || must_recreate_description {
+            remove_diffs.push(PostDiff::RemoveFile { index: old_index });
+            old_index += 1;
+            continue;
+        }
+
+        diffs.push(PostDiff::RetainFile { index: old_index });
+
+        if !description_matches {
+            diffs.push(PostDiff::EditFileDescription {
+                index: old_index,
+                description: new_file.description.clone(),
+            });
+        }
+
+        old_index += 1;
+        new_index += 1;
+    }
+
+    for index in new_index..new.files.len() {
+        // Since we removed all the posts with the earlier diff,
+        // the retained files of the old post are a prefix of the new post object.
+        // Therefore, new files can be appended.
+        diffs.push(PostDiff::AddFile { index });
+    }
+
+    diffs.extend(remove_diffs);
+    for index in old_index..old.files.len() {
+        diffs.push(PostDiff::RemoveFile { index });
+    }
+
+    Ok(pair_moved_files(old, new, diffs))
+}
+
+/// Replace pairs of removed and added copies of the same file with a move.
+///
+/// The API cannot reorder files, so moves are still applied as a removal and a re-upload.
+/// However, this makes the intent of the diffs clearer.
+fn pair_moved_files(old: &Post, new: &Post, diffs: Vec<PostDiff>) -> Vec<PostDiff> {
+    let mut removed: Vec<usize> = diffs
+        .iter()
+        .filter_map(|diff| match diff {
+            PostDiff::RemoveFile { index } => Some(*index),
+            _ => None,
+        })
+        .collect();
+
+    // Added files always come before removed files,
+    // so every move is paired before any removals are visited.
+    let mut paired_diffs = Vec::with_capacity(diffs.len());
+    for diff in diffs {
+        match diff {
+            PostDiff::AddFile { index } => {
+                let new_file = &new.files[index];
+                let position = removed.iter().position(|old_index| {
+                    let old_file = &old.files[*old_index];
+                    old_file.sha256 == new_file.sha256
+                        && old_file.description == new_file.description
+                        && (new_file.id.is_none() || new_file.id == old_file.id)
+                });
+
+                match position {
+                    Some(position) => {
+                        let from = removed.remove(position);
+                        paired_diffs.push(PostDiff::MoveFile { from, to: index });
+                    }
+                    None => {
+                        paired_diffs.push(PostDiff::AddFile { index });
+                    }
+                }
+            }
+            PostDiff::RemoveFile { index } if !removed.contains(&index) => {}
+            diff => paired_diffs.push(diff),
+        }
+    }
+
+    paired_diffs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SHA256_A: &str = "a";
+    const SHA256_B: &str = "b";
+    const SHA256_C: &str = "c";
+    const SHA256_D: &str = "d";
+
+    /// An API call made to the mock API.
+    #[derive(Debug, PartialEq, Eq)]
+    pub(crate) enum MockCall {
+        GetPost { id: String },
+        CreatePost { num_files: usize },
+        UpdatePost { id: String },
+        DeletePost { id: String },
+        AddPostImages { id: String, num_files: usize },
+        DeleteFile { id: String },
+        UpdateFilesBulk { ids: Vec<String> },
+    }
+
+    #[derive(Debug, Default)]
+    struct MockState {
+        posts: HashMap<String, RemotePost>,
+        next_id: u64,
+        calls: Vec<MockCall>,
+
+        /// Whether updating posts fails, like with a read-only token.
+        read_only: bool,
+
+        /// Whether deleting files fails, like when an update is interrupted.
+        fail_file_deletes: bool,
+
+        /// Whether adding files to an existing post fails.
+        fail_file_adds: bool,
+    }
+
+    impl MockState {
+        fn make_id(&mut self, prefix: &str) -> String {
+            self.next_id += 1;
+            format!("{prefix}-{}", self.next_id)
+        }
+
+        fn make_files(&mut self, num_files: usize) -> Vec<RemoteFile> {
+            (0..num_files)
+                .map(|_| {
+                    let id = self.make_id("file");
+                    RemoteFile {
+                        link: format!("https://example.com/{id}"),
+                        id,
+                        description: None,
+                    }
+                })
+                .collect()
+        }
+
+        fn get_post(&self, id: &str) -> anyhow::Result<RemotePost> {
+            self.posts
+                .get(id)
+                .cloned()
+                .with_context(|| format!("missing post \"{id}\""))
+        }
+    }
+
+    /// An in-memory mock of the imgchest API, which records every call.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockApi {
+        state: std::sync::Arc<std::sync::Mutex<MockState>>,
+        http_client: reqwest::Client,
+    }
+
+    impl MockApi {
+        /// Take the calls made since the last time this was called.
+        pub(crate) fn take_calls(&self) -> Vec<MockCall> {
+            std::mem::take(&mut self.state.lock().unwrap().calls)
+        }
+    }
+
+    impl Api for MockApi {
+        async fn get_post(&self, id: &str) -> anyhow::Result<RemotePost> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(MockCall::GetPost { id: id.into() });
+            state.get_post(id)
+        }
+
+        async fn create_post(&self, post: NewPost) -> anyhow::Result<RemotePost> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(MockCall::CreatePost {
+                num_files: post.files.len(),
+            });
+
+            let id = state.make_id("post");
+            let images = state.make_files(post.files.len());
+            let remote_post = RemotePost {
+                id: id.clone(),
+                title: Some(post.title),
+                privacy: post.privacy,
+                nsfw: post.nsfw,
+                images,
+            };
+            state.posts.insert(id, remote_post.clone());
+
+            Ok(remote_post)
+        }
+
+        async fn update_post(&self, id: &str, update: PostUpdate) -> anyhow::Result<()> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(MockCall::UpdatePost { id: id.into() });
+            anyhow::ensure!(!state.read_only, "token is read-only");
+
+            let post = state.posts.get_mut(id).context("missing post")?;
+            if let Some(title) = update.title {
+                post.title = Some(title);
+            }
+            if let Some(privacy) = update.privacy {
+                post.privacy = privacy;
+            }
+            if let Some(nsfw) = update.nsfw {
+                post.nsfw = nsfw;
+            }
+
+            Ok(())
+        }
+
+        async fn delete_post(&self, id: &str) -> anyhow::Result<()> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(MockCall::DeletePost { id: id.into() });
+            state.posts.remove(id).context("missing post")?;
+
+            Ok(())
+        }
+
+        async fn add_post_images(
+            &self,
+            id: &str,
+            files: Vec<imgchest::UploadPostFile>,
+        ) -> anyhow::Result<RemotePost> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(MockCall::AddPostImages {
+                id: id.into(),
+                num_files: files.len(),
+            });
+            anyhow::ensure!(!state.fail_file_adds, "connection reset");
+
+            let images = state.make_files(files.len());
+            let post = state.posts.get_mut(id).context("missing post")?;
+            post.images.extend(images);
+
+            state.get_post(id)
+        }
+
+        async fn delete_file(&self, id: &str) -> anyhow::Result<()> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(MockCall::DeleteFile { id: id.into() });
+            anyhow::ensure!(!state.fail_file_deletes, "connection reset");
+
+            let post = state
+                .posts
+                .values_mut()
+                .find(|post| post.images.iter().any(|image| image.id == id))
+                .context("missing file")?;
+            post.images.retain(|image| image.id != id);
+
+            Ok(())
+        }
+
+        async fn update_files_bulk(
+            &self,
+            updates: Vec<imgchest::FileUpdate>,
+        ) -> anyhow::Result<()> {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(MockCall::UpdateFilesBulk {
+                ids: updates.iter().map(|update| update.id.clone()).collect(),
+            });
+
+            for update in updates {
+                let image = state
+                    .posts
+                    .values_mut()
+                    .flat_map(|post| post.images.iter_mut())
+                    .find(|image| image.id == update.id)
+                    .context("missing file")?;
+                image.description = Some(update.description);
+            }
+
+            Ok(())
+        }
+
+        fn http_client(&self) -> &reqwest::Client {
+            &self.http_client
+        }
+
+        fn with_token(&self, _token: &str) -> Self {
+            Self {
+                state: self.state.clone(),
+                http_client: self.http_client.clone(),
+            }
+        }
+    }
+
+    /// Run a future to completion on a new runtime.
+    pub(crate) fn block_on<F>(future: F) -> F::Output
+    where
+        F: std::future::Future,
+    {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build runtime")
+            .block_on(future)
+    }
+
+    /// A temp directory for tests, removed on drop.
+    pub(crate) struct TestDir {
+        pub(crate) path: Utf8PathBuf,
+    }
+
+    impl TestDir {
+        /// Make an empty temp directory.
+        pub(crate) fn new(name: &str) -> Self {
+            let path = Utf8PathBuf::try_from(std::env::temp_dir())
+                .expect("temp dir is not utf8")
+                .join(format!("imgchest-sync-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).expect("failed to create dir");
+
+            Self { path }
+        }
+
+        pub(crate) fn write(&self, file_name: &str, data: &str) {
+            std::fs::write(self.path.join(file_name), data).expect("failed to write file");
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    /// A post directory for end-to-end tests, removed on drop.
+    pub(crate) struct TestPostDir {
+        dir: TestDir,
+    }
+
+    impl TestPostDir {
+        /// Make a post directory with the given files and config.
+        pub(crate) fn new(name: &str, files: &[(&str, &str)], config: &str) -> Self {
+            let dir = TestDir::new(name);
+            for (file_name, data) in files {
+                dir.write(file_name, data);
+            }
+            dir.write("imgchest-sync.toml", config);
+
+            Self { dir }
+        }
+
+        pub(crate) fn read_config(&self) -> String {
+            std::fs::read_to_string(self.path.join("imgchest-sync.toml"))
+                .expect("failed to read config")
+        }
+
+        /// Sync this post directory.
+        fn sync(&self, ctx: &SyncContext<MockApi>) -> SyncOutcome {
+            self.try_sync(ctx).expect("failed to sync")
+        }
+
+        /// Sync this post directory, returning any error.
+        fn try_sync(&self, ctx: &SyncContext<MockApi>) -> anyhow::Result<SyncOutcome> {
+            let config_path = self.path.join("imgchest-sync.toml");
+            let cache_path = self.path.join(".imgchest-sync-cache.toml");
+
+            block_on(async {
+                let mut config = Config::new(&self.read_config())?;
+                sync_post(
+                    ctx,
+                    &self.path,
+                    &mut config,
+                    Some(&config_path),
+                    Some(&cache_path),
+                )
+                .await
+            })
+        }
+    }
+
+    impl std::ops::Deref for TestPostDir {
+        type Target = TestDir;
+
+        fn deref(&self) -> &TestDir {
+            &self.dir
+        }
+    }
+
+    pub(crate) fn mock_context(args: &[&str]) -> SyncContext<MockApi> {
+        let options = <Options as argh::FromArgs>::from_args(&["imgchest-sync"], args)
+            .expect("failed to parse options");
+        let hash_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .expect("failed to build hash pool");
+
+        SyncContext {
+            options,
+            client: MockApi::default(),
+            account_clients: HashMap::new(),
+            hasher: Hasher {
+                pool: hash_pool,
+                db: None,
+            },
+            http_headers: HeaderMap::new(),
+            upload_limiter: None,
+            default_privacy: PostConfigPrivacy::Hidden,
+            nsfw_marker: false,
+            verified_accounts: std::sync::Mutex::new(HashMap::new()),
+            credential_tokens: std::sync::Mutex::new(HashMap::new()),
+            interrupted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub(crate) const TEST_POST_CONFIG: &str = "[post]\n\n[[post.files]]\npath = \"a.png\"\ndescription = \"first\"\n\n[[post.files]]\npath = \"b.png\"\n";
+
+    /// Make a post directory and sync it, so that it has a remote post.
+    fn create_test_post(name: &str, ctx: &SyncContext<MockApi>) -> TestPostDir {
+        let dir = TestPostDir::new(name, &[("a.png", "a"), ("b.png", "b")], TEST_POST_CONFIG);
+        dir.sync(ctx);
+        ctx.client.take_calls();
+
+        dir
+    }
+
+    #[test]
+    fn sync_post_creates_post() {
+        let ctx = mock_context(&[]);
+        let dir = TestPostDir::new(
+            "sync-post-creates-post",
+            &[("a.png", "a"), ("b.png", "b")],
+            TEST_POST_CONFIG,
+        );
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        assert!(outcome.post_id.as_deref() == Some("post-1"));
+        assert!(
+            ctx.client.take_calls()
+                == [
+                    MockCall::CreatePost { num_files: 2 },
+                    MockCall::UpdateFilesBulk {
+                        ids: vec![String::from("file-2")]
+                    },
+                ]
+        );
+        assert!(dir.read_config().contains("id = \"post-1\""));
+
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        assert!(remote_post.images[0].description.as_deref() == Some("first"));
+    }
+
+    #[test]
+    fn sync_post_adds_files() {
+        let ctx = mock_context(&[]);
+        let dir = create_test_post("sync-post-adds-files", &ctx);
+
+        dir.write("c.png", "c");
+        dir.write(
+            "imgchest-sync.toml",
+            &format!("{}\n[[post.files]]\npath = \"c.png\"\n", dir.read_config()),
+        );
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        assert!(
+            ctx.client.take_calls()
+                == [MockCall::AddPostImages {
+                    id: String::from("post-1"),
+                    num_files: 1
+                }]
+        );
+
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        assert!(remote_post.images.len() == 3);
+    }
+
+    #[test]
+    fn humanize_file_stem_works() {
+        assert!(humanize_file_stem("sunset_over_the_bay") == "Sunset Over The Bay");
+        assert!(humanize_file_stem("__day  2__") == "Day 2");
+        assert!(humanize_file_stem("iPhone_HDR") == "IPhone HDR");
+        assert!(humanize_file_stem("élan_vital") == "Élan Vital");
+        assert!(humanize_file_stem("").is_empty());
+    }
+
+    #[test]
+    fn sync_post_uses_file_names_as_descriptions() {
+        let config = "[post]\n\n[[post.files]]\npath = \"first_file.png\"\ndescription = \"explicit\"\n\n[[post.files]]\npath = \"second_file.png\"\n";
+        let dir = TestPostDir::new(
+            "sync-post-uses-file-names-as-descriptions",
+            &[("first_file.png", "a"), ("second_file.png", "b")],
+            config,
+        );
+
+        let ctx = mock_context(&["--description-from", "humanized-filename"]);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        let descriptions: Vec<_> = remote_post
+            .images
+            .iter()
+            .map(|image| image.description.as_deref())
+            .collect();
+        assert!(descriptions == [Some("explicit"), Some("Second File")]);
+    }
+
+    #[test]
+    fn sync_post_ignores_descriptions() {
+        let mut ctx = mock_context(&["--no-descriptions"]);
+        let dir = TestPostDir::new(
+            "sync-post-ignores-descriptions",
+            &[("a.png", "a"), ("b.png", "b")],
+            TEST_POST_CONFIG,
+        );
+        dir.sync(&ctx);
+        assert!(ctx.client.take_calls() == [MockCall::CreatePost { num_files: 2 }]);
+
+        dir.write("c.png", "c");
+        let config = dir.read_config().replace("\"first\"", "\"changed\"");
+        dir.write(
+            "imgchest-sync.toml",
+            &format!("{config}\n[[post.files]]\npath = \"c.png\"\ndescription = \"third\"\n"),
+        );
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        assert!(
+            ctx.client.take_calls()
+                == [MockCall::AddPostImages {
+                    id: String::from("post-1"),
+                    num_files: 1
+                }]
+        );
+
+        // The descriptions are synced once they are no longer ignored.
+        ctx.options.no_descriptions = false;
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        let descriptions: Vec<_> = remote_post
+            .images
+            .iter()
+            .map(|image| image.description.as_deref())
+            .collect();
+        assert!(descriptions == [Some("changed"), None, Some("third")]);
+    }
+
+    #[test]
+    fn sync_post_syncs_listed_files() {
+        let mut ctx = mock_context(&["--files", "b.png"]);
+        let dir = TestPostDir::new(
+            "sync-post-syncs-listed-files",
+            &[("a.png", "a"), ("b.png", "b")],
+            TEST_POST_CONFIG,
+        );
+        ctx.options.files = None;
+        dir.sync(&ctx);
+        ctx.client.take_calls();
+        ctx.options.files = Some(String::from("b.png"));
+
+        let config = dir
+            .read_config()
+            .replace("\"first\"", "\"changed\"")
+            .replace(
+                "path = \"b.png\"\n",
+                "path = \"b.png\"\ndescription = \"second\"\n",
+            );
+        dir.write("imgchest-sync.toml", &config);
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        let descriptions: Vec<_> = remote_post
+            .images
+            .iter()
+            .map(|image| image.description.as_deref())
+            .collect();
+        assert!(descriptions == [Some("first"), Some("second")]);
+
+        // The other files are synced once they are no longer filtered out.
+        ctx.options.files = None;
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        let descriptions: Vec<_> = remote_post
+            .images
+            .iter()
+            .map(|image| image.description.as_deref())
+            .collect();
+        assert!(descriptions == [Some("changed"), Some("second")]);
+    }
+
+    #[test]
+    fn sync_post_ignores_old_diff_version_caches() {
+        let mut ctx = mock_context(&[]);
+        ctx.options.api_base = Some(serve_files(&[("file-2", "a"), ("file-3", "b")]));
+        let dir = create_test_post("sync-post-ignores-old-diff-version-caches", &ctx);
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+
+        // An unchanged post with a current cache needs no requests.
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
+        assert!(ctx.client.take_calls().is_empty());
+
+        let cache = std::fs::read_to_string(&cache_path).expect("failed to read cache");
+        let cache = cache.replace(
+            &format!("diff_version = {DIFF_VERSION}"),
+            &format!("diff_version = {}", DIFF_VERSION - 1),
+        );
+        std::fs::write(&cache_path, cache).expect("failed to write cache");
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
+        assert!(
+            ctx.client.take_calls()
+                == [MockCall::GetPost {
+                    id: String::from("post-1")
+                }]
+        );
+    }
+
+    #[test]
+    fn sync_post_recovers_interrupted_updates() {
+        let ctx = mock_context(&[]);
+        let dir = create_test_post("sync-post-recovers-interrupted-updates", &ctx);
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+        let journal_path = get_journal_path(&cache_path);
+
+        dir.write("c.png", "c");
+        let config = dir.read_config().replace(
+            "[[post.files]]\npath = \"b.png\"\n",
+            "[[post.files]]\npath = \"c.png\"\n",
+        );
+        dir.write("imgchest-sync.toml", &config);
+
+        // Interrupt the update after the new file is added.
+        ctx.client.state.lock().unwrap().fail_file_deletes = true;
+        let result = dir.try_sync(&ctx);
+        assert!(result.is_err());
+        assert!(!cache_path.exists());
+        assert!(journal_path.exists());
+        ctx.client.take_calls();
+
+        // The resumed sync only needs the remote post, and does not upload the new file again.
+        ctx.client.state.lock().unwrap().fail_file_deletes = false;
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        assert!(
+            ctx.client.take_calls()
+                == [
+                    MockCall::GetPost {
+                        id: String::from("post-1")
+                    },
+                    MockCall::DeleteFile {
+                        id: String::from("file-3")
+                    },
+                ]
+        );
+        assert!(cache_path.exists());
+        assert!(!journal_path.exists());
+
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        let ids: Vec<_> = remote_post
+            .images
+            .iter()
+            .map(|image| image.id.as_str())
+            .collect();
+        assert!(ids == ["file-2", "file-4"]);
+    }
+
+    #[test]
+    fn sync_post_removes_files() {
+        let ctx = mock_context(&[]);
+        let dir = create_test_post("sync-post-removes-files", &ctx);
+
+        let config = dir
+            .read_config()
+            .replace("[[post.files]]\npath = \"b.png\"\n", "");
+        dir.write("imgchest-sync.toml", &config);
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        assert!(
+            ctx.client.take_calls()
+                == [MockCall::DeleteFile {
+                    id: String::from("file-3")
+                }]
+        );
+
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        assert!(remote_post.images.len() == 1);
+    }
+
+    #[test]
+    fn sync_post_no_changes() {
+        let ctx = mock_context(&[]);
+        let dir = create_test_post("sync-post-no-changes", &ctx);
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
+        assert!(ctx.client.take_calls().is_empty());
+    }
+
+    #[test]
+    fn sync_post_creates_drafts_and_publishes_them() {
+        let mut ctx = mock_context(&["--create-as", "secret"]);
+        let dir = TestPostDir::new(
+            "sync-post-creates-drafts-and-publishes-them",
+            &[("a.png", "a")],
+            "[post]\nprivacy = \"public\"\n\n[[post.files]]\npath = \"a.png\"\n",
+        );
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        assert!(dir.read_config().contains("draft = true"));
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        assert!(remote_post.privacy == PostPrivacy::Secret);
+        ctx.client.take_calls();
+
+        // Drafts keep their privacy, even without --create-as.
+        ctx.options.create_as = None;
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
+        assert!(ctx.client.take_calls().is_empty());
+
+        let config_path = dir.path.join("imgchest-sync.toml");
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+        let mut config = Config::new(&dir.read_config()).expect("failed to parse config");
+        block_on(crate::commands::publish::publish_post(
+            &ctx,
+            &mut config,
+            ConfigTarget::File(&config_path),
+            Some(&cache_path),
+        ))
+        .expect("failed to publish");
+        assert!(!dir.read_config().contains("draft"));
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        assert!(remote_post.privacy == PostPrivacy::Public);
+        ctx.client.take_calls();
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
+        assert!(ctx.client.take_calls().is_empty());
+    }
+
+    fn post_from_hashes(hashes: &[&str]) -> Post {
+        Post {
+            title: String::from("title"),
+            privacy: PostPrivacy::Hidden,
+            nsfw: false,
+            files: hashes
+                .iter()
+                .map(|sha256| PostFile {
+                    description: String::new(),
+                    sha256: String::from(*sha256),
+                    id: None,
+                    path: None,
+                    data: None,
+                    orient: false,
+                    upload_name: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn generate_post_diffs_works() {
+        let old_post = Post {
+            title: String::from("title"),
+            privacy: PostPrivacy::Hidden,
+            nsfw: false,
+            files: vec![PostFile {
+                description: String::new(),
+                sha256: SHA256_B.into(),
+                id: None,
+                path: None,
+                data: None,
+                orient: false,
+                upload_name: None,
+            }],
+        };
+        let new_post = Post {
+            title: String::from("title"),
+            privacy: PostPrivacy::Hidden,
+            nsfw: false,
+            files: vec![PostFile {
+                description: String::new(),
+                sha256: SHA256_A.into(),
+                id: None,
+                path: None,
+                data: None,
+                orient: false,
+                upload_name: None,
+            }],
+        };
+
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        let expected_diffs = vec![
+            PostDiff::AddFile { index: 0 },
+            PostDiff::RemoveFile { index: 0 },
+        ];
+        assert!(actual_diffs == expected_diffs);
+
+        let old_post = Post {
+            title: String::from("title"),
+            privacy: PostPrivacy::Hidden,
+            nsfw: false,
+            files: vec![
+                PostFile {
+                    description: String::new(),
+                    sha256: SHA256_A.into(),
+                    id: None,
+                    path: None,
+                    data: None,
+                    orient: false,
+                    upload_name: None,
+                },
+                PostFile {
+                    description: String::new(),
+                    sha256: SHA256_A.into(),
+                    id: None,
+                    path: None,
+                    data: None,
+                    orient: false,
+                    upload_name: None,
+                },
+            ],
+        };
+        let new_post = Post {
+            title: String::from("title"),
+            privacy: PostPrivacy::Hidden,
+            nsfw: false,
+            files: vec![PostFile {
+                description: String::new(),
+                sha256: SHA256_A.into(),
+                id: None,
+                path: None,
+                data: None,
+                orient: false,
+                upload_name: None,
+            }],
+        };
+
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        let expected_diffs = vec![
+            PostDiff::RetainFile { index: 0 },
+            PostDiff::RemoveFile { index: 1 },
+        ];
+        assert!(actual_diffs == expected_diffs);
+
+        let old_post = Post {
+            title: String::from("title"),
+            privacy: PostPrivacy::Hidden,
+            nsfw: false,
+            files: vec![PostFile {
+                description: String::new(),
+                sha256: SHA256_A.into(),
+                id: None,
+                path: None,
+                data: None,
+                orient: false,
+                upload_name: None,
+            }],
+        };
+        let new_post = Post {
+            title: String::from("title"),
+            privacy: PostPrivacy::Hidden,
+            nsfw: false,
+            files: vec![PostFile {
+                description: String::new(),
+                sha256: SHA256_A.into(),
+                id: None,
+                path: None,
+                data: None,
+                orient: false,
+                upload_name: None,
+            }],
+        };
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        let expected_diffs = vec![PostDiff::RetainFile { index: 0 }];
+        assert!(actual_diffs == expected_diffs);
+
+        let old_post = Post {
+            title: String::from("title"),
+            privacy: PostPrivacy::Hidden,
+            nsfw: false,
+            files: vec![PostFile {
+                description: String::new(),
+                sha256: SHA256_A.into(),
+                id: None,
+                path: None,
+                data: None,
+                orient: false,
+                upload_name: None,
+            }],
+        };
+        let new_post = Post {
+            title: String::from("title"),
+            privacy: PostPrivacy::Hidden,
+            nsfw: false,
+            files: vec![PostFile {
+                description: "hello world!".into(),
+                sha256: SHA256_A.into(),
+                id: None,
+                path: None,
+                data: None,
+                orient: false,
+                upload_name: None,
+            }],
+        };
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        let expected_diffs = vec![
+            PostDiff::RetainFile { index: 0 },
+            PostDiff::EditFileDescription {
+                index: 0,
+                description: "hello world!".into(),
+            },
+        ];
+        dbg!(&actual_diffs);
+        assert!(actual_diffs == expected_diffs);
+    }
+
+    #[test]
+    fn generate_post_diffs_delete_middle_file() {
+        let old_post = post_from_hashes(&[SHA256_A, SHA256_B, SHA256_C]);
+        let new_post = post_from_hashes(&[SHA256_A, SHA256_C]);
+
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        let expected_diffs = vec![
+            PostDiff::RetainFile { index: 0 },
+            PostDiff::RetainFile { index: 2 },
+            PostDiff::RemoveFile { index: 1 },
+        ];
+        assert!(actual_diffs == expected_diffs);
+    }
+
+    #[test]
+    fn generate_post_diffs_keep_remote_empty_descriptions() {
+        let mut old_post = post_from_hashes(&[SHA256_A, SHA256_B]);
+        old_post.files[0].description = String::from("remote");
+        let new_post = post_from_hashes(&[SHA256_A, SHA256_B]);
+
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        let expected_diffs = vec![
+            PostDiff::AddFile { index: 0 },
+            PostDiff::MoveFile { from: 1, to: 1 },
+            PostDiff::RemoveFile { index: 0 },
+        ];
+        assert!(actual_diffs == expected_diffs);
+
+        let actual_diffs = generate_post_diffs(
+            &old_post,
+            &new_post,
+            DiffOptions {
+                keep_remote_empty_descriptions: true,
+                ..DiffOptions::default()
+            },
+        )
+        .expect("failed to generate diffs");
+        let expected_diffs = vec![
+            PostDiff::RetainFile { index: 0 },
+            PostDiff::RetainFile { index: 1 },
+        ];
+        assert!(actual_diffs == expected_diffs);
+    }
+
+    #[test]
+    fn generate_post_diffs_move_files() {
+        let mut old_post = post_from_hashes(&[SHA256_A, SHA256_B, SHA256_C]);
+        for (i, file) in old_post.files.iter_mut().enumerate() {
+            file.id = Some(format!("file-{i}"));
+        }
+        let mut new_post = post_from_hashes(&[SHA256_B, SHA256_D, SHA256_A]);
+
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        let expected_diffs = vec![
+            PostDiff::RetainFile { index: 1 },
+            PostDiff::AddFile { index: 1 },
+            PostDiff::MoveFile { from: 0, to: 2 },
+            PostDiff::RemoveFile { index: 2 },
+        ];
+        assert!(actual_diffs == expected_diffs, "{actual_diffs:?}");
+
+        let plan = plan_online_post_update(actual_diffs, &old_post, &mut new_post)
+            .expect("failed to plan update");
+        assert!(plan.files_to_add == [1, 2]);
+        assert!(plan.files_to_remove == ["file-0", "file-2"]);
+    }
+
+    #[test]
+    fn shuffle_post_files_works() {
+        let hashes = [SHA256_A, SHA256_B, SHA256_C, SHA256_D];
+        let mut old_post = post_from_hashes(&hashes);
+        for (i, file) in old_post.files.iter_mut().enumerate() {
+            file.id = Some(format!("file-{i}"));
+        }
+
+        let mut new_post = post_from_hashes(&hashes);
+        shuffle_post_files(&mut new_post, 1);
+        let mut same_seed_post = post_from_hashes(&hashes);
+        shuffle_post_files(&mut same_seed_post, 1);
+        let shuffled_hashes: Vec<&str> = new_post
+            .files
+            .iter()
+            .map(|file| file.sha256.as_str())
+            .collect();
+        assert!(same_seed_post
+            .files
+            .iter()
+            .map(|file| file.sha256.as_str())
+            .eq(shuffled_hashes.iter().copied()));
+
+        let mut shuffled_hashes = shuffled_hashes;
+        shuffled_hashes.sort_unstable();
+        let mut sorted_hashes = hashes;
+        sorted_hashes.sort_unstable();
+        assert!(shuffled_hashes == sorted_hashes);
+
+        // Reorders must never add or remove files.
+        for seed in 0..32 {
+            let mut new_post = post_from_hashes(&hashes);
+            shuffle_post_files(&mut new_post, seed);
+            let diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+                .expect("failed to generate diffs");
+            assert!(
+                diffs.iter().all(|diff| matches!(
+                    diff,
+                    PostDiff::RetainFile { .. } | PostDiff::MoveFile { .. }
+                )),
+                "{diffs:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn generate_post_diffs_clear_descriptions() {
+        let mut old_post = post_from_hashes(&[SHA256_A, SHA256_B]);
+        old_post.files[0].description = String::from("remote");
+        old_post.files[1].description = String::from(CLEARED_DESCRIPTION);
+        let new_post = post_from_hashes(&[SHA256_A, SHA256_B]);
+
+        let diff_options = DiffOptions {
+            clear_descriptions: true,
+            ..DiffOptions::default()
+        };
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, diff_options)
+            .expect("failed to generate diffs");
+        let expected_diffs = vec![
+            PostDiff::RetainFile { index: 0 },
+            PostDiff::EditFileDescription {
+                index: 0,
+                description: String::new(),
+            },
+            PostDiff::RetainFile { index: 1 },
+        ];
+        assert!(actual_diffs == expected_diffs);
+    }
+
+    #[test]
+    fn allowed_extensions_filter_files() {
+        let mut config = Config::new(
+            "[post]\nextensions = [\"PNG\", \".webp\"]\n\n[[post.files]]\npath = \"a.png\"\n",
+        )
+        .expect("failed to parse config");
+        let options = <Options as argh::FromArgs>::from_args(&["imgchest-sync"], &[])
+            .expect("failed to parse options");
+        let allowed_extensions = get_allowed_extensions(&options, &config.post_mut())
+            .expect("missing allowed extensions");
+        assert!(allowed_extensions == ["png", "webp"]);
+
+        assert!(is_extension_allowed(
+            Utf8Path::new("a.png"),
+            &allowed_extensions
+        ));
+        assert!(is_extension_allowed(
+            Utf8Path::new("b.WEBP"),
+            &allowed_extensions
+        ));
+        assert!(!is_extension_allowed(
+            Utf8Path::new("c.jpg"),
+            &allowed_extensions
+        ));
+        assert!(!is_extension_allowed(
+            Utf8Path::new("notes.txt"),
+            &allowed_extensions
+        ));
+        assert!(!is_extension_allowed(
+            Utf8Path::new(".DS_Store"),
+            &allowed_extensions
+        ));
+
+        let options = <Options as argh::FromArgs>::from_args(
+            &["imgchest-sync"],
+            &["--allowed-extensions", "jpg"],
+        )
+        .expect("failed to parse options");
+        let allowed_extensions = get_allowed_extensions(&options, &config.post_mut());
+        assert!(allowed_extensions.as_deref() == Some(&[String::from("jpg")][..]));
+
+        let mut config = Config::new("[post]\n\n[[post.files]]\npath = \"a.png\"\n")
+            .expect("failed to parse config");
+        let options = <Options as argh::FromArgs>::from_args(&["imgchest-sync"], &[])
+            .expect("failed to parse options");
+        // Without an allowlist, every extension is accepted.
+        let allowed_extensions = get_allowed_extensions(&options, &config.post_mut());
+        assert!(allowed_extensions.is_none());
+        assert!(is_upload_extension_allowed(
+            Utf8Path::new("notes.txt"),
+            None,
+            None
+        ));
+
+        assert!(
+            Config::new("[post]\nextensions = \"png\"\n\n[[post.files]]\npath = \"a.png\"\n")
+                .is_err()
+        );
+        assert!(
+            Config::new("[post]\nextensions = [1]\n\n[[post.files]]\npath = \"a.png\"\n").is_err()
+        );
+    }
+
+    #[test]
+    fn create_post_from_post_config_filters_extensions() {
+        let ctx = mock_context(&[]);
+        let dir = TestPostDir::new(
+            "create-post-from-post-config-filters-extensions",
+            &[("a.png", "a"), ("a.txt", "notes"), (".DS_Store", "")],
+            "[post]\nextensions = [\"png\"]\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"a.txt\"\n\n[[post.files]]\npath = \".DS_Store\"\n",
+        );
+        let mut config = Config::new(&dir.read_config()).expect("failed to parse config");
+
+        let (post, _, unreadable) = block_on(create_post_from_post_config(
+            &dir.path,
+            &config.post_mut(),
+            &ctx.options,
+            ctx.default_privacy,
+            ctx.nsfw_marker,
+            &ctx.hasher,
+            None,
+        ))
+        .expect("failed to create post");
+        let file_names: Vec<_> = post
+            .files
+            .iter()
+            .map(|file| file.path.as_deref().and_then(|path| path.file_name()))
+            .collect();
+        assert!(file_names == [Some("a.png")]);
+        assert!(unreadable.is_empty());
+    }
+
+    #[test]
+    fn ensure_description_length_works() {
+        assert!(ensure_description_length("a.png", "", 0).is_ok());
+        assert!(ensure_description_length("a.png", "abc", 3).is_ok());
+        assert!(ensure_description_length("a.png", "ééé", 3).is_ok());
+
+        let error = ensure_description_length("a.png", "abcd", 3).unwrap_err();
+        assert!(matches!(
+            error,
+            SyncError::DescriptionTooLong {
+                ref file,
+                length: 4,
+                max_length: 3,
+            } if file == "a.png"
+        ));
+    }
+
+    #[test]
+    fn fit_title_works() {
+        let dir_path = Utf8Path::new("posts/trip");
+        assert!(fit_title(dir_path, String::from("abc"), 3, false).unwrap() == "abc");
+        assert!(fit_title(dir_path, String::from("ééé"), 3, false).unwrap() == "ééé");
+        assert!(fit_title(dir_path, String::from("abcdef"), 4, true).unwrap() == "abc…");
+        assert!(fit_title(dir_path, String::from("ééééé"), 4, true).unwrap() == "ééé…");
+
+        let error = fit_title(dir_path, String::from("abcd"), 3, false).unwrap_err();
+        assert!(matches!(
+            error,
+            SyncError::TitleTooLong {
+                ref dir,
+                length: 4,
+                max_length: 3,
+            } if dir == "posts/trip"
+        ));
+    }
+
+    #[test]
+    fn sync_post_fits_long_titles() {
+        let config = format!(
+            "[post]\ntitle = \"a long title\"\n{}",
+            &TEST_POST_CONFIG[7..]
+        );
+        let dir = TestPostDir::new(
+            "sync-post-fits-long-titles",
+            &[("a.png", "a"), ("b.png", "b")],
+            &config,
+        );
+
+        let ctx = mock_context(&["--max-title-length", "6"]);
+        let result = dir.try_sync(&ctx);
+        let error = result.expect_err("long title was not rejected");
+        assert!(
+            error.to_string().contains("sync-post-fits-long-titles"),
+            "{error:?}"
+        );
+        assert!(ctx.client.take_calls().is_empty());
+
+        let ctx = mock_context(&["--max-title-length", "6", "--truncate-titles"]);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        assert!(remote_post.title.as_deref() == Some("a lon…"));
+    }
+
+    #[test]
+    fn sync_post_rejects_long_descriptions() {
+        let ctx = mock_context(&["--max-description-length", "3"]);
+        let dir = TestPostDir::new(
+            "sync-post-rejects-long-descriptions",
+            &[("a.png", "a"), ("b.png", "b")],
+            TEST_POST_CONFIG,
+        );
+
+        let result = dir.try_sync(&ctx);
+        let error = result.expect_err("long description was not rejected");
+        assert!(error.to_string().contains("a.png"), "{error:?}");
+        assert!(ctx.client.take_calls().is_empty());
+
+        assert!(Config::new(
+            "[post]\ndescription_format = \"markdown\"\n\n[[post.files]]\npath = \"a.png\"\n"
+        )
+        .is_ok());
+        assert!(Config::new(
+            "[post]\ndescription_format = \"html\"\n\n[[post.files]]\npath = \"a.png\"\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn sync_post_reports_missing_credentials() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+
+        let ctx = mock_context(&[]);
+        let dir = TestPostDir::new(
+            "sync-post-reports-missing-credentials",
+            &[("a.png", "a")],
+            "[post]\ncredential = \"imgchest-sync-test:missing\"\n\n[[post.files]]\npath = \"a.png\"\n",
+        );
+
+        let result = dir.try_sync(&ctx);
+        let error = result.expect_err("missing credential was not reported");
+        assert!(
+            error.to_string().contains("was not found in the keyring"),
+            "{error:?}"
+        );
+        assert!(ctx.client.take_calls().is_empty());
+
+        assert!(Config::new(
+            "[post]\ncredential = \"imgchest\"\n\n[[post.files]]\npath = \"a.png\"\n"
+        )
+        .is_err());
+        assert!(Config::new(
+            "[post]\naccount = \"work\"\ncredential = \"imgchest:work\"\n\n[[post.files]]\npath = \"a.png\"\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn orient_file_at_path_works() {
+        let dir = TestDir::new("orient-file-at-path-works");
+
+        let image = image::RgbImage::from_pixel(2, 1, image::Rgb([255, 0, 0]));
+        let mut plain = Vec::new();
+        image
+            .write_to(
+                &mut std::io::Cursor::new(&mut plain),
+                image::ImageFormat::Jpeg,
+            )
+            .expect("failed to encode jpeg");
+
+        // An exif segment with an orientation of 6, which is a 90 degree rotation.
+        let mut exif =
+            b"Exif\0\0II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0".to_vec();
+        let exif_len = u16::try_from(exif.len() + 2).unwrap();
+        exif.splice(0..0, [0xFF, 0xE1]);
+        exif.splice(2..2, exif_len.to_be_bytes());
+        let mut rotated = plain.clone();
+        rotated.splice(2..2, exif);
+
+        let plain_path = dir.path.join("plain.jpg");
+        let rotated_path = dir.path.join("rotated.jpg");
+        std::fs::write(&plain_path, &plain).expect("failed to write plain.jpg");
+        std::fs::write(&rotated_path, &rotated).expect("failed to write rotated.jpg");
+
+        assert!(orient_file_at_path(&plain_path)
+            .expect("failed to orient plain.jpg")
+            .is_none());
+
+        let oriented = orient_file_at_path(&rotated_path)
+            .expect("failed to orient rotated.jpg")
+            .expect("rotated.jpg was not reoriented");
+        let oriented = image::load_from_memory(&oriented).expect("failed to decode oriented image");
+        assert!((oriented.width(), oriented.height()) == (1, 2));
+
+        let (sha256, orient) = hash_local_file(&rotated_path, true).expect("failed to hash");
+        assert!(orient);
+        let bytes =
+            read_oriented_file(&rotated_path, &sha256).expect("failed to read oriented file");
+        assert!(sha256 == hash_bytes(&bytes));
+        assert!(read_oriented_file(&rotated_path, &hash_bytes(&rotated)).is_err());
+
+        // Reoriented hashes are cached, but only used with --auto-orient.
+        let metadata = std::fs::metadata(&rotated_path).expect("failed to get metadata");
+        let mut hash_cache = HashCache::default();
+        hash_cache.insert("rotated.jpg".into(), &metadata, sha256.clone(), Some(true));
+        assert!(hash_cache.get("rotated.jpg", &metadata, true) == Some((sha256.as_str(), true)));
+        assert!(hash_cache.get("rotated.jpg", &metadata, false).is_none());
+
+        let (sha256, orient) = hash_local_file(&rotated_path, false).expect("failed to hash");
+        assert!(!orient);
+        assert!(sha256 == hash_bytes(&rotated));
+    }
+
+    #[test]
+    fn hash_local_file_with_db_works() {
+        let dir = TestDir::new("hash-local-file-with-db-works");
+        dir.write("a.png", "a");
+        let path = dir.path.join("a.png");
+        let hash_db = HashDb::open(&dir.path.join("hashes.db")).expect("failed to open hash db");
+
+        let (sha256, _) =
+            hash_local_file_with_db(&hash_db, &path, false, true, true).expect("failed to hash");
+        assert!(sha256 == hash_bytes(b"a"));
+
+        // Unchanged files use the stored hash, even if it is wrong.
+        let metadata = std::fs::metadata(&path).expect("failed to get metadata");
+        hash_db
+            .insert(&path, &metadata, "stored", None)
+            .expect("failed to insert hash");
+        let (sha256, _) =
+            hash_local_file_with_db(&hash_db, &path, false, true, true).expect("failed to hash");
+        assert!(sha256 == "stored");
+        assert!(hash_db
+            .get(&path, &metadata, true)
+            .expect("failed to get hash")
+            .is_none());
+
+        // Changed files are hashed again.
+        dir.write("a.png", "changed");
+        let (sha256, _) =
+            hash_local_file_with_db(&hash_db, &path, false, true, true).expect("failed to hash");
+        assert!(sha256 == hash_bytes(b"changed"));
+        let metadata = std::fs::metadata(&path).expect("failed to get metadata");
+        assert!(
+            hash_db
+                .get(&path, &metadata, false)
+                .expect("failed to get hash")
+                .map(|(sha256, _)| sha256)
+                == Some(hash_bytes(b"changed"))
+        );
+    }
+
+    #[test]
+    fn ensure_animated_gif_size_works() {
+        let dir = TestDir::new("ensure-animated-gif-size-works");
+
+        let encode_gif = |num_frames: u8| {
+            let mut bytes = Vec::new();
+            let mut encoder = image::codecs::gif::GifEncoder::new(&mut bytes);
+            let frames = (0..num_frames).map(|i| {
+                let buffer = image::RgbaImage::from_pixel(2, 2, image::Rgba([i * 100, 0, 0, 255]));
+                image::Frame::new(buffer)
+            });
+            encoder.encode_frames(frames).expect("failed to encode gif");
+            drop(encoder);
+            bytes
+        };
+        let animated_path = dir.path.join("animated.gif");
+        let still_path = dir.path.join("still.gif");
+        std::fs::write(&animated_path, encode_gif(2)).expect("failed to write animated.gif");
+        std::fs::write(&still_path, encode_gif(1)).expect("failed to write still.gif");
+
+        let error = block_on(ensure_animated_gif_size(&animated_path, 1))
+            .expect_err("large animated gif was not rejected");
+        assert!(error.to_string().contains("animated gif"), "{error:?}");
+        block_on(ensure_animated_gif_size(&animated_path, 1 << 20))
+            .expect("small animated gif was rejected");
+        block_on(ensure_animated_gif_size(&still_path, 1)).expect("still gif was rejected");
+    }
+
+    #[test]
+    fn write_output_ids_works() {
+        let dir = TestDir::new("write-output-ids-works");
+        let mut output_ids = BTreeMap::new();
+        output_ids.insert(
+            String::from("gallery"),
+            OutputId {
+                id: String::from("abc"),
+                url: get_post_url("abc"),
+            },
+        );
+
+        let toml_path = dir.path.join("ids.toml");
+        let json_path = dir.path.join("ids.json");
+        block_on(write_output_ids(&toml_path, &output_ids)).expect("failed to write toml");
+        block_on(write_output_ids(&json_path, &output_ids)).expect("failed to write json");
+
+        let toml_ids: toml::Table = toml::from_str(&std::fs::read_to_string(&toml_path).unwrap())
+            .expect("failed to parse toml");
+        assert!(toml_ids["gallery"]["id"].as_str() == Some("abc"));
+        assert!(toml_ids["gallery"]["url"].as_str() == Some("https://imgchest.com/p/abc"));
+
+        let json_ids: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json_path).unwrap())
+                .expect("failed to parse json");
+        assert!(json_ids["gallery"]["id"] == "abc");
+        assert!(json_ids["gallery"]["url"] == "https://imgchest.com/p/abc");
+    }
+
+    #[test]
+    fn describe_status_works() {
+        let old_post = post_from_hashes(&["a", "b", "c"]);
+        let mut new_post = post_from_hashes(&["a", "c", "d"]);
+        new_post.title = String::from("new title");
+        new_post.privacy = PostPrivacy::Public;
+
+        let diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        let changes = crate::commands::status::describe_status(&old_post, &new_post, &diffs);
+        assert!(changes.len() == 3);
+        assert!(changes[0] == "title will change from \"title\" to \"new title\"");
+        assert!(changes[1] == "privacy will change from Hidden to Public");
+        assert!(changes[2].starts_with("files will change from 3 to 3: 1 added, 1 removed"));
+
+        let diffs = generate_post_diffs(&old_post, &old_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        assert!(crate::commands::status::describe_status(&old_post, &old_post, &diffs).is_empty());
+    }
+
+    #[test]
+    fn status_post_is_offline() {
+        let ctx = mock_context(&[]);
+        let dir = create_test_post("status-post-is-offline", &ctx);
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+        let cache = std::fs::read_to_string(&cache_path).expect("missing cache");
+
+        let status = |config: &str| {
+            let mut config = Config::new(config).expect("failed to parse config");
+            block_on(crate::commands::status::status_post(
+                &ctx,
+                &dir.path,
+                &mut config,
+                Some(&cache_path),
+            ))
+            .expect("failed to get status")
+            .0
+        };
+
+        assert!(!status(&dir.read_config()));
+        assert!(status(&dir.read_config().replacen(
+            "[post]",
+            "[post]\ntitle = \"new\"",
+            1
+        )));
+        assert!(ctx.client.take_calls().is_empty());
+        assert!(std::fs::read_to_string(&cache_path).expect("missing cache") == cache);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn subcommands_leave_out_generated_files() {
+        let dir = TestPostDir::new(
+            "subcommands-leave-out-generated-files",
+            &[("a.png", "a")],
+            "[post]\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\nname = \"gen.png\"\ncommand = [\"sh\", \"-c\", \"touch ran && printf gen\"]\n",
+        );
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+        let ran_path = dir.path.join("ran");
+
+        let ctx = mock_context(&["--allow-file-commands"]);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        std::fs::remove_file(&ran_path).expect("command did not run");
+
+        // Subcommands never run commands, and keep the synced copy of the generated file.
+        let mut status_ctx = mock_context(&["--allow-file-commands", "status"]);
+        status_ctx.client.state = ctx.client.state.clone();
+        let mut config = Config::new(&dir.read_config()).expect("failed to parse config");
+        let (changed, _) = block_on(crate::commands::status::status_post(
+            &status_ctx,
+            &dir.path,
+            &mut config,
+            Some(&cache_path),
+        ))
+        .expect("failed to get status");
+        assert!(!changed);
+
+        let mut compare_ctx = mock_context(&["--allow-file-commands", "compare"]);
+        compare_ctx.client.state = ctx.client.state.clone();
+        compare_ctx.options.api_base = Some(serve_files(&[("file-2", "a"), ("file-3", "gen")]));
+        let mut config = Config::new(&dir.read_config()).expect("failed to parse config");
+        let (is_drifted, _) = block_on(crate::commands::compare::compare_post(
+            &compare_ctx,
+            &dir.path,
+            &mut config,
+            Some(&cache_path),
+        ))
+        .expect("failed to compare");
+        assert!(!is_drifted);
+        assert!(!ran_path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sync_post_requires_allow_file_commands() {
+        let dir = TestPostDir::new(
+            "sync-post-requires-allow-file-commands",
+            &[],
+            "[post]\n\n[[post.files]]\nname = \"gen.png\"\ncommand = [\"printf\", \"gen\"]\n",
+        );
+
+        let ctx = mock_context(&[]);
+        let error = format!(
+            "{:#}",
+            dir.try_sync(&ctx)
+                .expect_err("command ran without --allow-file-commands")
+        );
+        assert!(error.contains("--allow-file-commands"), "{error}");
+        assert!(ctx.client.take_calls().is_empty());
+
+        let ctx = mock_context(&["--allow-file-commands"]);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        assert!(ctx.client.take_calls() == [MockCall::CreatePost { num_files: 1 }]);
+    }
+
+    #[test]
+    fn collect_post_credentials_works() {
+        let dir = TestDir::new("collect-post-credentials");
+        for (name, config) in [
+            (
+                "a",
+                "[post]\ncredential = \"imgchest:work\"\n[[post.files]]\npath = \"a.png\"\n",
+            ),
+            (
+                "b",
+                "[post]\ncredential = \"imgchest:work\"\n[[post.files]]\npath = \"a.png\"\n",
+            ),
+            (
+                "c",
+                "[post]\ncredential = \"imgchest:home\"\n[[post.files]]\npath = \"a.png\"\n",
+            ),
+            ("d", "[post]\n[[post.files]]\npath = \"a.png\"\n"),
+        ] {
+            std::fs::create_dir(dir.path.join(name)).expect("failed to create dir");
+            dir.write(&format!("{name}/imgchest-sync.toml"), config);
+        }
+        std::fs::create_dir(dir.path.join("e")).expect("failed to create dir");
+
+        let ctx = mock_context(&["--input", dir.path.as_str()]);
+        let credentials = block_on(collect_post_credentials(&ctx.options))
+            .expect("failed to collect credentials");
+        assert!(credentials.iter().eq(["imgchest:home", "imgchest:work"]));
+
+        let config_path = dir.path.join("c/imgchest-sync.toml");
+        let ctx = mock_context(&["--config", config_path.as_str()]);
+        let credentials = block_on(collect_post_credentials(&ctx.options))
+            .expect("failed to collect credentials");
+        assert!(credentials.iter().eq(["imgchest:home"]));
+    }
+
+    #[test]
+    fn token_fingerprint_works() {
+        assert!(self::commands::tokens::fingerprint("abc") == "ba7816bf8f01");
+    }
+
+    #[test]
+    fn write_summary_works() {
+        let dir = TestDir::new("write-summary-works");
+        let mut summary = RunSummary::default();
+        summary.record(
+            "first",
+            &SyncOutcome {
+                stats: Stats::new(),
+                quit: false,
+                action: SyncAction::Created,
+                post_id: Some(String::from("abc")),
+                posts: Vec::new(),
+            },
+        );
+        summary.record_failure("second", &anyhow::anyhow!("failed to sync"));
+        let mut total_stats = Stats::new();
+        total_stats.bytes_uploaded = 5;
+
+        let summary_path = dir.path.join("summary.json");
+        block_on(write_summary(
+            &summary_path,
+            summary,
+            &total_stats,
+            Instant::now(),
+        ))
+        .expect("failed to write summary");
+
+        let raw = std::fs::read_to_string(&summary_path).expect("failed to read summary");
+        let value: serde_json::Value = serde_json::from_str(&raw).expect("invalid json");
+        assert!(value["created"] == 1);
+        assert!(value["failed"] == 1);
+        assert!(value["bytes_uploaded"] == 5);
+        assert!(value["directories"][0]["name"] == "first");
+        assert!(value["directories"][0]["result"] == "created");
+        assert!(value["directories"][0]["post_id"] == "abc");
+        assert!(value["directories"][1]["result"] == "failed");
+        assert!(value["directories"][1]["error"] == "failed to sync");
+    }
+
+    #[test]
+    fn format_trace_headers_redacts() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        headers.insert("cookie", "session=secret".parse().unwrap());
+        headers.insert("user-agent", "imgchest-sync".parse().unwrap());
+
+        let lines = crate::api::format_trace_headers(&headers);
+        assert!(lines.contains(&String::from("authorization: <redacted>")));
+        assert!(lines.contains(&String::from("cookie: <redacted>")));
+        assert!(lines.contains(&String::from("user-agent: imgchest-sync")));
+        assert!(!lines.iter().any(|line| line.contains("secret")));
+    }
+
+    #[test]
+    fn token_stdin_conflicts_with_token() {
+        let options = <Options as argh::FromArgs>::from_args(
+            &["imgchest-sync"],
+            &["--token", "a", "--token-stdin"],
+        )
+        .expect("failed to parse options");
+        let config = UserConfig::new("").expect("failed to parse user config");
+        let error = block_on(resolve_default_token(&options, &config))
+            .expect_err("--token-stdin was ignored");
+        assert!(error
+            .to_string()
+            .contains("--token-stdin cannot be used with --token"));
+    }
+
+    #[test]
+    fn dump_config_redacts_headers() {
+        let config = UserConfig::new(
+            "token = \"secret\"\n\n[http.headers]\nAuthorization = \"Bearer secret\"\nCookie = \"session=secret\"\nX-Trace = \"visible\"\n",
+        )
+        .expect("failed to parse user config");
+
+        let dump = crate::commands::config::dump_config(&config).to_string();
+        assert!(dump.contains("Authorization = \"<redacted>\""), "{dump}");
+        assert!(dump.contains("Cookie = \"<redacted>\""), "{dump}");
+        assert!(dump.contains("X-Trace = \"visible\""), "{dump}");
+        assert!(!dump.contains("secret"), "{dump}");
+    }
+
+    #[test]
+    fn apply_content_type_renames_files() {
+        let dir = TestPostDir::new(
+            "apply-content-type-renames-files",
+            &[("a.jpg", "a"), ("b.JPEG", "b")],
+            TEST_POST_CONFIG,
+        );
+        let path_file = |file_name: &str| PostFile {
+            description: String::new(),
+            sha256: String::new(),
+            id: None,
+            path: Some(dir.path.join(file_name)),
+            data: None,
+            orient: false,
+            upload_name: None,
+        };
+
+        let mut file = path_file("a.jpg");
+        apply_content_type(&mut file, PostConfigContentType::Png)
+            .expect("failed to apply content type");
+        assert!(file.data.is_none());
+        assert!(file.upload_name.as_deref() == Some("a.png"));
+        assert!(upload_file_name(&file).ok() == Some("a.png"));
+
+        let mut file = path_file("b.JPEG");
+        apply_content_type(&mut file, PostConfigContentType::Jpeg)
+            .expect("failed to apply content type");
+        assert!(file.data.is_none());
+        assert!(file.upload_name.is_none());
+
+        assert!(Config::new(
+            "[post]\n\n[[post.files]]\npath = \"a.jpg\"\ncontent_type = \"image/png\"\n"
+        )
+        .is_ok());
+        assert!(Config::new(
+            "[post]\n\n[[post.files]]\npath = \"a.jpg\"\ncontent_type = \"text/html\"\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn content_type_allows_other_extensions() {
+        let dir = TestPostDir::new(
+            "content-type-allows-other-extensions",
+            &[("scan.bin", "a")],
+            "[post]\n\n[[post.files]]\npath = \"scan.bin\"\n",
+        );
+
+        // Without a content type, the file is uploaded as a .bin file.
+        let ctx = mock_context(&[]);
+        let error = format!(
+            "{:#}",
+            dir.try_sync(&ctx).expect_err("bin file was not rejected")
+        );
+        assert!(error.contains("\"BIN\" files"), "{error}");
+        assert!(ctx.client.take_calls().is_empty());
+
+        // With one, the file is uploaded with the extension of the content type.
+        let config = dir.read_config().replace(
+            "path = \"scan.bin\"\n",
+            "path = \"scan.bin\"\ncontent_type = \"image/png\"\n",
+        );
+        dir.write("imgchest-sync.toml", &config);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        assert!(ctx.client.take_calls() == [MockCall::CreatePost { num_files: 1 }]);
+    }
+
+    #[test]
+    fn open_upload_file_moves_data() {
+        let mut file = PostFile {
+            description: String::new(),
+            sha256: String::new(),
+            id: None,
+            path: None,
+            data: Some(PostFileData {
+                file_name: String::from("a.png"),
+                bytes: vec![0; 16],
+            }),
+            orient: false,
+            upload_name: None,
+        };
+
+        let (_upload_file, size) =
+            block_on(open_upload_file(&mut file)).expect("failed to open upload file");
+        assert!(size == 16);
+        assert!(file.data.is_none());
+    }
+
+    #[test]
+    fn unique_file_name_works() {
+        let names: std::collections::HashSet<String> =
+            ["a.png", "1-a.png"].into_iter().map(String::from).collect();
+        let unique_file_name = crate::commands::export::unique_file_name;
+        assert!(unique_file_name(&names, 0, "b.png") == "b.png");
+        assert!(unique_file_name(&names, 1, "a.png") == "2-a.png");
+        assert!(unique_file_name(&names, 2, "a.png") == "2-a.png");
+    }
+
+    #[test]
+    fn is_large_removal_works() {
+        assert!(!is_large_removal(10, 0, 50));
+        assert!(!is_large_removal(0, 0, 50));
+        assert!(!is_large_removal(10, 5, 50));
+        assert!(is_large_removal(10, 6, 50));
+        assert!(is_large_removal(10, 1, 0));
+        assert!(!is_large_removal(10, 10, 100));
+    }
+
+    #[test]
+    fn sync_post_finds_case_collisions() {
+        let config = "[post]\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"A.PNG\"\n\n[[post.files]]\npath = \"b.png\"\n";
+        let dir = TestPostDir::new(
+            "sync-post-finds-case-collisions",
+            &[("a.png", "a"), ("A.PNG", "A"), ("b.png", "b")],
+            config,
+        );
+
+        let ctx = mock_context(&["--strict"]);
+        let result = dir.try_sync(&ctx);
+        let error = format!("{:#}", result.expect_err("case collision was not reported"));
+        assert!(error.contains("only differ in case"), "{error}");
+        assert!(ctx.client.take_calls().is_empty());
+
+        // Without --strict, this is only a warning.
+        let ctx = mock_context(&[]);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        assert!(ctx.client.take_calls() == [MockCall::CreatePost { num_files: 3 }]);
+    }
+
+    #[test]
+    fn sync_post_skips_disabled_posts() {
+        let ctx = mock_context(&[]);
+        let dir = create_test_post("sync-post-skips-disabled-posts", &ctx);
+        let config = dir
+            .read_config()
+            .replace("[post]\n", "[post]\nenabled = false\n");
+        dir.write("imgchest-sync.toml", &config);
+        dir.write("b.png", "changed");
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Skipped);
+        assert!(ctx.client.take_calls().is_empty());
+        assert!(dir.read_config() == config);
+
+        // The other modes of --config skip it as well.
+        let config_path = dir.path.join("imgchest-sync.toml");
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+        let cache = std::fs::read_to_string(&cache_path).expect("missing cache");
+        for args in [
+            &["publish"][..],
+            &["status"],
+            &["compare"],
+            &["--hash-only", "--cache", cache_path.as_str()],
+            &["--refresh-cache", "--cache", cache_path.as_str()],
+        ] {
+            let mut mode_ctx = mock_context(args);
+            mode_ctx.options.config = Some(config_path.clone());
+            mode_ctx.client.state = ctx.client.state.clone();
+            block_on(run(&mode_ctx)).expect("failed to run");
+            assert!(ctx.client.take_calls().is_empty(), "{args:?}");
+            assert!(dir.read_config() == config, "{args:?}");
+            assert!(
+                std::fs::read_to_string(&cache_path).expect("missing cache") == cache,
+                "{args:?}"
+            );
+            assert!(!get_hash_cache_path(&cache_path).exists(), "{args:?}");
+        }
+
+        let config = config.replace("enabled = false", "enabled = true");
+        dir.write("imgchest-sync.toml", &config);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+    }
+
+    #[test]
+    fn sync_post_requires_cache_with_cache_diff_base() {
+        let ctx = mock_context(&["--diff-base", "cache"]);
+        let dir = create_test_post("sync-post-requires-cache-with-cache-diff-base", &ctx);
+
+        // The cache is used as normal.
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
+        assert!(ctx.client.take_calls().is_empty());
+
+        std::fs::remove_file(dir.path.join(".imgchest-sync-cache.toml"))
+            .expect("failed to remove cache");
+        let result = dir.try_sync(&ctx);
+        let error = format!("{:#}", result.expect_err("missing cache was not reported"));
+        assert!(error.contains("has no cache"), "{error}");
+        assert!(ctx.client.take_calls().is_empty());
+    }
+
+    #[test]
+    fn sync_post_downloads_remote_with_remote_diff_base() {
+        let ctx = mock_context(&[]);
+        let dir = create_test_post("sync-post-downloads-remote-with-remote-diff-base", &ctx);
+        ctx.client
+            .state
+            .lock()
+            .unwrap()
+            .posts
+            .get_mut("post-1")
+            .unwrap()
+            .title = Some(String::from("changed online"));
+
+        // The cache does not know about the remote change.
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
+        assert!(ctx.client.take_calls().is_empty());
+
+        let mut remote_ctx = mock_context(&["--diff-base", "remote"]);
+        remote_ctx.client.state = ctx.client.state.clone();
+        remote_ctx.options.api_base = Some(serve_files(&[("file-2", "a"), ("file-3", "b")]));
+        let outcome = dir.sync(&remote_ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        let post_id = String::from("post-1");
+        assert!(
+            remote_ctx.client.take_calls()
+                == [
+                    MockCall::GetPost {
+                        id: post_id.clone()
+                    },
+                    MockCall::UpdatePost { id: post_id }
+                ]
+        );
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        assert!(remote_post.title.as_deref() == dir.path.file_name());
+    }
+
+    #[test]
+    fn sync_post_uses_nsfw_marker() {
+        let dir = TestPostDir::new(
+            "sync-post-uses-nsfw-marker",
+            &[("a.png", "a"), ("b.png", "b"), (".nsfw", "")],
+            TEST_POST_CONFIG,
+        );
+        let is_remote_nsfw = |ctx: &SyncContext<MockApi>| {
+            let mut state = ctx.client.state.lock().unwrap();
+            let id = state.posts.keys().next().unwrap().clone();
+            state.posts.remove(&id).unwrap().nsfw
+        };
+
+        // The marker is ignored unless it is enabled in the user config.
+        let ctx = mock_context(&[]);
+        let mut config = Config::new(TEST_POST_CONFIG).expect("failed to parse config");
+        block_on(sync_post(&ctx, &dir.path, &mut config, None, None)).expect("failed to sync");
+        assert!(!is_remote_nsfw(&ctx));
+
+        let mut ctx = mock_context(&[]);
+        ctx.nsfw_marker = true;
+        let mut config = Config::new(TEST_POST_CONFIG).expect("failed to parse config");
+        block_on(sync_post(&ctx, &dir.path, &mut config, None, None)).expect("failed to sync");
+        assert!(is_remote_nsfw(&ctx));
+
+        // An explicit config value takes precedence.
+        let config_raw = TEST_POST_CONFIG.replace("[post]\n", "[post]\nnsfw = false\n");
+        let mut config = Config::new(&config_raw).expect("failed to parse config");
+        block_on(sync_post(&ctx, &dir.path, &mut config, None, None)).expect("failed to sync");
+        assert!(!is_remote_nsfw(&ctx));
+
+        std::fs::remove_file(dir.path.join(".nsfw")).expect("failed to remove marker");
+        let mut config = Config::new(TEST_POST_CONFIG).expect("failed to parse config");
+        block_on(sync_post(&ctx, &dir.path, &mut config, None, None)).expect("failed to sync");
+        assert!(!is_remote_nsfw(&ctx));
+    }
+
+    #[test]
+    fn sync_post_reports_every_unreadable_file() {
+        let config = "[post]\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"b.png\"\n\n[[post.files]]\npath = \"c.png\"\n";
+        let dir = TestPostDir::new(
+            "sync-post-reports-every-unreadable-file",
+            &[("a.png", "a")],
+            config,
+        );
+
+        let ctx = mock_context(&[]);
+        let result = dir.try_sync(&ctx);
+        let error = format!(
+            "{:#}",
+            result.expect_err("unreadable files were not reported")
+        );
+        assert!(error.contains("failed to read 2 files"), "{error}");
+        assert!(
+            error.contains("b.png") && error.contains("c.png"),
+            "{error}"
+        );
+        assert!(ctx.client.take_calls().is_empty());
+
+        let ctx = mock_context(&["--skip-unreadable"]);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        assert!(ctx.client.take_calls() == [MockCall::CreatePost { num_files: 1 }]);
+
+        dir.write("b.png", "b");
+        dir.write("c.png", "c");
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        ctx.client.take_calls();
+
+        // Files that become unreadable keep their remote copy.
+        std::fs::remove_file(dir.path.join("b.png")).expect("failed to remove file");
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
+        assert!(ctx.client.take_calls().is_empty());
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        assert!(remote_post.images.len() == 3);
+    }
+
+    #[test]
+    fn sync_post_verifies_token_scope() {
+        let ctx = mock_context(&["--verify-token-scope"]);
+        let dir = create_test_post("sync-post-verifies-token-scope", &ctx);
+        ctx.client.state.lock().unwrap().read_only = true;
+
+        let config = dir
+            .read_config()
+            .replace("[[post.files]]\npath = \"b.png\"\n", "");
+        dir.write("imgchest-sync.toml", &config);
+
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+        let result = dir.try_sync(&ctx);
+        let error = format!(
+            "{:#}",
+            result.expect_err("read-only token was not detected")
+        );
+        assert!(error.contains("cannot modify posts"), "{error}");
+        let post_id = String::from("post-1");
+        assert!(
+            ctx.client.take_calls()
+                == [
+                    MockCall::GetPost {
+                        id: post_id.clone()
+                    },
+                    MockCall::UpdatePost { id: post_id }
+                ]
+        );
+        assert!(cache_path.exists());
+
+        // Failures are cached for the rest of the run, so the token is not probed again.
+        let result = dir.try_sync(&ctx);
+        let error = format!("{:#}", result.expect_err("cached failure was ignored"));
+        assert!(error.contains("cannot modify posts"), "{error}");
+        assert!(ctx.client.take_calls().is_empty());
+
+        // Successes are cached too.
+        ctx.client.state.lock().unwrap().read_only = false;
+        ctx.verified_accounts.lock().unwrap().clear();
+        dir.sync(&ctx);
+        ctx.client.take_calls();
+        dir.write(
+            "imgchest-sync.toml",
+            &format!("{}\n[[post.files]]\npath = \"b.png\"\n", dir.read_config()),
+        );
+        dir.sync(&ctx);
+        assert!(
+            ctx.client.take_calls()
+                == [MockCall::AddPostImages {
+                    id: String::from("post-1"),
+                    num_files: 1
+                }]
+        );
+    }
+
+    #[test]
+    fn sync_album_splits_posts() {
+        let ctx = mock_context(&[]);
+        let dir = TestPostDir::new(
+            "sync-album-splits-posts",
+            &[("a.png", "a"), ("b.png", "b"), ("c.png", "c")],
+            "[post]\ntitle = \"album\"\nalbum = true\nalbum_chunk_size = 2\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"b.png\"\n\n[[post.files]]\npath = \"c.png\"\n",
+        );
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        assert!(outcome.post_id.as_deref() == Some("post-1"));
+        assert!(
+            ctx.client.take_calls()
+                == [
+                    MockCall::CreatePost { num_files: 2 },
+                    MockCall::CreatePost { num_files: 1 },
+                ]
+        );
+        assert!(dir.read_config().contains("ids = [\"post-1\", \"post-4\"]"));
+        {
+            let state = ctx.client.state.lock().unwrap();
+            let first_post = state.get_post("post-1").unwrap();
+            assert!(first_post.title.as_deref() == Some("album (1/2)"));
+            let second_post = state.get_post("post-4").unwrap();
+            assert!(second_post.title.as_deref() == Some("album (2/2)"));
+        }
+
+        // Shrinking the album to one post renames the first post and leaves the second unused.
+        let config = dir
+            .read_config()
+            .replace("[[post.files]]\npath = \"c.png\"\n", "");
+        dir.write("imgchest-sync.toml", &config);
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        assert!(
+            ctx.client.take_calls()
+                == [MockCall::UpdatePost {
+                    id: String::from("post-1")
+                }]
+        );
+        {
+            let state = ctx.client.state.lock().unwrap();
+            let first_post = state.get_post("post-1").unwrap();
+            assert!(first_post.title.as_deref() == Some("album"));
+        }
+
+        // With --yes, the unused post is deleted.
+        let mut ctx = ctx;
+        ctx.options.yes = true;
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        assert!(
+            ctx.client.take_calls()
+                == [MockCall::DeletePost {
+                    id: String::from("post-4")
+                }]
+        );
+        assert!(dir.read_config().contains("ids = [\"post-1\"]"));
+    }
+
+    #[test]
+    fn sync_deleted_album_keeps_progress() {
+        let ctx = mock_context(&["--yes"]);
+        let dir = TestPostDir::new(
+            "sync-deleted-album-keeps-progress",
+            &[("a.png", "a"), ("b.png", "b")],
+            "[post]\ntitle = \"album\"\nalbum = true\nalbum_chunk_size = 1\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"b.png\"\n",
+        );
+        dir.sync(&ctx);
+        ctx.client.take_calls();
+        assert!(dir.read_config().contains("ids = [\"post-1\", \"post-3\"]"));
+
+        // Deleting the first post fails, after the second post was deleted.
+        ctx.client.state.lock().unwrap().posts.remove("post-1");
+        let config = dir
+            .read_config()
+            .replacen("[post]", "[post]\ndeleted = true", 1);
+        dir.write("imgchest-sync.toml", &config);
+        assert!(dir.try_sync(&ctx).is_err());
+        assert!(
+            ctx.client.take_calls()
+                == [
+                    MockCall::DeletePost {
+                        id: String::from("post-3")
+                    },
+                    MockCall::DeletePost {
+                        id: String::from("post-1")
+                    },
+                ]
+        );
+        assert!(dir.read_config().contains("ids = [\"post-1\"]"));
+    }
+
+    #[test]
+    fn album_flags_handle_each_post() {
+        let mut ctx = mock_context(&[]);
+        let dir = TestPostDir::new(
+            "album-flags-handle-each-post",
+            &[("a.png", "a"), ("b.png", "b")],
+            "[post]\ntitle = \"album\"\nalbum = true\nalbum_chunk_size = 1\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"b.png\"\n",
+        );
+        dir.sync(&ctx);
+        ctx.client.take_calls();
+        assert!(dir.read_config().contains("ids = [\"post-1\", \"post-3\"]"));
+
+        // --hash-only and --refresh-cache write the caches of every post.
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+        for i in 0..2 {
+            let chunk_cache_path = get_album_cache_path(&cache_path, i);
+            std::fs::remove_file(&chunk_cache_path).expect("failed to remove cache");
+        }
+        ctx.options.api_base = Some(serve_files(&[("file-2", "a"), ("file-4", "b")]));
+        block_on(async {
+            let mut config = Config::new(&dir.read_config())?;
+            hash_post(&ctx, &dir.path, &mut config, &cache_path).await?;
+            refresh_cache(&ctx, &dir.path, &mut config, &cache_path).await
+        })
+        .expect("failed to hash and refresh album");
+        for i in 0..2 {
+            let chunk_cache_path = get_album_cache_path(&cache_path, i);
+            assert!(chunk_cache_path.exists());
+            assert!(get_hash_cache_path(&chunk_cache_path).exists());
+        }
+        assert!(
+            ctx.client.take_calls()
+                == [
+                    MockCall::GetPost {
+                        id: String::from("post-1")
+                    },
+                    MockCall::GetPost {
+                        id: String::from("post-3")
+                    },
+                ]
+        );
+
+        // --force-recreate deletes every post before recreating the album.
+        ctx.options.api_base = None;
+        ctx.options.force_recreate = true;
+        ctx.options.yes = true;
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        assert!(
+            ctx.client.take_calls()
+                == [
+                    MockCall::DeletePost {
+                        id: String::from("post-3")
+                    },
+                    MockCall::DeletePost {
+                        id: String::from("post-1")
+                    },
+                    MockCall::CreatePost { num_files: 1 },
+                    MockCall::CreatePost { num_files: 1 },
+                ]
+        );
+        assert!(!dir.read_config().contains("\"post-1\""));
+    }
+
+    #[test]
+    fn compare_checks_album_posts() {
+        let ctx = mock_context(&[]);
+        let dir = TestPostDir::new(
+            "compare-checks-album-posts",
+            &[("a.png", "a"), ("b.png", "b")],
+            "[post]\ntitle = \"album\"\nalbum = true\nalbum_chunk_size = 1\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"b.png\"\n",
+        );
+        dir.sync(&ctx);
+        ctx.client.take_calls();
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+
+        let mut compare_ctx = mock_context(&["compare"]);
+        compare_ctx.client.state = ctx.client.state.clone();
+        compare_ctx.options.api_base = Some(serve_files(&[("file-2", "a"), ("file-4", "b")]));
+        let mut config = Config::new(&dir.read_config()).expect("failed to parse config");
+        let (is_drifted, _) = block_on(crate::commands::compare::compare_post(
+            &compare_ctx,
+            &dir.path,
+            &mut config,
+            Some(&cache_path),
+        ))
+        .expect("failed to compare");
+        assert!(!is_drifted);
+        assert!(
+            compare_ctx.client.take_calls()
+                == [
+                    MockCall::GetPost {
+                        id: String::from("post-1")
+                    },
+                    MockCall::GetPost {
+                        id: String::from("post-3")
+                    },
+                ]
+        );
+    }
+
+    #[test]
+    fn status_checks_album_posts() {
+        let ctx = mock_context(&[]);
+        let dir = TestPostDir::new(
+            "status-checks-album-posts",
+            &[("a.png", "a"), ("b.png", "b")],
+            "[post]\ntitle = \"album\"\nalbum = true\nalbum_chunk_size = 1\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"b.png\"\n",
+        );
+        dir.sync(&ctx);
+        ctx.client.take_calls();
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+
+        let status = |config: &str| {
+            let mut config = Config::new(config).expect("failed to parse config");
+            block_on(crate::commands::status::status_post(
+                &ctx,
+                &dir.path,
+                &mut config,
+                Some(&cache_path),
+            ))
+            .expect("failed to get status")
+            .0
+        };
+        assert!(!status(&dir.read_config()));
+        dir.write("b.png", "changed");
+        assert!(status(&dir.read_config()));
+        assert!(ctx.client.take_calls().is_empty());
+    }
+
+    #[test]
+    fn sync_album_truncates_titles() {
+        let ctx = mock_context(&["--max-title-length", "12", "--truncate-titles"]);
+        let dir = TestPostDir::new(
+            "sync-album-truncates-titles",
+            &[("a.png", "a"), ("b.png", "b")],
+            "[post]\ntitle = \"a long album\"\nalbum = true\nalbum_chunk_size = 1\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"b.png\"\n",
+        );
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        let state = ctx.client.state.lock().unwrap();
+        let first_post = state.get_post("post-1").unwrap();
+        assert!(first_post.title.as_deref() == Some("a lon… (1/2)"));
+        let second_post = state.get_post("post-3").unwrap();
+        assert!(second_post.title.as_deref() == Some("a lon… (2/2)"));
+    }
+
+    #[test]
+    fn sync_post_metadata_only() {
+        let ctx = mock_context(&[]);
+        let dir = create_test_post("sync-post-metadata-only", &ctx);
+
+        // Files are never read, so removed and changed files are ignored.
+        std::fs::remove_file(dir.path.join("a.png")).expect("failed to remove file");
+        dir.write("b.png", "c");
+        let config = dir
+            .read_config()
+            .replacen("[post]", "[post]\ntitle = \"renamed\"", 1);
+        dir.write("imgchest-sync.toml", &config);
+
+        let mut metadata_ctx = mock_context(&["--metadata-only"]);
+        metadata_ctx.client.state = ctx.client.state.clone();
+        let outcome = dir.sync(&metadata_ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        let post_id = outcome.post_id.expect("missing post id");
+        assert!(
+            metadata_ctx.client.take_calls()
+                == [MockCall::UpdatePost {
+                    id: post_id.clone()
+                }]
+        );
+
+        let state = ctx.client.state.lock().unwrap();
+        let remote_post = state.get_post(&post_id).unwrap();
+        assert!(remote_post.title.as_deref() == Some("renamed"));
+        assert!(remote_post.images.len() == 2);
+    }
+
+    #[test]
+    fn sync_post_resumes_partial_create() {
+        let file_names: Vec<String> = (0..CREATE_BATCH_SIZE + 5)
+            .map(|i| format!("{i}.png"))
+            .collect();
+        let files: Vec<(&str, &str)> = file_names
+            .iter()
+            .map(|file_name| (file_name.as_str(), file_name.as_str()))
+            .collect();
+        let mut config = String::from("[post]\n");
+        for file_name in file_names.iter() {
+            config.push_str(&format!("\n[[post.files]]\npath = \"{file_name}\"\n"));
+        }
+        let dir = TestPostDir::new("sync-post-resumes-partial-create", &files, &config);
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+
+        // Fail the second batch.
+        let ctx = mock_context(&[]);
+        ctx.client.state.lock().unwrap().fail_file_adds = true;
+        let result = dir.try_sync(&ctx);
+        assert!(result.is_err());
+        assert!(dir.read_config().contains("id = \"post-1\""));
+        assert!(cache_path.exists());
+        ctx.client.take_calls();
+
+        // The resumed sync only uploads the missing files.
+        ctx.client.state.lock().unwrap().fail_file_adds = false;
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        assert!(
+            ctx.client.take_calls()
+                == [MockCall::AddPostImages {
+                    id: String::from("post-1"),
+                    num_files: 5,
+                }]
+        );
+        let state = ctx.client.state.lock().unwrap();
+        let remote_post = state.get_post("post-1").unwrap();
+        assert!(remote_post.images.len() == CREATE_BATCH_SIZE + 5);
+    }
+
+    #[test]
+    fn sync_post_rejects_escaping_paths() {
+        let dir = TestPostDir::new(
+            "sync-post-rejects-escaping-paths",
+            &[("a.png", "a")],
+            "[post]\n\n[[post.files]]\npath = \"./sub/../a.png\"\n\n[[post.files]]\npath = \"../outside.png\"\n",
+        );
+        let outside_path = dir
+            .path
+            .parent()
+            .expect("missing parent")
+            .join(format!("{}-outside.png", dir.path.file_name().unwrap()));
+        std::fs::write(&outside_path, "outside").expect("failed to write file");
+        let config = dir.read_config().replace(
+            "../outside.png",
+            &format!("../{}", outside_path.file_name().unwrap()),
+        );
+        dir.write("imgchest-sync.toml", &config);
+
+        let ctx = mock_context(&[]);
+        let error = dir
+            .try_sync(&ctx)
+            .expect_err("escaping path was not rejected");
+        let error = error
+            .downcast_ref::<SyncError>()
+            .expect("error is not a sync error");
+        assert!(
+            matches!(error, SyncError::PathEscapesDir { path, .. } if path.ends_with("-outside.png")),
+            "{error:?}"
+        );
+
+        // Symlinks are followed.
+        #[cfg(unix)]
+        {
+            let link_config = config.replace(
+                &format!("../{}", outside_path.file_name().unwrap()),
+                "link.png",
+            );
+            dir.write("imgchest-sync.toml", &link_config);
+            std::os::unix::fs::symlink(&outside_path, dir.path.join("link.png"))
+                .expect("failed to make symlink");
+
+            let error = dir
+                .try_sync(&ctx)
+                .expect_err("escaping symlink was not rejected");
+            assert!(
+                matches!(
+                    error.downcast_ref::<SyncError>(),
+                    Some(SyncError::PathEscapesDir { path, .. }) if path == "link.png"
+                ),
+                "{error:?}"
+            );
+
+            std::fs::remove_file(dir.path.join("link.png")).expect("failed to remove symlink");
+            std::os::unix::fs::symlink(dir.path.join("a.png"), dir.path.join("link.png"))
+                .expect("failed to make symlink");
+            let outcome = dir.sync(&ctx);
+            assert!(outcome.action == SyncAction::Created);
         }
-    }
 
-    if let Some(update_post_builder) = update_post_builder {
-        client.update_post(id, update_post_builder).await?;
-    }
+        let ctx = mock_context(&["--allow-path-escape"]);
+        dir.write("imgchest-sync.toml", &config);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
 
-    if !files_to_add.is_empty() {
-        let imgchest_post = client.add_post_images(id, files_to_add).await?;
-        for (i, file_index) in files_to_add_indicies.into_iter().enumerate() {
-            let imgchest_image = &imgchest_post.images[old_post.files.len() + i];
-            let new_post_file = &mut new_post.files[file_index];
+        std::fs::remove_file(&outside_path).expect("failed to remove file");
+    }
 
-            let id = String::from(imgchest_image.id.clone());
-            let description = &new_post_file.description;
+    #[test]
+    fn sync_post_concurrent_hash_and_upload() {
+        let file_names: Vec<String> = (0..CREATE_BATCH_SIZE + 5)
+            .map(|i| format!("{i}.png"))
+            .collect();
+        let files: Vec<(&str, &str)> = file_names
+            .iter()
+            .map(|file_name| (file_name.as_str(), file_name.as_str()))
+            .collect();
+        let mut config = String::from("[post]\n");
+        for file_name in file_names.iter() {
+            config.push_str(&format!("\n[[post.files]]\npath = \"{file_name}\"\n"));
+        }
+        let dir = TestPostDir::new("sync-post-concurrent-hash-and-upload", &files, &config);
 
-            new_post_file.id = Some(id.clone());
+        let ctx = mock_context(&["--concurrent-hash-and-upload"]);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        assert!(
+            ctx.client.take_calls()
+                == [
+                    MockCall::CreatePost {
+                        num_files: CREATE_BATCH_SIZE
+                    },
+                    MockCall::AddPostImages {
+                        id: String::from("post-1"),
+                        num_files: 5,
+                    },
+                ]
+        );
 
-            // If the new description is empty,
-            // do nothing.
-            // We just created a new post,
-            // so it must be empty already.
-            if !description.is_empty() {
-                file_updates.push(imgchest::FileUpdate {
-                    id,
-                    description: description.clone(),
-                });
-            }
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+        let cache = std::fs::read_to_string(&cache_path).expect("missing cache");
+        let cache: Cache = toml::from_str(&cache).expect("failed to parse cache");
+        assert!(cache.post.files.len() == CREATE_BATCH_SIZE + 5);
+        for (file, file_name) in cache.post.files.iter().zip(file_names.iter()) {
+            assert!(file.sha256 == hash_bytes(file_name.as_bytes()));
         }
+
+        // The next sync finds no changes.
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
     }
 
-    // This needs to happen after we add our files, in case the post is empied.
-    for id in files_to_remove.iter() {
-        client.delete_file(id).await?;
+    #[test]
+    fn sync_post_concurrent_hash_and_upload_checks_every_batch() {
+        let file_names: Vec<String> = (0..CREATE_BATCH_SIZE).map(|i| format!("{i}.png")).collect();
+        let files: Vec<(&str, &str)> = file_names
+            .iter()
+            .map(|file_name| (file_name.as_str(), file_name.as_str()))
+            .collect();
+        let mut config = String::from("[post]\n");
+        for file_name in file_names.iter() {
+            config.push_str(&format!("\n[[post.files]]\npath = \"{file_name}\"\n"));
+        }
+        // The duplicate is in the second batch, and its original in the first.
+        config.push_str("\n[[post.files]]\npath = \"./0.png\"\n");
+        let dir = TestPostDir::new(
+            "sync-post-concurrent-hash-and-upload-checks-every-batch",
+            &files,
+            &config,
+        );
+
+        let ctx = mock_context(&["--concurrent-hash-and-upload", "--strict"]);
+        let error = dir
+            .try_sync(&ctx)
+            .expect_err("duplicate path was not rejected");
+        assert!(
+            error.to_string().contains("is listed more than once"),
+            "{error:?}"
+        );
+        assert!(ctx.client.take_calls().is_empty());
     }
 
-    if !file_updates.is_empty() {
-        client.update_files_bulk(file_updates).await?;
+    #[test]
+    fn sync_post_dumps_post_json() {
+        let dir = TestPostDir::new(
+            "sync-post-dumps-post-json",
+            &[("a.png", "a"), ("b.png", "b")],
+            TEST_POST_CONFIG,
+        );
+        let dump_dir = dir.path.join("dump");
+        let ctx = mock_context(&["--dump-post-json", dump_dir.as_str()]);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+
+        block_on(write_post_dump(&dump_dir, "post", &outcome.posts))
+            .expect("failed to write post dump");
+        let raw = std::fs::read_to_string(dump_dir.join("post.json")).expect("missing dump");
+        let post: serde_json::Value = serde_json::from_str(&raw).expect("invalid json");
+        assert!(post["privacy"] == "hidden");
+        let files = post["files"].as_array().expect("missing files");
+        assert!(files.len() == 2);
+        assert!(files[0]["sha256"] == hash_bytes(b"a").as_str());
+        assert!(files[0]["description"] == "first");
+        assert!(files[1]["id"] == outcome.posts[0].files[1].id.as_deref().unwrap());
+
+        // Dry runs sync nothing, so nothing is dumped.
+        let ctx = mock_context(&["--dump-post-json", dump_dir.as_str(), "--dry-run"]);
+        assert!(dir.sync(&ctx).posts.is_empty());
     }
 
-    Ok(())
-}
+    #[test]
+    fn sync_post_warns_on_duplicate_paths() {
+        let dir = TestPostDir::new(
+            "sync-post-warns-on-duplicate-paths",
+            &[("a.png", "a"), ("b.png", "b")],
+            "[post]\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"b.png\"\n\n[[post.files]]\npath = \"./a.png\"\n",
+        );
 
-fn generate_post_diffs(old: &Post, new: &Post) -> anyhow::Result<Vec<PostDiff>> {
-    ensure!(!old.files.is_empty(), "old post has no files");
-    ensure!(!new.files.is_empty(), "new post has no files");
+        let ctx = mock_context(&["--strict"]);
+        let error = dir
+            .try_sync(&ctx)
+            .expect_err("duplicate path was not rejected");
+        assert!(
+            error.to_string().contains("is listed more than once"),
+            "{error:?}"
+        );
+        assert!(ctx.client.take_calls().is_empty());
 
-    let mut diffs = Vec::new();
-    if old.title != new.title {
-        diffs.push(PostDiff::EditTitle {
-            title: new.title.clone(),
-        });
+        // Without --strict, it is only a warning.
+        let ctx = mock_context(&[]);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Created);
+        assert!(ctx.client.take_calls() == [MockCall::CreatePost { num_files: 3 }]);
     }
-    if old.privacy != new.privacy {
-        diffs.push(PostDiff::EditPrivacy {
-            privacy: new.privacy,
-        });
+
+    #[test]
+    fn sync_post_dry_run_keeps_cache() {
+        let ctx = mock_context(&[]);
+        let dir = create_test_post("sync-post-dry-run-keeps-cache", &ctx);
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+        let cache = std::fs::read_to_string(&cache_path).expect("missing cache");
+
+        let config = dir
+            .read_config()
+            .replace("[[post.files]]\npath = \"b.png\"\n", "");
+        dir.write("imgchest-sync.toml", &config);
+
+        let dry_run_ctx = mock_context(&["--dry-run"]);
+        let outcome = dir.sync(&dry_run_ctx);
+        assert!(outcome.action == SyncAction::Skipped);
+        assert!(dry_run_ctx.client.take_calls().is_empty());
+        assert!(std::fs::read_to_string(&cache_path).ok().as_deref() == Some(cache.as_str()));
+        assert!(dir.read_config() == config);
+
+        // The same update without a dry run removes the cache before applying the diffs.
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        assert!(std::fs::read_to_string(&cache_path).ok().as_deref() != Some(cache.as_str()));
     }
-    if old.nsfw != new.nsfw {
-        diffs.push(PostDiff::EditNsfw { nsfw: new.nsfw });
+
+    #[test]
+    fn sync_post_resolves_conflicts() {
+        let mut ctx = mock_context(&["--check-remote"]);
+        let dir = create_test_post("sync-post-resolves-conflicts", &ctx);
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+
+        ctx.client
+            .state
+            .lock()
+            .unwrap()
+            .posts
+            .get_mut("post-1")
+            .unwrap()
+            .title = Some(String::from("remote title"));
+        dir.write("c.png", "c");
+        dir.write(
+            "imgchest-sync.toml",
+            &format!("{}\n[[post.files]]\npath = \"c.png\"\n", dir.read_config()),
+        );
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Skipped);
+        assert!(
+            ctx.client.take_calls()
+                == [MockCall::GetPost {
+                    id: String::from("post-1")
+                }]
+        );
+
+        ctx.options.on_conflict = Some(ConflictPolicy::RemoteWins);
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Skipped);
+        let cache = std::fs::read_to_string(&cache_path).expect("missing cache");
+        assert!(cache.contains("title = \"remote title\""));
+
+        assert!(dir.read_config().contains("title = \"remote title\""));
+
+        // The cache now matches the remote post, so there is no conflict.
+        // The config has the remote title, so it is kept while the new file is added.
+        ctx.options.on_conflict = None;
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        let remote_post = ctx.client.state.lock().unwrap().get_post("post-1").unwrap();
+        assert!(remote_post.images.len() == 3);
+        assert!(remote_post.title.as_deref() == Some("remote title"));
     }
 
-    // Ideally, we would diff and only upload what is changed.
-    // However, the imgchest api is horriblly handicapped:
-    // 1. We cannot reorder files without deleting and recreating them.
-    // 2. We can only change a file description, not remove it.
-    // 3. We cannot insert files at arbitrary indicies.
-    //
-    // While diffing would give us an advantage in some cases,
-    // most of the time we would just throw out our calculations
-    // or be forced to use some heurisitics to convert our diffs into
-    // something the API can use.
-    //
-    // As a result, we will use a simpler, faster algorithm.
-    // We will skip all initial files that are not changed and have the same description.
-    // When we reach an index where there is a mismatch, delete everything past it.
-    // Then, add the files from the new post.
+    /// Serve the given files over http on a local port, returning the base url.
+    ///
+    /// The server runs until the test process exits.
+    fn serve_files(files: &'static [(&'static str, &'static str)]) -> String {
+        use std::io::BufRead;
+        use std::io::Write;
 
-    let mut prefix_index = 0;
-    while let (Some(old_file), Some(new_file)) =
-        (old.files.get(prefix_index), new.files.get(prefix_index))
-    {
-        // TODO: It is possible to keep searching after a mismatch here
-        // if we can make the file sequence match again by only deleting from the old post.
-        if old_file.sha256 != new_file.sha256 {
-            break;
-        }
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("failed to bind");
+        let addr = listener.local_addr().expect("missing local address");
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let mut reader = std::io::BufReader::new(&mut stream);
+                let mut request_line = String::new();
+                let _ = reader.read_line(&mut request_line);
+                loop {
+                    let mut line = String::new();
+                    match reader.read_line(&mut line) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) if line == "\r\n" => break,
+                        Ok(_) => {}
+                    }
+                }
 
-        let mut edit_description = false;
-        if old_file.description != new_file.description {
-            // We know that the description needs an update.
-            // However, the API does not allow clearing a description.
-            // In this case, we are forced to recreate the file.
-            // As a result, we are forced to end our same file prefix search.
-            if new_file.description.is_empty() {
-                break;
-            } else {
-                edit_description = true;
+                let path = request_line.split(' ').nth(1).unwrap_or("");
+                let response = match files.iter().find(|(name, _)| path == format!("/{name}")) {
+                    Some((_, body)) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    ),
+                    None => String::from(
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    ),
+                };
+                let _ = stream.write_all(response.as_bytes());
             }
-        }
-
-        diffs.push(PostDiff::RetainFile {
-            index: prefix_index,
         });
 
-        if edit_description {
-            diffs.push(PostDiff::EditFileDescription {
-                index: prefix_index,
-                description: new_file.description.clone(),
-            });
-        }
+        format!("http://{addr}")
+    }
+
+    #[test]
+    fn sync_post_dry_run_with_remote_diff_base_is_read_only() {
+        let ctx = mock_context(&[]);
+        let dir = create_test_post("sync-post-dry-run-with-remote-diff-base-is-read-only", &ctx);
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+        let hash_cache_path = dir.path.join(".imgchest-sync-cache.hashes.toml");
+        std::fs::remove_file(&cache_path).expect("failed to remove cache");
+        let _ = std::fs::remove_file(&hash_cache_path);
+
+        let synced_config = dir.read_config();
+        let config = synced_config.replace("[[post.files]]\npath = \"b.png\"\n", "");
+        dir.write("imgchest-sync.toml", &config);
 
-        prefix_index += 1;
+        let api_base = serve_files(&[("file-2", "a"), ("file-3", "b")]);
+        let mut dry_run_ctx = mock_context(&["--dry-run", "--diff-base", "remote"]);
+        dry_run_ctx.client.state = ctx.client.state.clone();
+        dry_run_ctx.options.api_base = Some(api_base);
+
+        let outcome = dir.sync(&dry_run_ctx);
+        assert!(outcome.action == SyncAction::Skipped);
+        assert!(
+            dry_run_ctx.client.take_calls()
+                == [MockCall::GetPost {
+                    id: String::from("post-1")
+                }]
+        );
+        assert!(!cache_path.exists());
+        assert!(!hash_cache_path.exists());
+        assert!(dir.read_config() == config);
+
+        // Without local changes, a real run would write the cache, but a dry run must not.
+        dir.write("imgchest-sync.toml", &synced_config);
+        let outcome = dir.sync(&dry_run_ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
+        assert!(!cache_path.exists());
+        assert!(!hash_cache_path.exists());
     }
 
-    for index in prefix_index..new.files.len() {
-        // Since we removed all the posts with the earlier diff,
-        // The current old post object is a prefix of the new post object.
-        // Therefore, the indicies of the new post work with the old one.
-        diffs.push(PostDiff::AddFile { index });
+    #[test]
+    fn sync_post_refuses_large_removals() {
+        let ctx = mock_context(&["--max-removal-percent", "25"]);
+        let dir = create_test_post("sync-post-refuses-large-removals", &ctx);
+
+        let config = dir
+            .read_config()
+            .replace("[[post.files]]\npath = \"b.png\"\n", "");
+        dir.write("imgchest-sync.toml", &config);
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Skipped);
+        assert!(ctx.client.take_calls().is_empty());
+
+        let ctx = mock_context(&["--max-removal-percent", "25", "--allow-large-removals"]);
+        let dir = create_test_post("sync-post-allows-large-removals", &ctx);
+
+        let config = dir
+            .read_config()
+            .replace("[[post.files]]\npath = \"b.png\"\n", "");
+        dir.write("imgchest-sync.toml", &config);
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
     }
 
-    for index in prefix_index..old.files.len() {
-        diffs.push(PostDiff::RemoveFile { index });
+    #[test]
+    fn sync_post_allows_reordering_files() {
+        // Reversing the files moves 2 of 3 files, but removes none.
+        let ctx = mock_context(&[]);
+        let dir = TestPostDir::new(
+            "sync-post-allows-reordering-files",
+            &[("a.png", "a"), ("b.png", "b"), ("c.png", "c")],
+            "[post]\n\n[[post.files]]\npath = \"a.png\"\n\n[[post.files]]\npath = \"b.png\"\n\n[[post.files]]\npath = \"c.png\"\n",
+        );
+        dir.sync(&ctx);
+        ctx.client.take_calls();
+
+        let config = dir
+            .read_config()
+            .replace("\"a.png\"", "\"tmp.png\"")
+            .replace("\"c.png\"", "\"a.png\"")
+            .replace("\"tmp.png\"", "\"c.png\"");
+        dir.write("imgchest-sync.toml", &config);
+
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Updated);
+        assert!(!ctx.client.take_calls().is_empty());
     }
 
-    anyhow::Ok(diffs)
-}
+    #[test]
+    fn plan_online_post_update_works() {
+        let mut old_post = post_from_hashes(&[SHA256_A, SHA256_B, SHA256_C]);
+        for (i, file) in old_post.files.iter_mut().enumerate() {
+            file.id = Some(format!("file-{i}"));
+        }
+        let mut new_post = post_from_hashes(&[SHA256_A, SHA256_D]);
+        new_post.title = String::from("new title");
+        new_post.files[1].description = String::from("new");
 
-#[cfg(test)]
-mod test {
-    use super::*;
+        let diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        let plan = plan_online_post_update(diffs, &old_post, &mut new_post)
+            .expect("failed to plan update");
+        assert!(new_post.files[0].id.as_deref() == Some("file-0"));
 
-    const SHA256_A: &str = "a";
-    const SHA256_B: &str = "b";
+        let calls = plan.describe_calls(&new_post);
+        let expected_calls = [
+            "update_post (title)",
+            "add_post_images (1 images)",
+            "delete_file (file-1)",
+            "delete_file (file-2)",
+            "update_files_bulk (1 files)",
+        ];
+        assert!(calls == expected_calls, "{calls:?}");
+    }
 
     #[test]
-    fn generate_post_diffs_works() {
-        let old_post = Post {
-            title: String::from("title"),
-            privacy: PostPrivacy::Hidden,
-            nsfw: false,
-            files: vec![PostFile {
-                description: String::new(),
-                sha256: SHA256_B.into(),
-                id: None,
-                path: None,
-            }],
-        };
-        let new_post = Post {
-            title: String::from("title"),
-            privacy: PostPrivacy::Hidden,
-            nsfw: false,
-            files: vec![PostFile {
-                description: String::new(),
-                sha256: SHA256_A.into(),
-                id: None,
-                path: None,
-            }],
-        };
+    fn generate_post_diffs_delete_consecutive_files() {
+        let old_post = post_from_hashes(&[SHA256_A, SHA256_B, SHA256_C, SHA256_D]);
+        let new_post = post_from_hashes(&[SHA256_A, SHA256_D]);
 
-        let actual_diffs =
-            generate_post_diffs(&old_post, &new_post).expect("failed to generate diffs");
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
         let expected_diffs = vec![
-            PostDiff::AddFile { index: 0 },
+            PostDiff::RetainFile { index: 0 },
+            PostDiff::RetainFile { index: 3 },
+            PostDiff::RemoveFile { index: 1 },
+            PostDiff::RemoveFile { index: 2 },
+        ];
+        assert!(actual_diffs == expected_diffs);
+    }
+
+    #[test]
+    fn generate_post_diffs_file_ids() {
+        let mut old_post = post_from_hashes(&[SHA256_A, SHA256_A]);
+        old_post.files[0].id = Some("1".into());
+        old_post.files[1].id = Some("2".into());
+        let mut new_post = post_from_hashes(&[SHA256_A]);
+        new_post.files[0].id = Some("2".into());
+
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
+        let expected_diffs = vec![
+            PostDiff::RetainFile { index: 1 },
             PostDiff::RemoveFile { index: 0 },
         ];
         assert!(actual_diffs == expected_diffs);
+    }
 
-        let old_post = Post {
-            title: String::from("title"),
-            privacy: PostPrivacy::Hidden,
-            nsfw: false,
-            files: vec![
-                PostFile {
-                    description: String::new(),
-                    sha256: SHA256_A.into(),
-                    id: None,
-                    path: None,
-                },
-                PostFile {
-                    description: String::new(),
-                    sha256: SHA256_A.into(),
-                    id: None,
-                    path: None,
-                },
-            ],
-        };
-        let new_post = Post {
-            title: String::from("title"),
-            privacy: PostPrivacy::Hidden,
-            nsfw: false,
-            files: vec![PostFile {
-                description: String::new(),
-                sha256: SHA256_A.into(),
-                id: None,
-                path: None,
-            }],
-        };
+    #[test]
+    fn generate_post_diffs_set_order_mode() {
+        let old_post = post_from_hashes(&[SHA256_A, SHA256_B, SHA256_C]);
+        let mut new_post = post_from_hashes(&[SHA256_D, SHA256_C, SHA256_A]);
+
+        anchor_post_order(&old_post, &mut new_post, DiffOptions::default());
+        let actual_hashes: Vec<_> = new_post
+            .files
+            .iter()
+            .map(|file| file.sha256.as_str())
+            .collect();
+        assert!(actual_hashes == [SHA256_A, SHA256_C, SHA256_D]);
 
-        let actual_diffs =
-            generate_post_diffs(&old_post, &new_post).expect("failed to generate diffs");
+        let actual_diffs = generate_post_diffs(&old_post, &new_post, DiffOptions::default())
+            .expect("failed to generate diffs");
         let expected_diffs = vec![
             PostDiff::RetainFile { index: 0 },
+            PostDiff::RetainFile { index: 2 },
+            PostDiff::AddFile { index: 2 },
             PostDiff::RemoveFile { index: 1 },
         ];
         assert!(actual_diffs == expected_diffs);
+    }
 
-        let old_post = Post {
-            title: String::from("title"),
-            privacy: PostPrivacy::Hidden,
-            nsfw: false,
-            files: vec![PostFile {
-                description: String::new(),
-                sha256: SHA256_A.into(),
-                id: None,
-                path: None,
-            }],
-        };
-        let new_post = Post {
-            title: String::from("title"),
-            privacy: PostPrivacy::Hidden,
-            nsfw: false,
-            files: vec![PostFile {
-                description: String::new(),
+    #[test]
+    fn generate_description_updates_skips_applied() {
+        let new_files: Vec<_> = ["first", "", "third", "fourth"]
+            .into_iter()
+            .map(|description| PostFile {
+                description: description.into(),
                 sha256: SHA256_A.into(),
                 id: None,
                 path: None,
-            }],
-        };
-        let actual_diffs =
-            generate_post_diffs(&old_post, &new_post).expect("failed to generate diffs");
-        let expected_diffs = vec![PostDiff::RetainFile { index: 0 }];
-        assert!(actual_diffs == expected_diffs);
+                data: None,
+                orient: false,
+                upload_name: None,
+            })
+            .collect();
 
-        let old_post = Post {
-            title: String::from("title"),
-            privacy: PostPrivacy::Hidden,
-            nsfw: false,
-            files: vec![PostFile {
-                description: String::new(),
-                sha256: SHA256_A.into(),
-                id: None,
-                path: None,
-            }],
-        };
-        let new_post = Post {
-            title: String::from("title"),
-            privacy: PostPrivacy::Hidden,
-            nsfw: false,
-            files: vec![PostFile {
-                description: "hello world!".into(),
-                sha256: SHA256_A.into(),
-                id: None,
-                path: None,
-            }],
-        };
-        let actual_diffs =
-            generate_post_diffs(&old_post, &new_post).expect("failed to generate diffs");
-        let expected_diffs = vec![
-            PostDiff::RetainFile { index: 0 },
-            PostDiff::EditFileDescription {
-                index: 0,
-                description: "hello world!".into(),
-            },
+        // A previous run was interrupted after setting the first description.
+        let remote_files = [("1", "first"), ("2", ""), ("3", ""), ("4", "")];
+
+        let actual_updates: Vec<_> = generate_description_updates(&new_files, remote_files)
+            .into_iter()
+            .map(|update| (update.id, update.description))
+            .collect();
+        let expected_updates = vec![
+            (String::from("3"), String::from("third")),
+            (String::from("4"), String::from("fourth")),
         ];
-        dbg!(&actual_diffs);
-        assert!(actual_diffs == expected_diffs);
+        assert!(actual_updates == expected_updates);
+    }
+
+    #[test]
+    fn sync_post_keeps_new_id_without_cache() {
+        let dir = TestPostDir::new(
+            "sync-post-keeps-new-id-without-cache",
+            &[("a.png", "a"), ("b.png", "b")],
+            TEST_POST_CONFIG,
+        );
+        let config_path = dir.path.join("imgchest-sync.toml");
+        let cache_path = dir.path.join(".imgchest-sync-cache.toml");
+
+        // A non-empty dir at the cache path makes renaming the cache fail.
+        std::fs::create_dir_all(cache_path.join("dir")).expect("failed to create dir");
+        let mut ctx = mock_context(&["--no-read-cache"]);
+        let mut config = Config::new(&dir.read_config()).expect("failed to parse config");
+        let error = block_on(sync_post(
+            &ctx,
+            &dir.path,
+            &mut config,
+            Some(&config_path),
+            Some(&cache_path),
+        ))
+        .expect_err("cache was written");
+        assert!(
+            format!("{error:#}").contains("failed to write new config and cache"),
+            "{error:#}"
+        );
+        assert!(dir.read_config().contains("id = \"post-1\""));
+        ctx.client.take_calls();
+
+        // The next sync rebuilds the cache from the remote post, instead of creating the post again.
+        std::fs::remove_dir_all(&cache_path).expect("failed to remove dir");
+        ctx.options.no_read_cache = false;
+        ctx.options.api_base = Some(serve_files(&[("file-2", "a"), ("file-3", "b")]));
+        let outcome = dir.sync(&ctx);
+        assert!(outcome.action == SyncAction::Unchanged);
+        assert!(
+            ctx.client.take_calls()
+                == [MockCall::GetPost {
+                    id: String::from("post-1")
+                }]
+        );
+        assert!(cache_path.is_file());
+    }
+
+    #[test]
+    fn plan_post_action_deleted() {
+        let config_str = "[post]\nid = \"abc\"\n\n[[post.files]]\npath = \"1.png\"\n";
+        let mut config = Config::new(config_str).expect("failed to parse config");
+        assert!(plan_post_action(&config.post_mut()) == PostAction::Sync);
+
+        let config_str =
+            "[post]\nid = \"abc\"\ndeleted = true\n\n[[post.files]]\npath = \"1.png\"\n";
+        let mut config = Config::new(config_str).expect("failed to parse config");
+        assert!(
+            plan_post_action(&config.post_mut())
+                == PostAction::Delete {
+                    id: String::from("abc")
+                }
+        );
+
+        let config_str = "[post]\ndeleted = true\n\n[[post.files]]\npath = \"1.png\"\n";
+        let mut config = Config::new(config_str).expect("failed to parse config");
+        assert!(plan_post_action(&config.post_mut()) == PostAction::Skip);
     }
 }
@@ -0,0 +1,108 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Metrics of a run, as written by --metrics-file.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// The unix time that the run finished, in seconds.
+    pub timestamp: u64,
+
+    /// The time the run took.
+    pub duration: Duration,
+
+    /// The number of directories processed.
+    pub directories_processed: u64,
+
+    /// The number of posts created.
+    pub posts_created: u64,
+
+    /// The number of posts updated.
+    pub posts_updated: u64,
+
+    /// The number of posts that failed to sync.
+    pub posts_failed: u64,
+
+    /// The number of bytes of files uploaded.
+    pub bytes_uploaded: u64,
+}
+
+impl Metrics {
+    /// Format the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut output = String::new();
+        write_gauge(
+            &mut output,
+            "imgchest_sync_last_run_timestamp_seconds",
+            "The unix time that the last run finished.",
+            &[("", self.timestamp.to_string())],
+        );
+        write_gauge(
+            &mut output,
+            "imgchest_sync_last_run_duration_seconds",
+            "The time the last run took.",
+            &[("", format!("{:.3}", self.duration.as_secs_f64()))],
+        );
+        write_gauge(
+            &mut output,
+            "imgchest_sync_last_run_directories_processed",
+            "The number of directories processed in the last run.",
+            &[("", self.directories_processed.to_string())],
+        );
+        write_gauge(
+            &mut output,
+            "imgchest_sync_last_run_posts",
+            "The number of posts in the last run, by result.",
+            &[
+                ("{result=\"created\"}", self.posts_created.to_string()),
+                ("{result=\"updated\"}", self.posts_updated.to_string()),
+                ("{result=\"failed\"}", self.posts_failed.to_string()),
+            ],
+        );
+        write_gauge(
+            &mut output,
+            "imgchest_sync_last_run_bytes_uploaded",
+            "The number of bytes of files uploaded in the last run.",
+            &[("", self.bytes_uploaded.to_string())],
+        );
+
+        output
+    }
+}
+
+/// Write a gauge, with a sample for each pair of labels and value.
+fn write_gauge(output: &mut String, name: &str, help: &str, samples: &[(&str, String)]) {
+    writeln!(output, "# HELP {name} {help}").unwrap();
+    writeln!(output, "# TYPE {name} gauge").unwrap();
+    for (labels, value) in samples {
+        writeln!(output, "{name}{labels} {value}").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn to_prometheus_works() {
+        let metrics = Metrics {
+            timestamp: 1700000000,
+            duration: Duration::from_millis(1500),
+            directories_processed: 3,
+            posts_created: 1,
+            posts_updated: 2,
+            bytes_uploaded: 1024,
+            ..Metrics::default()
+        };
+
+        let raw = metrics.to_prometheus();
+        let lines: Vec<&str> = raw.lines().collect();
+        assert!(lines.contains(&"# TYPE imgchest_sync_last_run_posts gauge"));
+        assert!(lines.contains(&"imgchest_sync_last_run_timestamp_seconds 1700000000"));
+        assert!(lines.contains(&"imgchest_sync_last_run_duration_seconds 1.500"));
+        assert!(lines.contains(&"imgchest_sync_last_run_directories_processed 3"));
+        assert!(lines.contains(&"imgchest_sync_last_run_posts{result=\"created\"} 1"));
+        assert!(lines.contains(&"imgchest_sync_last_run_posts{result=\"updated\"} 2"));
+        assert!(lines.contains(&"imgchest_sync_last_run_posts{result=\"failed\"} 0"));
+        assert!(lines.contains(&"imgchest_sync_last_run_bytes_uploaded 1024"));
+    }
+}
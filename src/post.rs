@@ -1,7 +1,7 @@
 use camino::Utf8PathBuf;
 
 /// Representation of a post.
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct Post {
     /// The title
     pub title: String,
@@ -33,7 +33,7 @@ pub enum PostPrivacy {
 }
 
 /// A post image
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct PostFile {
     /// The post file description.
     pub description: String,
@@ -54,8 +54,47 @@ pub struct PostFile {
     /// This may not exist in certain cases,
     /// like creating a post from a config file.
     ///
-    /// This should not be used when diffing.
+    /// When creating a post from a config file,
+    /// this is the id of the remote file that the config says this file corresponds to.
+    /// When diffing, the file will only be matched with the old file with the same id.
     pub id: Option<String>,
+
+    /// The in-memory file data.
+    ///
+    /// This exists for generated files, which have no path.
+    /// It is never saved to the cache.
+    ///
+    /// This should not be used when diffing.
+    #[serde(skip)]
+    pub data: Option<PostFileData>,
+
+    /// Whether the file at the path is reoriented with --auto-orient before it is uploaded.
+    ///
+    /// The sha256 is the hash of the reoriented bytes.
+    /// It is never saved to the cache.
+    ///
+    /// This should not be used when diffing.
+    #[serde(skip)]
+    pub orient: bool,
+
+    /// The name to upload the file at the path under, if it differs from the name of the path.
+    ///
+    /// This is set when the extension of the path does not match the configured content type.
+    /// It is never saved to the cache.
+    ///
+    /// This should not be used when diffing.
+    #[serde(skip)]
+    pub upload_name: Option<String>,
+}
+
+/// In-memory data for a post file.
+#[derive(Debug, Clone)]
+pub struct PostFileData {
+    /// The file name to upload the data with.
+    pub file_name: String,
+
+    /// The file bytes.
+    pub bytes: Vec<u8>,
 }
 
 /// A diff for a post.
@@ -74,14 +113,16 @@ pub enum PostDiff {
         nsfw: bool,
     },
     EditFileDescription {
-        /// The id of the file to edit
+        /// The index of the file to edit, in the old post.
         index: usize,
 
         /// The new file description
         description: String,
     },
     RetainFile {
-        /// The index of the file to retain.
+        /// The index of the file to retain, in the old post.
+        ///
+        /// Retained files make up a prefix of the new post, in order.
         index: usize,
     },
     AddFile {
@@ -92,4 +133,52 @@ pub enum PostDiff {
         /// The index of the file to remove.
         index: usize,
     },
+    MoveFile {
+        /// The index of the file to move, in the old post.
+        from: usize,
+
+        /// The index to move the file to, in the new post.
+        ///
+        /// Like added files, moved files come after the retained files.
+        to: usize,
+    },
+}
+
+/// Options for generating post diffs.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DiffOptions {
+    /// Leave the old description as is when the new description is empty, instead of clearing it.
+    pub keep_remote_empty_descriptions: bool,
+
+    /// Clear descriptions by editing them, instead of recreating the file.
+    ///
+    /// The API does not accept an empty description,
+    /// so cleared descriptions are set to a placeholder instead.
+    /// Whitespace-only old descriptions are considered empty.
+    pub clear_descriptions: bool,
+}
+
+/// How the order of post files is synced.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum OrderMode {
+    /// The remote post must have the same file order as the local post.
+    #[default]
+    Strict,
+
+    /// The remote post must have the same files as the local post, in any order.
+    ///
+    /// The existing remote order is kept, new files are appended, and deleted files are removed.
+    Set,
+}
+
+impl std::str::FromStr for OrderMode {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "strict" => Ok(Self::Strict),
+            "set" => Ok(Self::Set),
+            _ => anyhow::bail!("\"{input}\" is not a valid order mode"),
+        }
+    }
 }
@@ -0,0 +1,431 @@
+use crate::config::Config;
+use crate::ConfigTarget;
+use crate::Options;
+use crate::Stats;
+use crate::SyncContext;
+use anyhow::ensure;
+use anyhow::Context;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use regex::Regex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// A post directory to process, with its loaded config.
+#[derive(Debug)]
+pub struct PostDir {
+    /// The name of the directory, which keys its output ids, summary entry, and post dump.
+    pub name: String,
+
+    /// The name to print for the directory.
+    ///
+    /// This is the directory name, or the directory path for a single post.
+    pub label: String,
+
+    /// The path of the directory.
+    pub path: Utf8PathBuf,
+
+    /// The path to write the config to, or `None` to print it to stdout.
+    pub config_path: Option<Utf8PathBuf>,
+
+    /// The path of the cache file, if there is one.
+    pub cache_path: Option<Utf8PathBuf>,
+
+    /// The config, with its includes loaded.
+    pub config: Config,
+}
+
+/// The post directories of a run.
+///
+/// This is the post of --config, if given,
+/// and otherwise every directory of --input that has a config,
+/// in order and with --resume-from, --filter-regex, --only-new, and --limit applied.
+/// Disabled posts are skipped.
+pub struct PostDirs<'a> {
+    options: &'a Options,
+    interrupted: &'a AtomicBool,
+
+    /// The post of --config, until it is returned.
+    single: Option<PostDir>,
+    is_single: bool,
+
+    input: Utf8PathBuf,
+    entries: Vec<Utf8PathBuf>,
+    next_index: usize,
+    filter_regex: Option<Regex>,
+    resume_from: Option<&'a str>,
+
+    num_skipped: usize,
+    num_existing_skipped: usize,
+    num_disabled_skipped: usize,
+    num_processed: usize,
+    started_any: bool,
+    total_stats: Stats,
+}
+
+impl<'a> PostDirs<'a> {
+    /// Load the post of --config, or list the directories of --input.
+    pub async fn new<A>(ctx: &'a SyncContext<A>) -> anyhow::Result<Self> {
+        let options = &ctx.options;
+        let mut post_dirs = Self {
+            options,
+            interrupted: &ctx.interrupted,
+            single: None,
+            is_single: false,
+            input: Utf8PathBuf::new(),
+            entries: Vec::new(),
+            next_index: 0,
+            filter_regex: None,
+            resume_from: options.resume_from.as_deref(),
+            num_skipped: 0,
+            num_existing_skipped: 0,
+            num_disabled_skipped: 0,
+            num_processed: 0,
+            started_any: false,
+            total_stats: Stats::new(),
+        };
+
+        if let Some(config_arg) = options.config.as_deref() {
+            post_dirs.is_single = true;
+            post_dirs.single = load_single(options, config_arg).await?;
+            return Ok(post_dirs);
+        }
+
+        let input = options
+            .input
+            .as_ref()
+            .context("missing input directory. Specify it with --input")?;
+        post_dirs.filter_regex = options
+            .filter_regex
+            .as_ref()
+            .map(|filter_regex| {
+                Regex::new(&format!("^{filter_regex}$")).context("invalid filter regex")
+            })
+            .transpose()?;
+
+        // Sort entries by name, so that runs are reproducible and can be resumed.
+        let mut dir_iter = tokio::fs::read_dir(input).await?;
+        while let Some(entry) = dir_iter.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if !file_type.is_dir() {
+                if options.verbose {
+                    println!("skipping file \"{}\"", entry.path().display());
+                }
+                continue;
+            }
+            let entry_path = Utf8PathBuf::try_from(entry.path())?;
+
+            post_dirs.entries.push(entry_path);
+        }
+        sort_dir_paths(&mut post_dirs.entries, options.natural_sort);
+        post_dirs.input = input.clone();
+
+        Ok(post_dirs)
+    }
+
+    /// Get the next post directory, or `None` if there are no more.
+    pub async fn next(&mut self) -> anyhow::Result<Option<PostDir>> {
+        let options = self.options;
+        if self.is_single {
+            let post_dir = self.single.take();
+            if post_dir.is_some() {
+                self.num_processed += 1;
+            }
+            return Ok(post_dir);
+        }
+
+        while let Some(entry_path) = self.entries.get(self.next_index) {
+            let entry_index = self.next_index;
+            self.next_index += 1;
+            let entry_file_name = entry_path.file_name().context("missing file name")?;
+
+            if let Some(name) = self.resume_from {
+                if entry_file_name != name {
+                    self.num_skipped += 1;
+                    continue;
+                }
+
+                println!(
+                    "resuming from \"{name}\", skipped {} earlier directories",
+                    self.num_skipped
+                );
+                self.resume_from = None;
+            }
+
+            if self.interrupted.load(Ordering::Relaxed) {
+                println!("stopped before \"{entry_file_name}\". Every earlier directory was processed, and the ones that synced have an up to date cache. Continue with --resume-from \"{entry_file_name}\"");
+                self.next_index = self.entries.len();
+                return Ok(None);
+            }
+
+            if let Some(filter_regex) = self.filter_regex.as_ref() {
+                if !filter_regex.is_match(entry_file_name) {
+                    continue;
+                }
+            }
+
+            let dir_path = self.input.join(entry_path);
+            let config_path = dir_path.join("imgchest-sync.toml");
+            let cache_path = crate::get_cache_path(&dir_path, options.cache_dir.as_deref())?;
+
+            let mut config = match crate::util::try_read_to_string(&config_path)
+                .await
+                .context("failed to read config file")?
+            {
+                Some(config_raw) => {
+                    Config::new(&config_raw).context("failed to parse config file")?
+                }
+                None => {
+                    if options.warn_missing_config {
+                        eprintln!(
+                            "warning: skipping \"{entry_file_name}\", it has no imgchest-sync.toml"
+                        );
+                    } else if options.verbose {
+                        println!("skipping \"{entry_file_name}\" (no imgchest-sync.toml)");
+                    }
+                    continue;
+                }
+            };
+            config
+                .load_includes(dir_path.as_std_path())
+                .context("failed to load included configs")?;
+
+            // Disabled posts are skipped before any work, in every mode.
+            if !config.post_mut().enabled() {
+                self.num_disabled_skipped += 1;
+                continue;
+            }
+
+            if options.only_new && config.post_mut().has_remote_post() {
+                self.num_existing_skipped += 1;
+                continue;
+            }
+
+            if options.limit == Some(self.num_processed) {
+                let mut num_remaining = 0;
+                for entry_path in self.entries[entry_index..].iter() {
+                    let entry_file_name = entry_path.file_name().context("missing file name")?;
+                    let matches_filter = self
+                        .filter_regex
+                        .as_ref()
+                        .is_none_or(|filter_regex| filter_regex.is_match(entry_file_name));
+                    let has_config = tokio::fs::try_exists(
+                        self.input.join(entry_path).join("imgchest-sync.toml"),
+                    )
+                    .await
+                    .context("failed to check for config file")?;
+                    if matches_filter && has_config {
+                        num_remaining += 1;
+                    }
+                }
+
+                println!("reached the limit of {} directories, {num_remaining} remain. Continue with --resume-from \"{entry_file_name}\"", self.num_processed);
+                self.next_index = self.entries.len();
+                return Ok(None);
+            }
+            self.num_processed += 1;
+
+            return Ok(Some(PostDir {
+                name: entry_file_name.into(),
+                label: entry_file_name.into(),
+                path: dir_path,
+                config_path: Some(config_path),
+                cache_path: Some(cache_path),
+                config,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    /// Wait for --delay-between-dirs before a directory that uses the API, unless it is the first.
+    ///
+    /// Hashing is local, so only API runs are spread out.
+    pub async fn delay(&mut self) {
+        if let Some(delay) = self.options.delay_between_dirs {
+            if self.started_any {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+            }
+        }
+        self.started_any = true;
+    }
+
+    /// Record the stats of a directory, printing them with --stats.
+    pub fn add_stats(&mut self, stats: Stats) {
+        if self.options.stats {
+            println!("  stats: {stats}");
+        }
+        self.total_stats += stats;
+    }
+
+    /// Get the stats of every directory so far.
+    pub fn total_stats(&self) -> &Stats {
+        &self.total_stats
+    }
+
+    /// Get the number of directories returned so far.
+    pub fn num_processed(&self) -> usize {
+        self.num_processed
+    }
+
+    /// Get the number of disabled posts skipped so far.
+    pub fn num_disabled_skipped(&self) -> usize {
+        self.num_disabled_skipped
+    }
+
+    /// Report the directories that were skipped,
+    /// and fail if the run was interrupted before every directory was processed.
+    pub fn finish(&self) -> anyhow::Result<()> {
+        if self.is_single {
+            return Ok(());
+        }
+
+        if let Some(name) = self.resume_from {
+            eprintln!("warning: directory \"{name}\" to resume from was not found");
+        }
+
+        if self.options.only_new {
+            println!("skipped {} existing posts", self.num_existing_skipped);
+        }
+
+        if self.num_disabled_skipped > 0 {
+            eprintln!("skipped {} disabled posts", self.num_disabled_skipped);
+        }
+
+        ensure!(
+            !self.interrupted.load(Ordering::Relaxed),
+            "interrupted before every directory was processed"
+        );
+
+        Ok(())
+    }
+
+    /// Print the total stats with --stats, unless only the post of --config was processed.
+    pub fn print_total_stats(&self) {
+        if self.options.stats && !self.is_single {
+            println!("total stats: {}", self.total_stats);
+        }
+    }
+}
+
+/// Load the post of --config, or stdin if the path is "-".
+///
+/// Returns `None` if the post is skipped.
+async fn load_single(options: &Options, config_arg: &Utf8Path) -> anyhow::Result<Option<PostDir>> {
+    let from_stdin = config_arg == "-";
+    ensure!(
+        !(from_stdin && options.interactive),
+        "--interactive cannot be used when reading the config from stdin"
+    );
+    ensure!(
+        options.output_config.is_none() || from_stdin,
+        "--output-config can only be used when reading the config from stdin"
+    );
+    let config_path = match from_stdin {
+        true => options.output_config.clone(),
+        false => Some(config_arg.into()),
+    };
+
+    let dir_path = match options.dir.as_ref() {
+        Some(dir_path) => dir_path.clone(),
+        None => {
+            ensure!(
+                !from_stdin,
+                "missing post directory. Specify it with --dir when reading the config from stdin"
+            );
+            let parent = config_arg.parent().context("config path has no parent")?;
+            if parent.as_str().is_empty() {
+                Utf8PathBuf::from(".")
+            } else {
+                parent.into()
+            }
+        }
+    };
+    let dir_path = Utf8PathBuf::try_from(
+        std::fs::canonicalize(&dir_path)
+            .with_context(|| format!("failed to canonicalize \"{dir_path}\""))?,
+    )?;
+
+    let config_raw = if from_stdin {
+        tokio::task::spawn_blocking(|| {
+            let mut config_raw = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut config_raw)?;
+            anyhow::Ok(config_raw)
+        })
+        .await?
+        .context("failed to read config from stdin")?
+    } else {
+        tokio::fs::read_to_string(config_arg)
+            .await
+            .context("failed to read config file")?
+    };
+    let mut config = Config::new(&config_raw).context("failed to parse config file")?;
+    config
+        .load_includes(dir_path.as_std_path())
+        .context("failed to load included configs")?;
+
+    // The output config always holds the config, even if the run does not change it.
+    if let (true, Some(config_path), false) = (from_stdin, config_path.as_deref(), options.dry_run)
+    {
+        crate::write_config(&config, ConfigTarget::File(config_path)).await?;
+    }
+
+    // Disabled posts are skipped before any work, in every mode.
+    if !config.post_mut().enabled() {
+        println!("skipping \"{dir_path}\", as its post is disabled");
+        return Ok(None);
+    }
+
+    if options.only_new && config.post_mut().has_remote_post() {
+        println!("skipping \"{dir_path}\", as it already has a post");
+        return Ok(None);
+    }
+
+    let cache_path = match (options.cache.as_ref(), options.cache_dir.as_deref()) {
+        (Some(cache_path), _) => Some(cache_path.clone()),
+        (None, Some(cache_dir)) => Some(crate::get_cache_path(&dir_path, Some(cache_dir))?),
+        (None, None) => None,
+    };
+
+    Ok(Some(PostDir {
+        name: dir_path.file_name().unwrap_or(dir_path.as_str()).into(),
+        label: dir_path.to_string(),
+        path: dir_path,
+        config_path,
+        cache_path,
+        config,
+    }))
+}
+
+/// Sort directory paths by file name.
+fn sort_dir_paths(paths: &mut [Utf8PathBuf], natural: bool) {
+    if natural {
+        paths.sort_by(|a, b| {
+            crate::util::natural_cmp(a.file_name().unwrap_or(""), b.file_name().unwrap_or(""))
+        });
+    } else {
+        paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sort_dir_paths_works() {
+        let names = ["post 10", "post 2", "Post 3", "post 1", "a"];
+        let input = Utf8Path::new("input");
+
+        let mut paths: Vec<_> = names.iter().map(|name| input.join(name)).collect();
+        sort_dir_paths(&mut paths, false);
+        let actual: Vec<_> = paths.iter().filter_map(|path| path.file_name()).collect();
+        assert!(actual == ["Post 3", "a", "post 1", "post 10", "post 2"]);
+
+        let mut paths: Vec<_> = names.iter().map(|name| input.join(name)).collect();
+        sort_dir_paths(&mut paths, true);
+        let actual: Vec<_> = paths.iter().filter_map(|path| path.file_name()).collect();
+        assert!(actual == ["Post 3", "a", "post 1", "post 2", "post 10"]);
+    }
+}
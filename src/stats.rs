@@ -0,0 +1,72 @@
+use std::time::Duration;
+use std::time::Instant;
+
+/// Timing and bandwidth stats for syncing.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    /// The time spent hashing local files.
+    pub hash_time: Duration,
+
+    /// The time spent downloading remote posts.
+    pub download_time: Duration,
+
+    /// The time spent creating and updating remote posts.
+    pub upload_time: Duration,
+
+    /// The number of bytes of files uploaded.
+    pub bytes_uploaded: u64,
+
+    /// The number of API calls made.
+    pub api_calls: u64,
+}
+
+impl Stats {
+    /// Make a new, empty stats object.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl std::ops::AddAssign for Stats {
+    fn add_assign(&mut self, other: Self) {
+        self.hash_time += other.hash_time;
+        self.download_time += other.download_time;
+        self.upload_time += other.upload_time;
+        self.bytes_uploaded += other.bytes_uploaded;
+        self.api_calls += other.api_calls;
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hashing {:.2}s, downloading {:.2}s, uploading {:.2}s, {} bytes uploaded, {} api calls",
+            self.hash_time.as_secs_f64(),
+            self.download_time.as_secs_f64(),
+            self.upload_time.as_secs_f64(),
+            self.bytes_uploaded,
+            self.api_calls,
+        )
+    }
+}
+
+/// A timer that adds the elapsed time to a duration when stopped.
+#[derive(Debug)]
+pub struct Timer {
+    start: Instant,
+}
+
+impl Timer {
+    /// Start a new timer.
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    /// Stop the timer, adding the elapsed time to the given duration.
+    pub fn stop(self, duration: &mut Duration) {
+        *duration += self.start.elapsed();
+    }
+}
@@ -1,4 +1,13 @@
+use crate::error::SyncError;
+use anyhow::ensure;
+use anyhow::Context;
+use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 /// Try to read a string from a path, if it exists.
 pub async fn try_read_to_string(path: impl AsRef<Path>) -> std::io::Result<Option<String>> {
@@ -9,15 +18,380 @@ pub async fn try_read_to_string(path: impl AsRef<Path>) -> std::io::Result<Optio
     }
 }
 
+/// Get a temp path next to the given path.
+///
+/// The temp file name is unique per call,
+/// so concurrent writers to the same path do not clobber each other's temp files.
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let pid = std::process::id();
+    let counter = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let jitter = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or(0);
+    let extension = format!("{pid}-{counter}-{jitter:08x}.temp");
+    nd_util::with_push_extension(path, extension.as_str())
+}
+
 /// Write a string to the given path, using a temp file.
-pub async fn write_string_safe<P>(path: P, data: &str) -> anyhow::Result<()>
+pub async fn write_string_safe<P>(path: P, data: &str) -> Result<(), SyncError>
 where
     P: AsRef<Path>,
 {
     let path = path.as_ref();
-    let tmp_path = nd_util::with_push_extension(path, "temp");
-    tokio::fs::write(&tmp_path, data).await?;
-    tokio::fs::rename(tmp_path, path).await?;
+    let tmp_path = temp_path(path);
+
+    let result = async {
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, path).await
+    }
+    .await;
+    if let Err(error) = result {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(error.into());
+    }
 
     Ok(())
 }
+
+/// Write strings to several paths as a group, using temp files.
+///
+/// Every temp file is written before any is renamed into place.
+/// If a rename fails, the paths that were already renamed are restored to their old contents.
+/// This is best-effort, as a crash between the renames still leaves only some paths written.
+pub async fn write_many_safe(files: &[(&Path, &str)]) -> Result<(), SyncError> {
+    let tmp_paths: Vec<PathBuf> = files.iter().map(|(path, _data)| temp_path(path)).collect();
+
+    let result = async {
+        let mut old_data = Vec::with_capacity(files.len());
+        for ((path, data), tmp_path) in files.iter().zip(tmp_paths.iter()) {
+            tokio::fs::write(tmp_path, data).await?;
+            old_data.push(try_read_to_string(path).await?);
+        }
+        Ok(old_data)
+    }
+    .await;
+    let old_data = match result {
+        Ok(old_data) => old_data,
+        Err(error) => {
+            for tmp_path in tmp_paths.iter() {
+                let _ = tokio::fs::remove_file(tmp_path).await;
+            }
+            return Err(SyncError::Io(error));
+        }
+    };
+
+    for (i, ((path, _data), tmp_path)) in files.iter().zip(tmp_paths.iter()).enumerate() {
+        if let Err(error) = tokio::fs::rename(tmp_path, path).await {
+            for tmp_path in tmp_paths[i..].iter() {
+                let _ = tokio::fs::remove_file(tmp_path).await;
+            }
+            for ((path, _data), old_data) in files[..i].iter().zip(old_data.iter()) {
+                let _ = match old_data {
+                    Some(old_data) => write_string_safe(path, old_data).await,
+                    None => tokio::fs::remove_file(path).await.map_err(SyncError::from),
+                };
+            }
+            return Err(error.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Expand `${VAR}` environment variable references in a string.
+///
+/// `${VAR:-default}` uses the default if the variable is not defined or empty.
+pub fn expand_env(input: &str) -> Result<String, SyncError> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        output.push_str(&rest[..start]);
+
+        let reference_start = &rest[start + 2..];
+        let end = reference_start.find('}').ok_or_else(|| {
+            SyncError::invalid_config(format!(
+                "unterminated environment variable reference in \"{input}\""
+            ))
+        })?;
+        let reference = &reference_start[..end];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+
+        match (std::env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => output.push_str(default),
+            (Ok(value), _) => output.push_str(&value),
+            (Err(_), Some(default)) => output.push_str(default),
+            (Err(_), None) => return Err(SyncError::UndefinedEnvVar(name.into())),
+        }
+
+        rest = &reference_start[end + 1..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+/// Compare strings in natural order, where runs of digits are compared by their numeric value.
+pub fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+
+    let mut a_iter = a.char_indices().peekable();
+    let mut b_iter = b.char_indices().peekable();
+    loop {
+        let ((a_start, a_char), (b_start, b_char)) = match (a_iter.peek(), b_iter.peek()) {
+            (Some(a), Some(b)) => (*a, *b),
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+        };
+
+        if a_char.is_ascii_digit() && b_char.is_ascii_digit() {
+            let mut a_end = a_start;
+            while let Some((i, c)) = a_iter.next_if(|(_, c)| c.is_ascii_digit()) {
+                a_end = i + c.len_utf8();
+            }
+            let mut b_end = b_start;
+            while let Some((i, c)) = b_iter.next_if(|(_, c)| c.is_ascii_digit()) {
+                b_end = i + c.len_utf8();
+            }
+
+            let a_digits = &a[a_start..a_end];
+            let b_digits = &b[b_start..b_end];
+            let a_trimmed = a_digits.trim_start_matches('0');
+            let b_trimmed = b_digits.trim_start_matches('0');
+            let ordering = a_trimmed
+                .len()
+                .cmp(&b_trimmed.len())
+                .then_with(|| a_trimmed.cmp(b_trimmed))
+                .then_with(|| a_digits.len().cmp(&b_digits.len()));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        } else {
+            let ordering = a_char.cmp(&b_char);
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            a_iter.next();
+            b_iter.next();
+        }
+    }
+}
+
+/// Print a prompt and read a line from stdin.
+pub async fn prompt(message: &str) -> anyhow::Result<String> {
+    let message = message.to_string();
+    tokio::task::spawn_blocking(move || {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(message.as_bytes())?;
+        stdout.flush()?;
+
+        let mut line = String::new();
+        let n = std::io::stdin()
+            .read_line(&mut line)
+            .context("failed to read from stdin")?;
+        ensure!(n != 0, "stdin was closed");
+        Ok(line)
+    })
+    .await?
+}
+
+/// Replace the scheme and host of a url with the given base url.
+///
+/// If the url does not have a scheme and host, the base is prepended.
+pub fn rebase_url(url: &str, base: &str) -> String {
+    let path = match url.split_once("://") {
+        Some((_scheme, rest)) => rest.find('/').map(|index| &rest[index..]).unwrap_or(""),
+        None => url,
+    };
+
+    format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+/// Expand `@argfile` arguments into the arguments contained in the file.
+///
+/// Each line of an argfile is a single argument, taken literally.
+/// No quote or escape processing is performed.
+/// Blank lines and lines starting with `#` are ignored.
+/// Argfiles are not expanded recursively.
+/// The first argument is the program name, so it is never expanded.
+/// An argument starting with `@@` is passed on with one `@` removed, for literal values starting with `@`.
+/// Arguments after `--` are positional, so they are passed on unchanged.
+pub fn expand_argfiles<I>(args: I) -> anyhow::Result<Vec<String>>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    let mut expanded: Vec<String> = args.next().into_iter().collect();
+    let mut positional = false;
+    for arg in args {
+        if positional || arg == "--" {
+            positional = true;
+            expanded.push(arg);
+            continue;
+        }
+
+        let path = match arg.strip_prefix('@') {
+            Some(escaped) if escaped.starts_with('@') => {
+                expanded.push(escaped.into());
+                continue;
+            }
+            Some(path) if !path.is_empty() => path,
+            _ => {
+                expanded.push(arg);
+                continue;
+            }
+        };
+
+        let argfile = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read argfile \"{path}\""))?;
+        for line in argfile.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            positional |= line == "--";
+            expanded.push(line.into());
+        }
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::block_on;
+    use crate::test::TestDir;
+
+    #[test]
+    fn write_string_safe_concurrent() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build runtime");
+        let dir = TestDir::new("write-string-safe-concurrent");
+        let path = dir.path.join("file.toml");
+
+        let first = "a".repeat(1 << 20);
+        let second = "b".repeat(1 << 20);
+        runtime.block_on(async {
+            let handles: Vec<_> = [first.clone(), second.clone()]
+                .into_iter()
+                .map(|data| {
+                    let path = path.clone();
+                    tokio::spawn(async move { write_string_safe(path, &data).await })
+                })
+                .collect();
+            for handle in handles {
+                handle
+                    .await
+                    .expect("write task panicked")
+                    .expect("write failed");
+            }
+        });
+
+        let data = std::fs::read_to_string(&path).expect("failed to read file");
+        assert!(data == first || data == second);
+
+        let entries: Vec<_> = std::fs::read_dir(&dir.path)
+            .expect("failed to read dir")
+            .map(|entry| entry.expect("failed to read entry").file_name())
+            .collect();
+        assert!(entries == ["file.toml"]);
+    }
+
+    #[test]
+    fn write_many_safe_rolls_back() {
+        let dir = TestDir::new("write-many-safe-rolls-back");
+        let first_path = dir.path.join("first.toml").into_std_path_buf();
+        let second_path = dir.path.join("second.toml").into_std_path_buf();
+        let third_path = dir.path.join("third.toml").into_std_path_buf();
+        std::fs::write(&first_path, "old").expect("failed to write file");
+
+        block_on(async {
+            write_many_safe(&[(first_path.as_path(), "new"), (third_path.as_path(), "new")])
+                .await
+                .expect("write failed");
+        });
+        assert!(std::fs::read_to_string(&first_path).unwrap() == "new");
+        assert!(std::fs::read_to_string(&third_path).unwrap() == "new");
+
+        // A non-empty dir at the third path makes its rename fail, after the first two renames.
+        std::fs::remove_file(&third_path).expect("failed to remove file");
+        std::fs::create_dir_all(third_path.join("dir")).expect("failed to create dir");
+        block_on(async {
+            write_many_safe(&[
+                (first_path.as_path(), "newer"),
+                (second_path.as_path(), "new"),
+                (third_path.as_path(), "newer"),
+            ])
+            .await
+            .expect_err("rename over a dir succeeded");
+        });
+        assert!(std::fs::read_to_string(&first_path).unwrap() == "new");
+        assert!(!second_path.exists());
+        assert!(third_path.is_dir());
+
+        let mut entries: Vec<_> = std::fs::read_dir(&dir.path)
+            .expect("failed to read dir")
+            .map(|entry| entry.expect("failed to read entry").file_name())
+            .collect();
+        entries.sort();
+        assert!(entries == ["first.toml", "third.toml"]);
+    }
+
+    #[test]
+    fn expand_env_works() {
+        std::env::set_var("IMGCHEST_SYNC_TEST_EXPAND_ENV", "v1.0");
+
+        let actual =
+            expand_env("${IMGCHEST_SYNC_TEST_EXPAND_ENV} gallery").expect("failed to expand");
+        assert!(actual == "v1.0 gallery");
+
+        let actual =
+            expand_env("${IMGCHEST_SYNC_TEST_UNDEFINED:-default} $5").expect("failed to expand");
+        assert!(actual == "default $5");
+
+        let error =
+            expand_env("${IMGCHEST_SYNC_TEST_UNDEFINED}").expect_err("undefined variable expanded");
+        assert!(
+            matches!(error, SyncError::UndefinedEnvVar(name) if name == "IMGCHEST_SYNC_TEST_UNDEFINED")
+        );
+
+        expand_env("${IMGCHEST_SYNC_TEST_EXPAND_ENV").expect_err("unterminated reference expanded");
+    }
+
+    #[test]
+    fn expand_argfiles_works() {
+        let dir = TestDir::new("expand-argfiles-works");
+        dir.write("args.txt", "--dry-run\n\n# comment\n--dir\nmy dir\n");
+        let argfile = format!("@{}", dir.path.join("args.txt"));
+
+        let args = [argfile.clone(), argfile.clone(), String::from("@")];
+        let expanded = expand_argfiles(args).expect("failed to expand");
+        assert!(expanded == [argfile.as_str(), "--dry-run", "--dir", "my dir", "@"]);
+
+        expand_argfiles([String::from("imgchest-sync"), String::from("@missing")])
+            .expect_err("missing argfile expanded");
+
+        // "@@" escapes a literal "@", and nothing after "--" is expanded.
+        let args = [
+            String::from("imgchest-sync"),
+            String::from("@@foo"),
+            String::from("--"),
+            argfile.clone(),
+        ];
+        let expanded = expand_argfiles(args).expect("failed to expand");
+        assert!(expanded == ["imgchest-sync", "@foo", "--", argfile.as_str()]);
+    }
+}